@@ -0,0 +1,38 @@
+#![feature(test)]
+
+extern crate test;
+extern crate time;
+extern crate crossroad_server;
+
+use test::Bencher;
+use crossroad_server::clock::TICK_MS;
+use crossroad_server::planner::*;
+use crossroad_server::default_crossroad::default_crossroad;
+
+/// The controller plans every tick, so the worst case has to fit in one.
+#[bench]
+fn default_crossroad_all_waiting(b: &mut Bencher) {
+    let crossroad = default_crossroad();
+    let demand = all_waiting(&crossroad);
+
+    let start = time::PreciseTime::now();
+    Planner::new(&crossroad, &demand, DEFAULT_HORIZON).plan();
+    let elapsed = start.to(time::PreciseTime::now()).num_milliseconds();
+    assert!(elapsed < TICK_MS as i64, "planning took {} milliseconds", elapsed);
+
+    b.iter(|| Planner::new(&crossroad, &demand, DEFAULT_HORIZON).plan());
+}
+
+#[bench]
+fn default_crossroad_all_waiting_horizon_5(b: &mut Bencher) {
+    let crossroad = default_crossroad();
+    let demand = all_waiting(&crossroad);
+    b.iter(|| Planner::new(&crossroad, &demand, 5).plan());
+}
+
+#[bench]
+fn default_crossroad_few_waiting(b: &mut Bencher) {
+    let crossroad = default_crossroad();
+    let demand: Vec<Demand> = all_waiting(&crossroad).into_iter().enumerate().filter(|&(i, _)| i % 3 == 0).map(|(_, d)| d).collect();
+    b.iter(|| Planner::new(&crossroad, &demand, DEFAULT_HORIZON).plan());
+}
@@ -0,0 +1,61 @@
+#![feature(test)]
+
+extern crate test;
+extern crate crossroad_server;
+
+use test::Bencher;
+use crossroad_server::solver;
+use crossroad_server::default_crossroad;
+
+/// Deterministic pseudo random numbers, so every run benchmarks the same graphs.
+fn lcg(seed: &mut u64) -> u64 {
+    *seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+    *seed >> 33
+}
+
+fn random_problem(n: usize, conflict_percentage: u64) -> (Vec<i64>, Vec<Vec<bool>>) {
+    let mut seed = n as u64;
+    let weights = (0..n).map(|_| (lcg(&mut seed) % 120) as i64 + 1).collect();
+    let mut conflicts = vec![vec![false; n]; n];
+
+    for a in 0..n {
+        for b in a+1..n {
+            if lcg(&mut seed) % 100 < conflict_percentage {
+                conflicts[a][b] = true;
+                conflicts[b][a] = true;
+            }
+        }
+    }
+
+    (weights, conflicts)
+}
+
+#[bench]
+fn default_crossroad_all_active(b: &mut Bencher) {
+    let crossroad = default_crossroad::default_crossroad();
+
+    let controls = crossroad.topology.controls();
+    let weights: Vec<i64> = (0..controls.len()).map(|i| (i as i64 * 7) % 50 + 1).collect();
+
+    b.iter(|| {
+        solver::max_weight_compatible(weights.clone(), |a, b| crossroad.conflicts.controls_conflict(&controls[a], &controls[b]))
+    });
+}
+
+#[bench]
+fn random_48_signals_dense(b: &mut Bencher) {
+    let (weights, conflicts) = random_problem(48, 40);
+    b.iter(|| solver::max_weight_compatible(weights.clone(), |a, b| conflicts[a][b]));
+}
+
+#[bench]
+fn random_48_signals_sparse(b: &mut Bencher) {
+    let (weights, conflicts) = random_problem(48, 15);
+    b.iter(|| solver::max_weight_compatible(weights.clone(), |a, b| conflicts[a][b]));
+}
+
+#[bench]
+fn random_64_signals(b: &mut Bencher) {
+    let (weights, conflicts) = random_problem(64, 25);
+    b.iter(|| solver::max_weight_compatible(weights.clone(), |a, b| conflicts[a][b]));
+}
@@ -0,0 +1,86 @@
+use time;
+
+use traffic_protocol::EmergencyTarget;
+use sensor_health::{SensorFault, Fallback};
+
+/// Entries the audit log keeps, older ones are dropped.
+pub const AUDIT_CAPACITY: usize = 1000;
+
+
+// -------------------------------------------------------------------------------
+// AuditLog
+// -------------------------------------------------------------------------------
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum AuditEvent {
+    PlanSwitched { from: Option<String>, to: String },
+    FailSafe { reason: String },
+    PreemptionStarted { target: EmergencyTarget },
+    /// `timed_out` when the client never cleared the request.
+    PreemptionEnded { target: EmergencyTarget, timed_out: bool },
+    SensorFault { sensor: usize, fault: SensorFault, fallback: Fallback },
+    SensorRecovered { sensor: usize, fault: SensorFault },
+    Stopped { reason: String },
+    Resumed,
+}
+
+#[derive(Debug, Clone)]
+pub struct AuditEntry {
+    pub time: i32,
+    pub at: time::Tm,
+    pub event: AuditEvent,
+}
+
+/// What the controller did that an operator has to be able to look back on, the last `AUDIT_CAPACITY` entries.
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    entries: Vec<AuditEntry>,
+    recorded: usize,
+}
+
+impl AuditLog {
+
+    pub fn new() -> AuditLog {
+        AuditLog { entries: vec![], recorded: 0 }
+    }
+
+    pub fn record(&mut self, time: i32, at: time::Tm, event: AuditEvent) {
+        println!("========== AUDIT: {} {:?}", at.rfc3339(), event);
+        if self.entries.len() == AUDIT_CAPACITY {
+            self.entries.remove(0);
+        }
+        self.entries.push(AuditEntry { time: time, at: at, event: event });
+        self.recorded += 1;
+    }
+
+    pub fn entries(&self) -> &Vec<AuditEntry> {
+        &self.entries
+    }
+
+    /// How many entries were recorded, including the dropped ones.
+    pub fn recorded(&self) -> usize {
+        self.recorded
+    }
+
+    /// The entries recorded after the first `count`, as far as they are still kept.
+    pub fn since(&self, count: usize) -> &[AuditEntry] {
+        let new = ::std::cmp::min(self.recorded - count, self.entries.len());
+        &self.entries[self.entries.len() - new..]
+    }
+}
+
+
+#[test]
+fn audit_log_keeps_the_last_entries() {
+    let mut log = AuditLog::new();
+    for _ in 0..AUDIT_CAPACITY + 5 {
+        log.record(0, time::empty_tm(), AuditEvent::Resumed);
+    }
+    log.record(1, time::empty_tm(), AuditEvent::Stopped { reason: "test".to_string() });
+
+    assert_eq!(log.entries().len(), AUDIT_CAPACITY);
+    assert_eq!(log.recorded(), AUDIT_CAPACITY + 6);
+    assert_eq!(log.since(AUDIT_CAPACITY + 5).iter().map(|e| e.time).collect::<Vec<_>>(), vec![1]);
+    assert_eq!(log.since(0).len(), AUDIT_CAPACITY);
+}
+
@@ -0,0 +1,164 @@
+use time;
+
+use traffic_controls::ControlId;
+
+/// Records the bus priority log keeps, older ones are dropped.
+pub const BUS_PRIORITY_CAPACITY: usize = 1000;
+
+
+// -------------------------------------------------------------------------------
+// BusPriorityPolicy
+// -------------------------------------------------------------------------------
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PriorityDecision {
+    /// The bus waits its turn like other traffic.
+    NoPriority,
+    /// The bus lane is green, it stays green while the bus is still at the sensor.
+    GreenExtension,
+    /// The conflicting green ends as soon as its minimum green allows, then the bus lane turns green.
+    EarlyGreen,
+}
+
+/// Decides how much priority a bus gets. The later the bus, the longer conflicting traffic may be kept
+/// waiting for it. A bus that is ahead of schedule only gets priority when nobody conflicting waits.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BusPriorityPolicy {
+    /// Seconds behind schedule from which a bus counts as late.
+    pub late_after: i32,
+    /// Conflicting traffic that waited longer than this keeps its turn before a bus that is on time.
+    pub on_time_max_conflict_wait: i32,
+    /// Conflicting traffic that waited longer than this keeps its turn before a late bus.
+    pub late_max_conflict_wait: i32,
+}
+
+impl Default for BusPriorityPolicy {
+    fn default() -> BusPriorityPolicy {
+        BusPriorityPolicy { late_after: 60, on_time_max_conflict_wait: 20, late_max_conflict_wait: 60 }
+    }
+}
+
+impl BusPriorityPolicy {
+
+    /// `delay` is in seconds behind schedule, negative when the bus is early.
+    /// `conflict_wait` is the longest waiting time of red conflicting traffic.
+    pub fn decide(&self, bus_green: bool, delay: i32, conflict_wait: i32) -> PriorityDecision {
+        let allowed = match delay {
+            d if d < 0 => 0,
+            d if d >= self.late_after => self.late_max_conflict_wait,
+            _ => self.on_time_max_conflict_wait,
+        };
+
+        if conflict_wait > allowed {
+            PriorityDecision::NoPriority
+        }
+        else if bus_green {
+            PriorityDecision::GreenExtension
+        }
+        else {
+            PriorityDecision::EarlyGreen
+        }
+    }
+}
+
+
+// -------------------------------------------------------------------------------
+// BusPriority
+// -------------------------------------------------------------------------------
+
+/// The decision for the first bus at a bus lane, and what it was based on.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BusPriority {
+    pub control: ControlId,
+    pub line: i32,
+    pub delay: i32,
+    pub conflict_wait: i32,
+    pub decision: PriorityDecision,
+}
+
+#[derive(Debug, Clone)]
+pub struct BusPriorityRecord {
+    pub time: i32,
+    pub at: time::Tm,
+    pub priority: BusPriority,
+}
+
+/// Every decision that differs from the previous one for the same bus lane and line,
+/// the last `BUS_PRIORITY_CAPACITY` of them.
+#[derive(Debug, Clone)]
+pub struct BusPriorityLog {
+    records: Vec<BusPriorityRecord>,
+    last: Vec<BusPriority>,
+}
+
+impl BusPriorityLog {
+
+    pub fn new() -> BusPriorityLog {
+        BusPriorityLog { records: vec![], last: vec![] }
+    }
+
+    pub fn record(&mut self, time: i32, at: time::Tm, priorities: &Vec<BusPriority>) {
+        for priority in priorities {
+            let previous = self.last.iter().position(|p| p.control == priority.control);
+
+            if previous.map(|index| (self.last[index].line, self.last[index].decision)) != Some((priority.line, priority.decision)) {
+                println!("========== BUS PRIORITY: line {} at {:?}, {} seconds late, conflicting traffic waited {} seconds: {:?}",
+                         priority.line, priority.control, priority.delay, priority.conflict_wait, priority.decision);
+                if self.records.len() == BUS_PRIORITY_CAPACITY {
+                    self.records.remove(0);
+                }
+                self.records.push(BusPriorityRecord { time: time, at: at, priority: *priority });
+
+                match previous {
+                    Some(index) => self.last[index] = *priority,
+                    None => self.last.push(*priority),
+                }
+            }
+        }
+    }
+
+    pub fn records(&self) -> &Vec<BusPriorityRecord> {
+        &self.records
+    }
+}
+
+
+#[test]
+fn late_buses_may_keep_conflicting_traffic_waiting_longer() {
+    let policy = BusPriorityPolicy::default();
+
+    // Nobody waits, every bus gets priority.
+    assert_eq!(policy.decide(false, -30, 0), PriorityDecision::EarlyGreen);
+    assert_eq!(policy.decide(true, -30, 0), PriorityDecision::GreenExtension);
+
+    // Someone waits a little.
+    assert_eq!(policy.decide(false, -30, 10), PriorityDecision::NoPriority);
+    assert_eq!(policy.decide(false, 0, 10), PriorityDecision::EarlyGreen);
+    assert_eq!(policy.decide(true, 0, 10), PriorityDecision::GreenExtension);
+
+    // Someone waits longer, only a late bus still goes first.
+    assert_eq!(policy.decide(false, 0, 40), PriorityDecision::NoPriority);
+    assert_eq!(policy.decide(true, 30, 40), PriorityDecision::NoPriority);
+    assert_eq!(policy.decide(false, 120, 40), PriorityDecision::EarlyGreen);
+    assert_eq!(policy.decide(true, 120, 40), PriorityDecision::GreenExtension);
+
+    // Too long, even for a late bus.
+    assert_eq!(policy.decide(false, 600, 90), PriorityDecision::NoPriority);
+}
+
+#[test]
+fn bus_priority_log_keeps_the_last_changes() {
+    let priority = |line: i32| BusPriority { control: ControlId(11), line: line, delay: 0, conflict_wait: 0, decision: PriorityDecision::EarlyGreen };
+    let mut log = BusPriorityLog::new();
+
+    for line in 0..BUS_PRIORITY_CAPACITY as i32 + 5 {
+        log.record(line, time::empty_tm(), &vec![priority(line)]);
+    }
+    assert_eq!(log.records().len(), BUS_PRIORITY_CAPACITY);
+    assert_eq!(log.records()[0].priority.line, 5);
+
+    // The same decision again isn't a change, even when its record was dropped long ago.
+    log.record(0, time::empty_tm(), &vec![priority(BUS_PRIORITY_CAPACITY as i32 + 4)]);
+    assert_eq!(log.records().len(), BUS_PRIORITY_CAPACITY);
+    assert_eq!(log.records()[0].priority.line, 5);
+}
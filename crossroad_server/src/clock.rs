@@ -0,0 +1,110 @@
+use std::sync::Mutex;
+use std::sync::mpsc::Receiver;
+use time;
+use schedule_recv as sched;
+
+/// Length of one controller tick, all timings are counted in ticks (seconds).
+pub const TICK_MS: u32 = 1000;
+
+
+// -------------------------------------------------------------------------------
+// Clock
+// -------------------------------------------------------------------------------
+
+/// Source of time for the controller: sensor timestamps, waiting times and the tick loop.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> time::Tm;
+
+    /// Waits for the next tick and returns its number, the first tick is 1.
+    fn tick(&self) -> i32;
+}
+
+
+// -------------------------------------------------------------------------------
+// WallClock
+// -------------------------------------------------------------------------------
+
+/// Real time, ticks once every `TICK_MS`.
+pub struct WallClock {
+    scheduler: Mutex<Receiver<()>>,
+    ticks: Mutex<i32>,
+}
+
+impl WallClock {
+    pub fn new() -> WallClock {
+        WallClock { scheduler: Mutex::new(sched::periodic_ms(TICK_MS)), ticks: Mutex::new(0) }
+    }
+}
+
+impl Clock for WallClock {
+    fn now(&self) -> time::Tm {
+        time::now()
+    }
+
+    fn tick(&self) -> i32 {
+        self.scheduler.lock().unwrap().recv().unwrap();
+        let mut ticks = self.ticks.lock().unwrap();
+        *ticks += 1;
+        *ticks
+    }
+}
+
+
+// -------------------------------------------------------------------------------
+// SimulatedClock
+// -------------------------------------------------------------------------------
+
+/// Manually advanced time. A tick doesn't wait, it moves the clock one tick forward,
+/// so scenarios run as fast as possible and give the same result every run.
+pub struct SimulatedClock {
+    start: time::Tm,
+    elapsed: Mutex<time::Duration>,
+    ticks: Mutex<i32>,
+}
+
+impl SimulatedClock {
+    /// Starts at the unix epoch, use `starting_at` when the date matters.
+    pub fn new() -> SimulatedClock {
+        SimulatedClock::starting_at(time::at_utc(time::Timespec::new(0, 0)))
+    }
+
+    pub fn starting_at(start: time::Tm) -> SimulatedClock {
+        SimulatedClock { start: start, elapsed: Mutex::new(time::Duration::zero()), ticks: Mutex::new(0) }
+    }
+
+    pub fn advance(&self, duration: time::Duration) {
+        let mut elapsed = self.elapsed.lock().unwrap();
+        *elapsed = *elapsed + duration;
+    }
+
+    pub fn ticks(&self) -> i32 {
+        *self.ticks.lock().unwrap()
+    }
+}
+
+impl Clock for SimulatedClock {
+    fn now(&self) -> time::Tm {
+        self.start + *self.elapsed.lock().unwrap()
+    }
+
+    fn tick(&self) -> i32 {
+        self.advance(time::Duration::milliseconds(TICK_MS as i64));
+        let mut ticks = self.ticks.lock().unwrap();
+        *ticks += 1;
+        *ticks
+    }
+}
+
+
+#[test]
+fn simulated_clock_ticks_without_waiting() {
+    let clock = SimulatedClock::new();
+    let start = clock.now();
+
+    for _ in 0..3600 {
+        clock.tick();
+    }
+
+    assert_eq!(clock.ticks(), 3600);
+    assert_eq!((clock.now() - start).num_seconds(), 3600);
+}
@@ -0,0 +1,621 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use serde_json;
+
+use error::{Result, Error};
+use traffic_protocol::BAAN_COUNT;
+use traffic_controls::*;
+use signal_group::*;
+use crossroad::*;
+use conflict_matrix::ConflictMatrix;
+use intergreen::*;
+use fixed_time::FixedTimePlan;
+use bus_priority::BusPriorityPolicy;
+use strategy::DefaultStrategy;
+use sensor_health::{SensorHealthPolicy, Fallback};
+use watchdog::WatchdogPolicy;
+use event_log::EventLogConfig;
+use schedule::{Schedule, Weekday};
+
+
+// -------------------------------------------------------------------------------
+// CrossroadConfig
+// -------------------------------------------------------------------------------
+
+/// Declarative description of a crossroad, see `default_crossroad.json` for an example.
+/// Every control is referenced by the id of one of its traffic lights, just like `Topology::indexed_controls`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CrossroadConfig {
+    pub traffic_lights: TrafficLightsConfig,
+    pub groups: Vec<GroupConfig>,
+    pub conflicts: Vec<ConflictsConfig>,
+    pub primary: Vec<usize>,
+    pub secondary: Vec<usize>,
+    pub priority: Vec<usize>,
+    #[serde(default)]
+    pub intergreen: Option<IntergreenConfig>,
+    #[serde(default)]
+    pub mode: ControlMode,
+    #[serde(default)]
+    pub fixed_time: Option<FixedTimeConfig>,
+    #[serde(default)]
+    pub schedule: Option<ScheduleConfig>,
+    #[serde(default)]
+    pub bus_routes: Vec<BusRouteConfig>,
+    #[serde(default)]
+    pub bus_priority: BusPriorityPolicy,
+    #[serde(default)]
+    pub sensor_health: Option<SensorHealthConfig>,
+    #[serde(default)]
+    pub watchdog: WatchdogPolicy,
+    #[serde(default)]
+    pub event_log: EventLogConfig,
+}
+
+/// Traffic lights 0..count (inclusive), North/Vehicle unless listed otherwise.
+/// Timings that are not set come from the type of the light.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TrafficLightsConfig {
+    pub count: usize,
+    pub directions: Vec<DirectionConfig>,
+    pub types: Vec<TypeConfig>,
+    #[serde(default)]
+    pub timings: Vec<TimingsConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DirectionConfig {
+    pub direction: Direction,
+    pub ids: Vec<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TypeConfig {
+    #[serde(rename = "type")]
+    pub traffic_type: Type,
+    pub ids: Vec<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TimingsConfig {
+    pub ids: Vec<usize>,
+    pub timings: Timings,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GroupConfig {
+    pub ids: Vec<usize>,
+    pub direction: Direction,
+    #[serde(rename = "type")]
+    pub traffic_type: Type,
+    #[serde(default)]
+    pub timings: Timings,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConflictsConfig {
+    pub name: String,
+    pub direction: Direction,
+    pub entries: Vec<ConflictEntryConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConflictEntryConfig {
+    pub control: usize,
+    pub conflicting_with: Vec<usize>,
+}
+
+/// Clearance times in seconds, `default` is used for every conflicting pair not listed in `times`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IntergreenConfig {
+    pub default: i32,
+    pub times: Vec<IntergreenEntryConfig>,
+}
+
+/// Clearance time from control `from` to the controls in `to`, or to all its conflicts when `to` is left out.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IntergreenEntryConfig {
+    pub from: usize,
+    #[serde(default)]
+    pub to: Option<Vec<usize>>,
+    pub seconds: i32,
+}
+
+/// Stages are run in order and the plan repeats every `cycle` seconds, shifted by `offset` seconds.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FixedTimeConfig {
+    pub cycle: i32,
+    #[serde(default)]
+    pub offset: i32,
+    pub stages: Vec<StageConfig>,
+}
+
+/// Controls that are green together for `green` seconds.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StageConfig {
+    pub ids: Vec<usize>,
+    pub green: i32,
+}
+
+/// The bus lines that go `movement` after the crossroad.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BusRouteConfig {
+    pub lines: Vec<i32>,
+    pub movement: BusMovement,
+}
+
+/// Switches plans by weekday and time of day, the `default` plan runs outside of the windows.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScheduleConfig {
+    pub default: String,
+    pub plans: Vec<PlanConfig>,
+    pub windows: Vec<TimeWindowConfig>,
+}
+
+/// A control mode with timings that replace the timings of the listed lights while the plan runs.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlanConfig {
+    pub name: String,
+    pub mode: ControlMode,
+    #[serde(default)]
+    pub timings: Vec<TimingsConfig>,
+}
+
+/// From `from` until `to` ("HH:MM") on the listed days, a window that ends before it starts runs past midnight.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TimeWindowConfig {
+    pub days: Vec<Weekday>,
+    pub from: String,
+    pub to: String,
+    pub plan: String,
+}
+
+/// A sensor is faulty when it is occupied for more than `max_occupied` seconds, or has no vehicle for more than
+/// the `max_idle` seconds of the `idle` window the time of day is in. Faulty sensors are replaced by `fallback`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SensorHealthConfig {
+    pub max_occupied: i32,
+    #[serde(default)]
+    pub idle: Vec<IdleWindowConfig>,
+    #[serde(default)]
+    pub fallback: Fallback,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IdleWindowConfig {
+    pub from: String,
+    pub to: String,
+    pub max_idle: i32,
+}
+
+impl CrossroadConfig {
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<CrossroadConfig> {
+        let mut json = String::new();
+        try!(File::open(path).and_then(|mut f| f.read_to_string(&mut json)));
+        CrossroadConfig::from_str(&json)
+    }
+
+    pub fn from_str(json: &str) -> Result<CrossroadConfig> {
+        let config: CrossroadConfig = try!(serde_json::from_str(json));
+        try!(config.validate());
+        Ok(config)
+    }
+
+    /// Checks that every id refers to an existing traffic light, and that no light is part of two groups.
+    pub fn validate(&self) -> Result<()> {
+        let count = self.traffic_lights.count;
+
+        // The sensors of the protocol only go up to BAAN_COUNT, a light past them would never be sensed.
+        if count >= BAAN_COUNT {
+            return Err(Error::Config(format!("highest traffic light id {} is more than the protocol supports ({})", count, BAAN_COUNT - 1)));
+        }
+
+        for d in &self.traffic_lights.directions {
+            try!(check_ids(count, &d.ids, &format!("direction {:?}", d.direction)));
+        }
+        for t in &self.traffic_lights.types {
+            try!(check_ids(count, &t.ids, &format!("type {:?}", t.traffic_type)));
+        }
+        for t in &self.traffic_lights.timings {
+            try!(check_ids(count, &t.ids, "timings"));
+            try!(check_timings(&t.timings, &format!("traffic lights {:?}", t.ids)));
+        }
+
+        let mut grouped = HashMap::new();
+        for (index, group) in self.groups.iter().enumerate() {
+            try!(check_ids(count, &group.ids, &format!("group #{}", index)));
+            try!(check_timings(&group.timings, &format!("group #{}", index)));
+
+            if group.ids.is_empty() {
+                return Err(Error::Config(format!("group #{} has no traffic lights", index)));
+            }
+            for &id in &group.ids {
+                if let Some(other) = grouped.insert(id, index) {
+                    return Err(Error::Config(format!("traffic light {} is part of both group #{} and group #{}", id, other, index)));
+                }
+            }
+        }
+
+        let mut directions = vec![];
+        for conflicts in &self.conflicts {
+            if directions.contains(&conflicts.direction) {
+                return Err(Error::Config(format!("conflicts for direction {:?} are defined twice", conflicts.direction)));
+            }
+            directions.push(conflicts.direction);
+
+            for entry in &conflicts.entries {
+                try!(check_ids(count, &vec![entry.control], &format!("conflicts '{}'", conflicts.name)));
+                try!(check_ids(count, &entry.conflicting_with, &format!("conflicts '{}' of control {}", conflicts.name, entry.control)));
+            }
+        }
+
+        try!(check_ids(count, &self.primary, "primary traffic"));
+        try!(check_ids(count, &self.secondary, "secondary traffic"));
+        try!(check_ids(count, &self.priority, "priority traffic"));
+
+        if let Some(ref intergreen) = self.intergreen {
+            for entry in &intergreen.times {
+                try!(check_ids(count, &vec![entry.from], "intergreen times"));
+                if let Some(ref to) = entry.to {
+                    try!(check_ids(count, to, &format!("intergreen times of control {}", entry.from)));
+                }
+                if entry.seconds < 0 {
+                    return Err(Error::Config(format!("negative intergreen time for control {}", entry.from)));
+                }
+            }
+        }
+
+        if let Some(ref fixed_time) = self.fixed_time {
+            if fixed_time.stages.is_empty() {
+                return Err(Error::Config("fixed time plan without stages".to_string()));
+            }
+            for (index, stage) in fixed_time.stages.iter().enumerate() {
+                try!(check_ids(count, &stage.ids, &format!("fixed time stage #{}", index)));
+                if stage.green <= 0 {
+                    return Err(Error::Config(format!("fixed time stage #{} needs a green time above 0", index)));
+                }
+            }
+        }
+
+        if self.mode == ControlMode::FixedTime && self.fixed_time.is_none() {
+            return Err(Error::Config("mode is FixedTime, but there is no fixed_time plan".to_string()));
+        }
+
+        let mut lines: Vec<i32> = vec![];
+        for route in &self.bus_routes {
+            for &line in &route.lines {
+                if lines.contains(&line) {
+                    return Err(Error::Config(format!("bus line {} has more than one route", line)));
+                }
+                lines.push(line);
+            }
+        }
+
+        if let Some(ref schedule) = self.schedule {
+            for plan in &schedule.plans {
+                for t in &plan.timings {
+                    try!(check_ids(count, &t.ids, &format!("plan '{}'", plan.name)));
+                    try!(check_timings(&t.timings, &format!("plan '{}'", plan.name)));
+                }
+                if plan.mode == ControlMode::FixedTime && self.fixed_time.is_none() {
+                    return Err(Error::Config(format!("plan '{}' is FixedTime, but there is no fixed_time plan", plan.name)));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn create_traffic_lights(&self) -> TrafficLightsBuilder {
+        let mut builder = TrafficLightsBuilder::new(self.traffic_lights.count);
+
+        for d in &self.traffic_lights.directions {
+            builder = builder.set_direction(d.direction, d.ids.clone());
+        }
+        for t in &self.traffic_lights.types {
+            builder = builder.set_type(t.traffic_type, t.ids.clone());
+        }
+        for t in &self.traffic_lights.timings {
+            builder = builder.set_timings(t.timings, t.ids.clone());
+        }
+
+        builder
+    }
+
+    pub fn create_traffic_controls(&self, traffic_lights: TrafficLightsBuilder) -> Topology {
+        let mut topology = self.groups.iter()
+            .fold(ControlsBuilder::new(traffic_lights), |builder, g| {
+                builder.add_group_with_timings(g.ids.clone(), g.direction, g.traffic_type, g.timings)
+            })
+            .create_controls();
+
+        topology.set_bus_routes(self.bus_routes.iter()
+            .flat_map(|route| route.lines.iter().map(move |&line| BusRoute { line: line, movement: route.movement }))
+            .collect());
+        topology
+    }
+
+    pub fn create_crossroad(&self, topology: Topology) -> Result<Crossroad> {
+        try!(self.validate());
+
+        let indexed_controls = topology.indexed_controls();
+
+        let mut directions = HashMap::new();
+        for conflicts in &self.conflicts {
+            let entries = conflicts.entries.iter().map(|entry| {
+                let conflicting_with = entry.conflicting_with.iter().map(|&id| indexed_controls[id]).collect();
+                indexed_controls[entry.control].conflicting_with(conflicting_with)
+            }).collect();
+
+            directions.insert(conflicts.direction, XorConflictsGroup::new(conflicts.name.clone(), entries));
+        }
+
+        let primary_traffic = lookup_controls(&indexed_controls, &self.primary);
+        let conflicts = ConflictMatrix::from_directions(&topology, &directions);
+        let intergreen = try!(self.create_intergreen(&topology, &conflicts));
+        let fixed_time = match self.fixed_time {
+            Some(ref plan) => Some(try!(FixedTimePlan::new(plan, &topology, &intergreen))),
+            None => None,
+        };
+        let schedule = match self.schedule {
+            Some(ref schedule) => Some(try!(Schedule::new(schedule, &topology))),
+            None => None,
+        };
+
+        let sensor_health = match self.sensor_health {
+            Some(ref health) => try!(SensorHealthPolicy::new(health)),
+            None => SensorHealthPolicy::default(),
+        };
+
+        Ok(Crossroad {
+            primary_group: SignalGroup::new(topology.lookup(&primary_traffic), true),
+            topology: topology,
+            primary_traffic: primary_traffic,
+            secondary_traffic: lookup_controls(&indexed_controls, &self.secondary),
+            priority_traffic: lookup_controls(&indexed_controls, &self.priority),
+            directions: directions,
+            conflicts: conflicts,
+            intergreen: intergreen,
+            mode: self.mode,
+            fixed_time: fixed_time,
+            schedule: schedule,
+            bus_priority: self.bus_priority,
+            sensor_health: sensor_health,
+            watchdog: self.watchdog,
+            event_log: self.event_log.clone(),
+            strategy: Box::new(DefaultStrategy),
+        })
+    }
+
+    fn create_intergreen(&self, topology: &Topology, conflicts: &ConflictMatrix) -> Result<IntergreenMatrix> {
+        let config = match self.intergreen {
+            Some(ref config) => config,
+            None => {
+                let intergreen = IntergreenMatrix::new(conflicts, DEFAULT_INTERGREEN);
+                try!(check_yellow(topology, conflicts, &intergreen));
+                return Ok(intergreen);
+            },
+        };
+
+        let mut intergreen = IntergreenMatrix::new(conflicts, config.default);
+
+        for entry in &config.times {
+            let from = topology.control_of(LightId(entry.from)).unwrap();
+
+            match entry.to {
+                Some(ref to) => for &id in to {
+                    let other = topology.control_of(LightId(id)).unwrap();
+                    if !conflicts.controls_conflict(from, other) {
+                        return Err(Error::Config(format!("intergreen time from control {} to {}, but they don't conflict", entry.from, id)));
+                    }
+                    intergreen.set_controls(from, other, entry.seconds);
+                },
+                None => for other in topology.controls() {
+                    if conflicts.controls_conflict(from, other) {
+                        intergreen.set_controls(from, other, entry.seconds);
+                    }
+                },
+            }
+        }
+
+        try!(check_yellow(topology, conflicts, &intergreen));
+        Ok(intergreen)
+    }
+}
+
+/// A conflicting light may turn green once the intergreen time has passed, so it has to cover the yellow time.
+fn check_yellow(topology: &Topology, conflicts: &ConflictMatrix, intergreen: &IntergreenMatrix) -> Result<()> {
+    for from in topology.controls() {
+        let yellow = from.timings().yellow;
+
+        for to in topology.controls().iter().filter(|to| conflicts.controls_conflict(from, to)) {
+            for id_from in from.get_ids() {
+                if let Some(id_to) = to.get_ids().into_iter().find(|&id_to| intergreen.get(id_from, id_to) < yellow) {
+                    return Err(Error::Config(format!("intergreen time from light {} to {} is {} seconds, shorter than the yellow time of {} seconds",
+                        id_from, id_to, intergreen.get(id_from, id_to), yellow)));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check_ids(count: usize, ids: &Vec<usize>, context: &str) -> Result<()> {
+    match ids.iter().find(|&&id| id > count) {
+        Some(id) => Err(Error::Config(format!("unknown traffic light id {} in {} (highest id is {})", id, context, count))),
+        None => Ok(()),
+    }
+}
+
+fn check_timings(timings: &Timings, context: &str) -> Result<()> {
+    let values = [timings.min_green, timings.green_extra, timings.max_green, timings.yellow, timings.min_red, timings.max_waiting];
+
+    if values.iter().any(|v| v.map(|seconds| seconds < 0).unwrap_or(false)) {
+        return Err(Error::Config(format!("negative timing for {}", context)));
+    }
+    if let (Some(min), Some(max)) = (timings.min_green, timings.max_green) {
+        if min > max {
+            return Err(Error::Config(format!("min_green is larger than max_green for {}", context)));
+        }
+    }
+    Ok(())
+}
+
+/// Maps light ids to their controls, a group is only added once.
+fn lookup_controls(indexed_controls: &Vec<ControlId>, ids: &Vec<usize>) -> Vec<ControlId> {
+    let mut controls: Vec<ControlId> = vec![];
+    for &id in ids {
+        let control = indexed_controls[id];
+        if !controls.contains(&control) {
+            controls.push(control);
+        }
+    }
+    controls
+}
+
+
+#[test]
+fn default_config_matches_default_crossroad() {
+    let config = CrossroadConfig::from_str(include_str!("../default_crossroad.json")).unwrap();
+
+    let config_lights = config.create_traffic_lights();
+    let config_controls = config.create_traffic_controls(config_lights);
+    let config_crossroad = config.create_crossroad(config_controls).unwrap();
+
+    let crossroad = ::default_crossroad::default_crossroad();
+
+    assert_eq!(config_crossroad.topology.lights().len(), crossroad.topology.lights().len());
+
+    for id in 0..crossroad.topology.lights().len() {
+        let a = config_crossroad.topology.control_of(LightId(id)).unwrap();
+        let b = crossroad.topology.control_of(LightId(id)).unwrap();
+
+        assert_eq!(a.get_ids(), b.get_ids());
+        assert_eq!(a.direction(), b.direction());
+        assert_eq!(a.traffic_type(), b.traffic_type());
+        assert_eq!(a.timings(), b.timings());
+        assert_eq!(config_crossroad.conflicts_for(a), crossroad.conflicts_for(b));
+    }
+
+    for from in 0..crossroad.intergreen.size() {
+        for to in 0..crossroad.intergreen.size() {
+            assert_eq!(config_crossroad.intergreen.get(from, to), crossroad.intergreen.get(from, to));
+        }
+    }
+
+    assert_eq!(config_crossroad.mode, crossroad.mode);
+
+    let config_plan = config_crossroad.fixed_time.as_ref().unwrap();
+    let plan = crossroad.fixed_time.as_ref().unwrap();
+    assert_eq!((config_plan.cycle, config_plan.offset), (plan.cycle, plan.offset));
+    assert_eq!(config_plan.stages.len(), plan.stages.len());
+
+    for (a, b) in config_plan.stages.iter().zip(plan.stages.iter()) {
+        let ids = |crossroad: &Crossroad, stage: &::fixed_time::Stage| -> Vec<Vec<usize>> {
+            stage.controls.iter().map(|&id| crossroad.topology.control(id).get_ids()).collect()
+        };
+        assert_eq!(ids(&config_crossroad, a), ids(&crossroad, b));
+        assert_eq!((a.start, a.end), (b.start, b.end));
+    }
+}
+
+#[test]
+fn config_rejects_unknown_ids() {
+    let json = include_str!("../default_crossroad.json").replace("[15, 16]", "[15, 99]");
+
+    match CrossroadConfig::from_str(&json) {
+        Err(Error::Config(_)) => (),
+        other => panic!("expected a config error, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn config_rejects_more_lights_than_the_protocol() {
+    let json = include_str!("../default_crossroad.json").replace("\"count\": 34", &format!("\"count\": {}", BAAN_COUNT));
+
+    match CrossroadConfig::from_str(&json) {
+        Err(Error::Config(_)) => (),
+        other => panic!("expected a config error, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn config_timings_override_type_defaults() {
+    let json = include_str!("../default_crossroad.json")
+        .replace("\"types\": [", "\"timings\": [{ \"ids\": [6], \"timings\": { \"min_green\": 7, \"max_green\": 30 } }],\n        \"types\": [");
+    let config = CrossroadConfig::from_str(&json).unwrap();
+
+    let traffic_lights = config.create_traffic_lights();
+    let traffic_controls = config.create_traffic_controls(traffic_lights);
+    let crossroad = config.create_crossroad(traffic_controls).unwrap();
+
+    let timings = crossroad.topology.control_of(LightId(6)).unwrap().timings();
+    assert_eq!(timings.min_green, 7);
+    assert_eq!(timings.max_green, 30);
+    assert_eq!(timings.green_extra, Type::Vehicle.default_timings().green_extra);
+}
+
+#[test]
+fn config_rejects_yellow_longer_than_the_intergreen() {
+    let json = include_str!("../default_crossroad.json")
+        .replace("\"types\": [", "\"timings\": [{ \"ids\": [6], \"timings\": { \"yellow\": 7 } }],\n        \"types\": [");
+    let config = CrossroadConfig::from_str(&json).unwrap();
+
+    let traffic_lights = config.create_traffic_lights();
+    let traffic_controls = config.create_traffic_controls(traffic_lights);
+    match config.create_crossroad(traffic_controls) {
+        Err(Error::Config(_)) => (),
+        other => panic!("expected a config error, got {:?}", other.map(|_| ())),
+    }
+
+    // An intergreen time that covers the yellow time is fine.
+    let json = json.replace("{ \"from\": 17, \"seconds\": 8 },", "{ \"from\": 17, \"seconds\": 8 },\n            { \"from\": 6, \"seconds\": 9 },");
+    let config = CrossroadConfig::from_str(&json).unwrap();
+    let traffic_lights = config.create_traffic_lights();
+    let traffic_controls = config.create_traffic_controls(traffic_lights);
+    assert!(config.create_crossroad(traffic_controls).is_ok());
+}
+
+#[cfg(test)]
+fn bus_routes_json(routes: &str) -> String {
+    include_str!("../default_crossroad.json").replace("\"priority\":  [15, 16],", &format!("\"priority\":  [15, 16],\n    \"bus_routes\": {},", routes))
+}
+
+#[test]
+fn config_reads_the_bus_route_table() {
+    let config = CrossroadConfig::from_str(&bus_routes_json(r#"[{ "lines": [1, 5], "movement": "Straight" }, { "lines": [3], "movement": "Right" }]"#)).unwrap();
+    let topology = config.create_traffic_controls(config.create_traffic_lights());
+
+    assert_eq!(topology.bus_movement(5), BusMovement::Straight);
+    assert_eq!(topology.bus_movement(3), BusMovement::Right);
+    assert_eq!(topology.bus_movement(7), BusMovement::StraightAndRight);
+
+    match CrossroadConfig::from_str(&bus_routes_json(r#"[{ "lines": [1], "movement": "Straight" }, { "lines": [1], "movement": "Right" }]"#)) {
+        Err(Error::Config(_)) => (),
+        other => panic!("expected a config error, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn config_reads_the_sensor_health_policy() {
+    use sensor_health::IdleWindow;
+
+    let health = r#""sensor_health": { "max_occupied": 120, "idle": [{ "from": "22:00", "to": "06:00", "max_idle": 7200 }], "fallback": "FixedExtension" },"#;
+    let json = include_str!("../default_crossroad.json").replace("\"priority\":  [15, 16],", &format!("\"priority\":  [15, 16],\n    {}", health));
+    let config = CrossroadConfig::from_str(&json).unwrap();
+    let crossroad = config.create_crossroad(config.create_traffic_controls(config.create_traffic_lights())).unwrap();
+
+    assert_eq!(crossroad.sensor_health, SensorHealthPolicy {
+        max_occupied: 120,
+        idle: vec![IdleWindow { from: 22 * 60, to: 6 * 60, max_idle: 7200 }],
+        fallback: Fallback::FixedExtension,
+    });
+
+    match CrossroadConfig::from_str(&json.replace("22:00", "22h")).and_then(|c| c.create_crossroad(c.create_traffic_controls(c.create_traffic_lights()))) {
+        Err(Error::Config(_)) => (),
+        other => panic!("expected a config error, got {:?}", other.map(|_| ())),
+    }
+}
@@ -0,0 +1,258 @@
+use std::fmt;
+use std::collections::HashMap;
+
+use traffic_protocol::*;
+use traffic_controls::*;
+use crossroad::*;
+
+
+// -------------------------------------------------------------------------------
+// ConflictMatrix
+// -------------------------------------------------------------------------------
+
+/// Symmetric conflict relation between traffic light ids.
+/// Two lights conflict when they may never be green at the same time.
+#[derive(Clone)]
+pub struct ConflictMatrix {
+    size: usize,
+    conflicts: Vec<bool>,
+}
+
+impl ConflictMatrix {
+
+    pub fn empty(size: usize) -> ConflictMatrix {
+        ConflictMatrix { size: size, conflicts: vec![false; size * size] }
+    }
+
+    /// Only the listed conflicts count, so controls of the same approach may be green together.
+    /// A control listing itself is ignored here, `validate` reports it.
+    pub fn from_directions(topology: &Topology, directions: &HashMap<Direction, XorConflictsGroup>) -> ConflictMatrix {
+        let size = topology.controls().iter().flat_map(|c| c.get_ids()).max().map(|max| max + 1).unwrap_or(0);
+        let mut matrix = ConflictMatrix::empty(size);
+
+        for xor in directions.values() {
+            for entry in xor.entries() {
+                for &other in &entry.conflicting_with {
+                    if entry.control != other {
+                        matrix.set_controls(topology.control(entry.control), topology.control(other));
+                    }
+                }
+            }
+        }
+
+        matrix
+    }
+
+    pub fn set(&mut self, a: usize, b: usize) {
+        self.conflicts[a * self.size + b] = true;
+        self.conflicts[b * self.size + a] = true;
+    }
+
+    pub fn set_controls(&mut self, a: &Control, b: &Control) {
+        for id_a in a.get_ids() {
+            for id_b in b.get_ids() {
+                self.set(id_a, id_b);
+            }
+        }
+    }
+
+    pub fn is_conflicting(&self, a: usize, b: usize) -> bool {
+        a < self.size && b < self.size && self.conflicts[a * self.size + b]
+    }
+
+    pub fn controls_conflict(&self, a: &Control, b: &Control) -> bool {
+        a.get_ids().iter().any(|&id_a| b.get_ids().iter().any(|&id_b| self.is_conflicting(id_a, id_b)))
+    }
+
+    /// All light ids conflicting with one of the lights of this control.
+    pub fn conflicts_for(&self, control: &Control) -> Vec<usize> {
+        let ids = control.get_ids();
+        (0..self.size).filter(|&other| ids.iter().any(|&id| self.is_conflicting(id, other))).collect()
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+impl fmt::Debug for ConflictMatrix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for a in 0..self.size {
+            try!(write!(f, "\n{:>3} ", a));
+            for b in 0..self.size {
+                try!(write!(f, "{}", if self.is_conflicting(a, b) { 'x' } else { '.' }));
+            }
+        }
+        Ok(())
+    }
+}
+
+
+// -------------------------------------------------------------------------------
+// Validation
+// -------------------------------------------------------------------------------
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConflictIssue {
+    /// `control` lists `other` as a conflict, but not the other way around.
+    Asymmetric { control: Vec<usize>, other: Vec<usize> },
+    SelfConflict { control: Vec<usize> },
+    MissingEntry { control: Vec<usize> },
+    /// Two conflicting controls in the same fixed time stage.
+    StageConflict { stage: usize, control: Vec<usize>, other: Vec<usize> },
+}
+
+impl fmt::Display for ConflictIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConflictIssue::Asymmetric { ref control, ref other } =>
+                write!(f, "control {:?} conflicts with {:?}, but {:?} does not list {:?}", control, other, other, control),
+            ConflictIssue::SelfConflict { ref control } =>
+                write!(f, "control {:?} lists itself as a conflict", control),
+            ConflictIssue::MissingEntry { ref control } =>
+                write!(f, "control {:?} has no conflicts entry in its direction", control),
+            ConflictIssue::StageConflict { stage, ref control, ref other } =>
+                write!(f, "fixed time stage #{} has conflicting controls {:?} and {:?}", stage, control, other),
+        }
+    }
+}
+
+/// Checks the declared conflicts of a crossroad. An empty result means the conflict data is sane.
+pub fn validate(crossroad: &Crossroad) -> Vec<ConflictIssue> {
+    let mut issues = vec![];
+
+    let topology = &crossroad.topology;
+
+    for control in topology.controls() {
+        if crossroad.conflict_entry(control).is_none() {
+            issues.push(ConflictIssue::MissingEntry { control: control.get_ids() });
+        }
+    }
+
+    for xor in crossroad.directions.values() {
+        for entry in xor.entries() {
+            let control = topology.control(entry.control);
+
+            if entry.conflicting_with.contains(&entry.control) {
+                issues.push(ConflictIssue::SelfConflict { control: control.get_ids() });
+            }
+
+            for &other_id in entry.conflicting_with.iter().filter(|&&other| other != entry.control) {
+                let other = topology.control(other_id);
+                let listed_back = match crossroad.conflict_entry(other) {
+                    Some(other_entry) => other_entry.conflicting_with.contains(&entry.control),
+                    None => false,
+                };
+
+                if !listed_back {
+                    issues.push(ConflictIssue::Asymmetric { control: control.get_ids(), other: other.get_ids() });
+                }
+            }
+        }
+    }
+
+    if let Some(ref plan) = crossroad.fixed_time {
+        for (index, stage) in plan.stages.iter().enumerate() {
+            for (position, &a) in stage.controls.iter().enumerate() {
+                for &b in &stage.controls[position + 1..] {
+                    if crossroad.conflicts.controls_conflict(topology.control(a), topology.control(b)) {
+                        issues.push(ConflictIssue::StageConflict { stage: index, control: topology.control(a).get_ids(), other: topology.control(b).get_ids() });
+                    }
+                }
+            }
+        }
+    }
+
+    issues
+}
+
+
+#[test]
+fn default_crossroad_is_valid() {
+    let crossroad = ::default_crossroad::default_crossroad();
+
+    assert_eq!(validate(&crossroad), vec![]);
+}
+
+#[cfg(test)]
+const SMALL_CROSSROAD: &'static str = r#"{
+        "traffic_lights": {
+            "count": 3,
+            "directions": [
+                { "direction": "North", "ids": [0, 1] },
+                { "direction": "East",  "ids": [2, 3] }
+            ],
+            "types": []
+        },
+        "groups": [],
+        "conflicts": [
+            { "name": "noord", "direction": "North", "entries": [
+                { "control": 0, "conflicting_with": [2] },
+                { "control": 1, "conflicting_with": [3] }
+            ]},
+            { "name": "oost", "direction": "East", "entries": [
+                { "control": 2, "conflicting_with": [0] },
+                { "control": 3, "conflicting_with": [1] }
+            ]}
+        ],
+        "primary":   [0],
+        "secondary": [1, 2, 3],
+        "priority":  []
+    }"#;
+
+#[cfg(test)]
+fn small_crossroad(json: &str) -> Crossroad {
+    let config = ::config::CrossroadConfig::from_str(json).unwrap();
+    config.create_crossroad(config.create_traffic_controls(config.create_traffic_lights())).unwrap()
+}
+
+#[test]
+fn small_crossroad_is_valid() {
+    assert_eq!(validate(&small_crossroad(SMALL_CROSSROAD)), vec![]);
+}
+
+#[test]
+fn validator_reports_missing_entries() {
+    let json = SMALL_CROSSROAD.replace(",\n                { \"control\": 3, \"conflicting_with\": [1] }", "");
+    assert!(json != SMALL_CROSSROAD);
+
+    assert_eq!(validate(&small_crossroad(&json)), vec![
+        ConflictIssue::MissingEntry { control: vec![3] },
+        ConflictIssue::Asymmetric { control: vec![1], other: vec![3] },
+    ]);
+}
+
+#[test]
+fn validator_reports_asymmetric_and_self_conflicts() {
+    let json = include_str!("../default_crossroad.json")
+        .replace("{ \"control\": 11, \"conflicting_with\": [21] }", "{ \"control\": 11, \"conflicting_with\": [21, 11, 12] }");
+    let config = ::config::CrossroadConfig::from_str(&json).unwrap();
+
+    let traffic_lights = config.create_traffic_lights();
+    let traffic_controls = config.create_traffic_controls(traffic_lights);
+    let crossroad = config.create_crossroad(traffic_controls).unwrap();
+
+    let issues = validate(&crossroad);
+    assert!(issues.contains(&ConflictIssue::SelfConflict { control: vec![11] }));
+    assert!(issues.contains(&ConflictIssue::Asymmetric { control: vec![11], other: vec![12] }));
+
+    // The new conflict also breaks the fixed time stage with 11 and 12.
+    assert!(issues.contains(&ConflictIssue::StageConflict { stage: 3, control: vec![12], other: vec![11] }));
+    assert_eq!(issues.len(), 3);
+}
+
+#[test]
+fn validator_reports_conflicting_fixed_time_stages() {
+    let json = include_str!("../default_crossroad.json")
+        .replace("{ \"ids\": [17, 19, 21], \"green\": 10 }", "{ \"ids\": [17, 19, 21, 9], \"green\": 10 }");
+    let config = ::config::CrossroadConfig::from_str(&json).unwrap();
+
+    let traffic_lights = config.create_traffic_lights();
+    let traffic_controls = config.create_traffic_controls(traffic_lights);
+    let crossroad = config.create_crossroad(traffic_controls).unwrap();
+
+    assert_eq!(validate(&crossroad), vec![
+        ConflictIssue::StageConflict { stage: 5, control: vec![17, 23, 25, 24, 26], other: vec![9, 10] },
+        ConflictIssue::StageConflict { stage: 5, control: vec![21, 22, 31, 34, 32, 33], other: vec![9, 10] },
+    ]);
+}
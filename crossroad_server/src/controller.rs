@@ -0,0 +1,618 @@
+use std::panic;
+use time;
+
+use traffic_protocol::*;
+use traffic_controls::*;
+use crossroad::*;
+use intergreen::ClearanceState;
+use fixed_time::FixedTimeState;
+use flashing::FlashingState;
+use schedule::PlanTransition;
+use audit::{AuditLog, AuditEvent};
+use bus_priority::BusPriorityLog;
+use detector::Detectors;
+use sensor_health::SensorHealth;
+use watchdog::SafeState;
+
+
+// -------------------------------------------------------------------------------
+// SignalChange
+// -------------------------------------------------------------------------------
+
+/// A traffic light that switched to a new state during a step.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SignalChange {
+    pub id: usize,
+    pub state: JsonState,
+}
+
+impl SignalChange {
+    pub fn json_obj(&self) -> StoplichtJson {
+        StoplichtJson { id: self.id, status: self.state.id() }
+    }
+}
+
+/// What the controller decided during a step, for the event log.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum ControllerEvent {
+    StateChanged { from: &'static str, to: &'static str },
+    /// The lights of the group that starts, chosen by the strategy or for a bus or emergency vehicle.
+    GroupChosen { lights: Vec<usize>, bus: bool },
+    ModeChanged { from: ControlMode, to: ControlMode },
+    Audit(AuditEvent),
+}
+
+/// Why the controller stopped, and what to continue with once it resumes.
+#[derive(Debug, Clone)]
+struct Stop {
+    reason: String,
+    mode: ControlMode,
+    safe_state: SafeState,
+}
+
+/// The sensor states as seen by one step, copied out of the shared state so the lock isn't held while stepping.
+pub type SensorSnapshot = SensorStates;
+
+
+// -------------------------------------------------------------------------------
+// Controller
+// -------------------------------------------------------------------------------
+
+/// Runs a crossroad one step at a time. It doesn't know about threads, channels or json,
+/// the caller decides when to step and what to do with the changes.
+/// Owns all of its state, a clone is a snapshot that can be stepped on its own.
+#[derive(Debug, Clone)]
+pub struct Controller {
+    crossroad: Crossroad,
+    state: CrossroadState,
+    fixed_time_state: FixedTimeState,
+    flashing_state: FlashingState,
+    clearance: ClearanceState,
+    start: Option<time::Tm>,
+    plan: Option<usize>,
+    transitions: Vec<PlanTransition>,
+    signals: Vec<JsonState>,
+    fault: Option<String>,
+    stopped: Option<Stop>,
+    events: Vec<ControllerEvent>,
+    reported_mode: ControlMode,
+    reported_audit: usize,
+    audit: AuditLog,
+    bus_priority_log: BusPriorityLog,
+    detectors: Detectors,
+    sensor_health: SensorHealth,
+    last_step: (i32, time::Tm),
+}
+
+impl Controller {
+
+    pub fn new(crossroad: Crossroad) -> Controller {
+        Controller {
+            clearance: ClearanceState::new(crossroad.intergreen.size()),
+            fixed_time_state: FixedTimeState::new(&crossroad.topology),
+            flashing_state: FlashingState::new(&crossroad.topology),
+            reported_mode: crossroad.mode,
+            sensor_health: SensorHealth::new(&crossroad.topology),
+            signals: vec![JsonState::Rood; crossroad.intergreen.size()],
+            crossroad: crossroad,
+            state: CrossroadState::AllRed,
+            start: None,
+            plan: None,
+            transitions: vec![],
+            fault: None,
+            stopped: None,
+            events: vec![],
+            reported_audit: 0,
+            audit: AuditLog::new(),
+            bus_priority_log: BusPriorityLog::new(),
+            detectors: Detectors::new(),
+            last_step: (0, time::empty_tm()),
+        }
+    }
+
+    pub fn crossroad(&self) -> &Crossroad {
+        &self.crossroad
+    }
+
+    /// Every plan switch of the schedule so far, the first one is the plan the controller started with.
+    pub fn transitions(&self) -> &Vec<PlanTransition> {
+        &self.transitions
+    }
+
+    pub fn audit(&self) -> &AuditLog {
+        &self.audit
+    }
+
+    /// Occupancy, vehicle counts and queues estimated from the sensors.
+    pub fn detectors(&self) -> &Detectors {
+        &self.detectors
+    }
+
+    /// The sensors that are faulty now.
+    pub fn sensor_health(&self) -> &SensorHealth {
+        &self.sensor_health
+    }
+
+    pub fn bus_priority_log(&self) -> &BusPriorityLog {
+        &self.bus_priority_log
+    }
+
+    /// Why the controller fell back to flashing, it stays there until it is restarted.
+    pub fn fault(&self) -> Option<&String> {
+        self.fault.as_ref()
+    }
+
+    /// The decisions since the last call, in order.
+    pub fn take_events(&mut self) -> Vec<ControllerEvent> {
+        ::std::mem::replace(&mut self.events, vec![])
+    }
+
+    /// Why the controller stopped, until it resumes.
+    pub fn stopped(&self) -> Option<&String> {
+        self.stopped.as_ref().map(|stop| &stop.reason)
+    }
+
+    /// The lights finished clearing, they are in the safe state of `stop` or flash after a fault.
+    pub fn in_safe_state(&self) -> bool {
+        self.crossroad.mode == ControlMode::Flashing && self.flashing_state.is_cleared()
+    }
+
+    /// Ends the signal program from the next step on: green lights go through yellow to red, then every
+    /// light stays red or flashes until `resume`.
+    pub fn stop(&mut self, reason: String, safe_state: SafeState) {
+        if self.stopped.is_some() {
+            return;
+        }
+
+        println!("========== STOP: {}", reason);
+        let (time, now) = self.last_step;
+        self.audit.record(time, now, AuditEvent::Stopped { reason: reason.clone() });
+        self.stopped = Some(Stop { reason: reason, mode: self.crossroad.mode, safe_state: safe_state });
+
+        // A crossroad that already flashes keeps flashing.
+        match (self.crossroad.mode, safe_state) {
+            (ControlMode::Flashing, SafeState::Flashing) => (),
+            (_, SafeState::Flashing) => self.flashing_state = FlashingState::new(&self.crossroad.topology),
+            (_, SafeState::AllRed) => self.flashing_state = FlashingState::all_red(&self.crossroad.topology),
+        }
+        self.crossroad.mode = ControlMode::Flashing;
+    }
+
+    /// Continues the mode the controller stopped in, through the all red start-up. After a fault it keeps flashing.
+    pub fn resume(&mut self) {
+        let stop = match self.stopped.take() {
+            Some(stop) => stop,
+            None => return,
+        };
+
+        println!("========== RESUME: continuing {:?}", stop.mode);
+        let (time, now) = self.last_step;
+        self.audit.record(time, now, AuditEvent::Resumed);
+
+        match (self.fault.is_some(), stop.mode, stop.safe_state) {
+            (true, _, _) | (false, ControlMode::Flashing, SafeState::Flashing) => (),
+            (false, mode, _) => self.flashing_state.leave(mode),
+        }
+    }
+
+    /// What every light shows after the last step, indexed by light id.
+    pub fn signals(&self) -> &Vec<JsonState> {
+        &self.signals
+    }
+
+    /// The last changes never reached the lights, they show `signals` instead.
+    pub fn restore_signals(&mut self, signals: &Vec<JsonState>) {
+        self.signals = signals.clone();
+    }
+
+    /// Something went wrong inside the controller, all lights clear and flash yellow from the next step on.
+    pub fn fail_safe(&mut self, reason: String) {
+        println!("========== FAIL SAFE: {}", reason);
+        let (time, now) = self.last_step;
+        self.audit.record(time, now, AuditEvent::FailSafe { reason: reason.clone() });
+        self.fault = Some(reason);
+        self.flashing_state = FlashingState::new(&self.crossroad.topology);
+        self.crossroad.mode = ControlMode::Flashing;
+    }
+
+    /// Advances the crossroad to `now`, meant to be called once per tick. Returns the lights that changed, in order.
+    /// A panic or two conflicting green lights put the crossroad in the fail-safe flashing state.
+    pub fn step(&mut self, now: time::Tm, sensors: &SensorSnapshot) -> Vec<SignalChange> {
+        let time = self.seconds_at(now);
+        self.last_step = (time, now);
+
+        let changes = match panic::catch_unwind(panic::AssertUnwindSafe(|| self.step_mode(time, now, sensors))) {
+            Ok(changes) => changes,
+            Err(_) => {
+                self.fail_safe(format!("the controller panicked at {}", time));
+
+                // Only the flashing state runs, the sensor and schedule code that panicked may panic again.
+                match panic::catch_unwind(panic::AssertUnwindSafe(|| self.step_flashing(time))) {
+                    Ok(changes) => changes,
+                    Err(_) => self.crossroad.set_all(JsonState::GeelKnipperend),
+                }
+            },
+        };
+
+        for change in &changes {
+            self.signals[change.id] = change.state;
+        }
+
+        if let Some((a, b)) = self.conflicting_greens() {
+            self.fail_safe(format!("conflicting lights {} and {} are green together at {}", a, b, time));
+        }
+
+        self.report();
+        changes
+    }
+
+    fn step_mode(&mut self, time: i32, now: time::Tm, sensors: &SensorSnapshot) -> Vec<SignalChange> {
+        let mut changes = vec![];
+
+        let sensors = &self.check_sensors(time, now, sensors);
+        self.detectors.update(time, sensors);
+        self.follow_schedule(time, now);
+
+        match (self.crossroad.mode, &self.crossroad.fixed_time) {
+            (ControlMode::Flashing, _) => {
+                if let Some(next) = self.flashing_state.run_loop(time, &self.crossroad, &self.signals, &mut changes, &mut self.clearance) {
+                    self.crossroad.mode = next;
+                    self.state = CrossroadState::AllRed;
+                    self.fixed_time_state = FixedTimeState::new(&self.crossroad.topology);
+                    self.flashing_state = FlashingState::new(&self.crossroad.topology);
+                }
+            },
+            (ControlMode::FixedTime, &Some(ref plan)) => {
+                plan.run_loop(time, &self.crossroad, sensors, &mut self.fixed_time_state, &mut changes, &mut self.clearance);
+            },
+            _ => {
+                let preempting = self.state.preemption();
+                let priorities = self.crossroad.bus_priorities(time, now, &self.state, sensors, &self.clearance);
+                self.bus_priority_log.record(time, now, &priorities);
+
+                if let Some(newstate) = self.crossroad.run_loop(time, now, &mut self.state, sensors, &self.signals, &self.detectors, &priorities, &mut changes, &mut self.clearance) {
+                    self.report_state(&newstate);
+                    self.state = newstate;
+                }
+                self.audit_preemption(time, now, preempting, sensors);
+            },
+        }
+
+        changes
+    }
+
+    fn step_flashing(&mut self, time: i32) -> Vec<SignalChange> {
+        let mut changes = vec![];
+        self.flashing_state.run_loop(time, &self.crossroad, &self.signals, &mut changes, &mut self.clearance);
+        changes
+    }
+
+    /// Records sensors that became faulty or work again, and replaces the faulty ones by their fallback.
+    fn check_sensors(&mut self, time: i32, now: time::Tm, sensors: &SensorSnapshot) -> SensorSnapshot {
+        let (started, ended) = self.sensor_health.check(now, &self.crossroad.sensor_health, sensors);
+
+        for status in started {
+            self.audit.record(time, now, AuditEvent::SensorFault { sensor: status.sensor.0, fault: status.fault, fallback: status.fallback });
+        }
+        for status in ended {
+            self.audit.record(time, now, AuditEvent::SensorRecovered { sensor: status.sensor.0, fault: status.fault });
+        }
+        for id in 0..BAAN_COUNT {
+            self.detectors.set_fixed_extension(SensorId(id), self.sensor_health.extension(SensorId(id)));
+        }
+
+        self.sensor_health.apply(sensors)
+    }
+
+    /// A state also returns a new state when it only moves on to its next phase, that isn't a decision.
+    fn report_state(&mut self, newstate: &CrossroadState) {
+        if newstate.name() != self.state.name() {
+            self.events.push(ControllerEvent::StateChanged { from: self.state.name(), to: newstate.name() });
+        }
+
+        if let CrossroadState::SignalGroup(ref group) = *newstate {
+            let lights: Vec<usize> = group.controls.iter().flat_map(|c| self.crossroad.topology.control(c.control).get_ids()).collect();
+            let same_group = match self.state {
+                CrossroadState::SignalGroup(ref current) => current.controls.iter().flat_map(|c| self.crossroad.topology.control(c.control).get_ids()).eq(lights.iter().cloned()),
+                _ => false,
+            };
+            if !same_group {
+                self.events.push(ControllerEvent::GroupChosen { lights: lights, bus: group.is_bus });
+            }
+        }
+    }
+
+    /// Adds the mode changes and audit entries since the last step to the events.
+    fn report(&mut self) {
+        if self.crossroad.mode != self.reported_mode {
+            self.events.push(ControllerEvent::ModeChanged { from: self.reported_mode, to: self.crossroad.mode });
+            self.reported_mode = self.crossroad.mode;
+        }
+
+        self.events.extend(self.audit.since(self.reported_audit).iter().map(|e| ControllerEvent::Audit(e.event.clone())));
+        self.reported_audit = self.audit.recorded();
+    }
+
+    fn audit_preemption(&mut self, time: i32, now: time::Tm, before: Option<EmergencyTarget>, sensors: &SensorSnapshot) {
+        match (before, self.state.preemption()) {
+            (None, Some(target)) => self.audit.record(time, now, AuditEvent::PreemptionStarted { target: target }),
+            (Some(target), None) => {
+                let timed_out = sensors.emergency_request().map_or(false, |request| request.target == target);
+                self.audit.record(time, now, AuditEvent::PreemptionEnded { target: target, timed_out: timed_out });
+            },
+            _ => (),
+        }
+    }
+
+    fn conflicting_greens(&self) -> Option<(usize, usize)> {
+        let green: Vec<usize> = (0..self.signals.len())
+            .filter(|&id| self.signals[id] != JsonState::Rood && self.signals[id] != JsonState::Geel && self.signals[id] != JsonState::GeelKnipperend)
+            .collect();
+
+        for &a in &green {
+            if let Some(&b) = green.iter().find(|&&b| self.crossroad.conflicts.is_conflicting(a, b)) {
+                return Some((a, b));
+            }
+        }
+        None
+    }
+
+    /// Switches to the plan the schedule wants at `now`, but only while it is safe to take over:
+    /// every light red, or only the primary lanes green.
+    fn follow_schedule(&mut self, time: i32, now: time::Tm) {
+        let wanted = match self.crossroad.schedule {
+            Some(ref schedule) => schedule.plan_at(&now),
+            None => return,
+        };
+
+        // After a fault the crossroad keeps flashing and a stopped one stays stopped, whatever the schedule says.
+        if self.fault.is_some() || self.stopped.is_some() || self.plan == Some(wanted) || (self.plan.is_some() && !self.at_safe_point()) {
+            return;
+        }
+
+        let schedule = self.crossroad.schedule.clone().unwrap();
+        let plan = schedule.plan(wanted);
+        let from = self.plan.map(|index| schedule.plan(index).name.clone());
+
+        println!("========== SCHEDULE: switching from plan {:?} to '{}' ({:?}) at {}", from, plan.name, plan.mode, time);
+
+        schedule.apply(wanted, &mut self.crossroad.topology);
+
+        match (self.crossroad.mode, plan.mode) {
+            (ControlMode::Flashing, ControlMode::Flashing) => (),
+            // The mode changes once the all red start-up is done.
+            (ControlMode::Flashing, next) => self.flashing_state.leave(next),
+            (_, ControlMode::Flashing) => {
+                self.flashing_state = FlashingState::new(&self.crossroad.topology);
+                self.crossroad.mode = ControlMode::Flashing;
+            },
+            (ControlMode::Actuated, ControlMode::FixedTime) => {
+                let green = match self.state {
+                    CrossroadState::PrimaryTraffic(_) => self.crossroad.primary_traffic.clone(),
+                    _ => vec![],
+                };
+                self.fixed_time_state = FixedTimeState::with_green(&self.crossroad.topology, &green);
+                self.crossroad.mode = plan.mode;
+            },
+            (ControlMode::FixedTime, ControlMode::Actuated) => {
+                self.state = CrossroadState::AllRed;
+                self.crossroad.mode = plan.mode;
+            },
+            _ => (),
+        }
+
+        self.plan = Some(wanted);
+        self.audit.record(time, now, AuditEvent::PlanSwitched { from: from.clone(), to: plan.name.clone() });
+        self.transitions.push(PlanTransition { time: time, at: now, from: from, to: plan.name.clone() });
+    }
+
+    fn at_safe_point(&self) -> bool {
+        match self.crossroad.mode {
+            ControlMode::Flashing => self.flashing_state.is_flashing(),
+            ControlMode::FixedTime => self.fixed_time_state.all_red(),
+            ControlMode::Actuated => match self.state {
+                CrossroadState::AllRed => true,
+                CrossroadState::PrimaryTraffic(ref group) => group.controls_have_state(TrafficLightState::Green{start:0}),
+                _ => false,
+            },
+        }
+    }
+
+    /// Seconds since the first step, which is second 1. Rounded, so a tick that arrives a bit early still counts.
+    fn seconds_at(&mut self, now: time::Tm) -> i32 {
+        let start = *self.start.get_or_insert(now);
+        ((now - start).num_milliseconds() + 500) as i32 / 1000 + 1
+    }
+}
+
+
+#[test]
+fn controller_steps_without_a_server() {
+    use clock::*;
+
+    let crossroad = ::default_crossroad::default_crossroad();
+
+    let clock = SimulatedClock::new();
+    let mut controller = Controller::new(crossroad);
+    let mut sensors = SensorStates::new();
+
+    // Without traffic the primary lanes turn green.
+    let mut changes = vec![];
+    for _ in 0..3 {
+        clock.tick();
+        changes.extend(controller.step(clock.now(), &sensors));
+    }
+    assert!(changes.contains(&SignalChange { id: 2, state: JsonState::Groen }));
+    assert!(!changes.iter().any(|c| c.id == 13));
+
+    // A car at 13 closes them again.
+    sensors.update(&vec![Baan { id: 13, bezet: true }], clock.now());
+    let mut changes = vec![];
+    for _ in 0..30 {
+        clock.tick();
+        changes.extend(controller.step(clock.now(), &sensors));
+    }
+    assert!(changes.contains(&SignalChange { id: 2, state: JsonState::Geel }));
+    assert!(changes.contains(&SignalChange { id: 13, state: JsonState::Groen }));
+}
+
+#[test]
+fn controller_snapshot_steps_on_another_thread() {
+    use std::thread;
+    use clock::*;
+
+    let mut controller = Controller::new(::default_crossroad::default_crossroad());
+
+    let clock = SimulatedClock::new();
+    let sensors = SensorStates::new();
+    for _ in 0..3 {
+        clock.tick();
+        controller.step(clock.now(), &sensors);
+    }
+
+    // The snapshot continues from the same state as the original.
+    let mut snapshot = controller.clone();
+    let now = clock.now() + time::Duration::seconds(1);
+    let expected = controller.step(now, &sensors);
+    let changes = thread::spawn(move || snapshot.step(now, &SensorStates::new())).join().unwrap();
+
+    assert_eq!(changes, expected);
+}
+
+#[test]
+fn conflicting_greens_fall_back_to_flashing() {
+    use clock::*;
+    use fixed_time::Stage;
+
+    let mut crossroad = ::default_crossroad::default_crossroad();
+
+    // A broken plan that starts 2 and 13 together.
+    let conflicting = vec![crossroad.topology.control_id_of(LightId(2)).unwrap(), crossroad.topology.control_id_of(LightId(13)).unwrap()];
+    crossroad.mode = ControlMode::FixedTime;
+    crossroad.fixed_time.as_mut().unwrap().stages = vec![Stage { controls: conflicting, start: 0, end: 120 }];
+
+    let clock = SimulatedClock::new();
+    let mut controller = Controller::new(crossroad);
+    let sensors = SensorStates::new();
+
+    for _ in 0..10 {
+        clock.tick();
+        controller.step(clock.now(), &sensors);
+    }
+
+    assert!(controller.fault().is_some());
+    assert_eq!(controller.crossroad().mode, ControlMode::Flashing);
+    assert!(controller.signals().iter().all(|&s| s == JsonState::GeelKnipperend));
+}
+
+#[test]
+fn stopped_controller_clears_to_red_and_resumes() {
+    use clock::*;
+    use flashing::START_UP_ALL_RED;
+
+    let mut controller = Controller::new(::default_crossroad::default_crossroad());
+
+    let clock = SimulatedClock::new();
+    let sensors = SensorStates::new();
+
+    // The primary lanes are green.
+    for _ in 0..5 {
+        clock.tick();
+        controller.step(clock.now(), &sensors);
+    }
+    assert_eq!(controller.signals()[2], JsonState::Groen);
+
+    controller.stop("test".to_string(), SafeState::AllRed);
+
+    let mut red_at = None;
+    for _ in 0..10 {
+        let time = clock.tick();
+        for change in controller.step(clock.now(), &sensors) {
+            assert!(change.state == JsonState::Geel || change.state == JsonState::Rood, "light {} turned {:?}", change.id, change.state);
+        }
+        if red_at.is_none() && controller.in_safe_state() {
+            red_at = Some(time);
+        }
+    }
+    assert!(red_at.is_some());
+    assert!(controller.signals().iter().all(|&s| s == JsonState::Rood));
+    assert_eq!(controller.stopped(), Some(&"test".to_string()));
+
+    // Green again after the all red start-up.
+    controller.resume();
+    let resumed = clock.ticks();
+    let mut green_at = None;
+    for _ in 0..20 {
+        let time = clock.tick();
+        if green_at.is_none() && controller.step(clock.now(), &sensors).iter().any(|c| c.state == JsonState::Groen) {
+            green_at = Some(time);
+        }
+    }
+    assert!(green_at.map_or(false, |t| t > resumed + START_UP_ALL_RED), "green at {:?}, resumed at {}", green_at, resumed);
+    assert_eq!(controller.crossroad().mode, ControlMode::Actuated);
+
+    let events: Vec<AuditEvent> = controller.audit().entries().iter().map(|e| e.event.clone()).collect();
+    assert_eq!(events, vec![AuditEvent::Stopped { reason: "test".to_string() }, AuditEvent::Resumed]);
+}
+
+#[test]
+fn controller_reports_its_decisions() {
+    use clock::*;
+
+    let mut controller = Controller::new(::default_crossroad::default_crossroad());
+
+    let clock = SimulatedClock::new();
+    let mut sensors = SensorStates::new();
+    let mut events = vec![];
+
+    for _ in 0..30 {
+        let time = clock.tick();
+        if time == 13 {
+            sensors.update(&vec![Baan { id: 13, bezet: true }], clock.now());
+        }
+        controller.step(clock.now(), &sensors);
+        events.extend(controller.take_events());
+    }
+    controller.stop("test".to_string(), SafeState::AllRed);
+    controller.step(clock.now(), &sensors);
+    events.extend(controller.take_events());
+
+    // Only real changes of the state, and the group of 13 once.
+    assert!(events.iter().all(|e| match *e { ControllerEvent::StateChanged { from, to } => from != to, _ => true }), "{:?}", events);
+    assert_eq!(events.iter().filter(|e| match **e { ControllerEvent::GroupChosen { ref lights, .. } => lights.contains(&13), _ => false }).count(), 1);
+    assert!(events.contains(&ControllerEvent::Audit(AuditEvent::Stopped { reason: "test".to_string() })));
+    assert_eq!(controller.take_events(), vec![]);
+}
+
+#[cfg(test)]
+#[derive(Debug, Copy, Clone)]
+struct PanickingStrategy;
+
+#[cfg(test)]
+impl ::strategy::ControlStrategy for PanickingStrategy {
+    fn name(&self) -> &'static str { "panicking" }
+    fn next_group(&self, _: &::strategy::StrategyView) -> ::strategy::Decision { panic!("next_group") }
+    fn stop_group(&self, _: &::strategy::StrategyView, _: &::signal_group::SignalGroup, _: bool) -> bool { panic!("stop_group") }
+    fn box_clone(&self) -> Box<::strategy::ControlStrategy> { Box::new(*self) }
+}
+
+#[test]
+fn panicking_controller_falls_back_to_flashing() {
+    use clock::*;
+
+    let mut crossroad = ::default_crossroad::default_crossroad();
+    crossroad.strategy = Box::new(PanickingStrategy);
+    let mut controller = Controller::new(crossroad);
+
+    let clock = SimulatedClock::new();
+    let sensors = SensorStates::new();
+
+    // The strategy panics every step, the controller keeps going without it.
+    for _ in 0..20 {
+        clock.tick();
+        controller.step(clock.now(), &sensors);
+    }
+    assert!(controller.fault().is_some());
+    assert_eq!(controller.crossroad().mode, ControlMode::Flashing);
+    assert!(controller.signals().iter().all(|&s| s == JsonState::GeelKnipperend), "{:?}", controller.signals());
+}
@@ -1,67 +1,182 @@
 use traffic_protocol::*;
 use traffic_controls::*;
 use signal_group::*;
-use std::collections::HashSet;
-use std::sync::{Arc, Mutex};
 use std::collections::HashMap;
-use std::sync::mpsc::{Sender};
 use time;
-use cartesian;
-
-pub enum CrossroadState<'a> {
+use solver;
+use conflict_matrix::ConflictMatrix;
+use intergreen::*;
+use controller::*;
+use fixed_time::FixedTimePlan;
+use schedule::Schedule;
+use bus_priority::*;
+use strategy::*;
+use detector::Detectors;
+use sensor_health::SensorHealthPolicy;
+use watchdog::WatchdogPolicy;
+use event_log::EventLogConfig;
+use clock::*;
+
+#[derive(Debug, Clone)]
+pub enum CrossroadState {
     AllRed,
-    PrimaryTraffic(SignalGroup<'a>),
-    CreatePriorityGroup,
-    CreateSignalGroup,
-    SignalGroup(SignalGroup<'a>),
+    PrimaryTraffic(SignalGroup),
+    SignalGroup(SignalGroup),
+    /// The group is stopped for an emergency vehicle.
+    ClearForPreemption(SignalGroup),
+    /// Only the lights of the emergency vehicle are green, `ending` once the request is gone.
+    Preemption { group: SignalGroup, target: EmergencyTarget, ending: bool },
+}
+
+impl CrossroadState {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            CrossroadState::AllRed => "AllRed",
+            CrossroadState::PrimaryTraffic(_) => "PrimaryTraffic",
+            CrossroadState::SignalGroup(_) => "SignalGroup",
+            CrossroadState::ClearForPreemption(_) => "ClearForPreemption",
+            CrossroadState::Preemption { .. } => "Preemption",
+        }
+    }
+
+    pub fn preemption(&self) -> Option<EmergencyTarget> {
+        match *self {
+            CrossroadState::Preemption { target, .. } => Some(target),
+            _ => None,
+        }
+    }
+
+    /// The controls that are green or yellow.
+    pub fn green_controls(&self) -> Vec<ControlId> {
+        match *self {
+            CrossroadState::PrimaryTraffic(ref group) |
+            CrossroadState::SignalGroup(ref group) |
+            CrossroadState::ClearForPreemption(ref group) |
+            CrossroadState::Preemption { ref group, .. } => group.green_controls(),
+            _ => vec![],
+        }
+    }
 }
 
-pub struct Crossroad<'a> {
-    pub traffic_controls: Vec<&'a Control<'a>>,
-    pub primary_group: SignalGroup<'a>,
-    pub primary_traffic: Vec<&'a Control<'a>>,
-    pub secondary_traffic: Vec<&'a Control<'a>>,
-    pub priority_traffic: Vec<&'a Control<'a>>,
-    pub directions: HashMap<Direction, XorConflictsGroup<'a>>
+/// Seconds after which an emergency request is dropped, in case the client never clears it.
+pub const PREEMPTION_TIMEOUT: i64 = 90;
+
+/// How the lights are controlled, selected per crossroad.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ControlMode {
+    /// Signal groups are formed from the waiting traffic.
+    Actuated,
+    /// The stages of the `FixedTimePlan` are run in order, the sensors are ignored.
+    FixedTime,
+    /// Every light flashes yellow, for the night and as the fail-safe state.
+    Flashing,
 }
 
-impl<'a> Crossroad<'a> {
+impl Default for ControlMode {
+    fn default() -> ControlMode {
+        ControlMode::Actuated
+    }
+}
 
-    pub fn run_loop(&'a self, time: i32,
-                              state: &mut CrossroadState<'a>,
-                              sensor_shared_state: Arc<Mutex<SensorStates>>,
-                              out_tx: &Sender<String>)
-                           -> Option<CrossroadState<'a>> {
+impl ControlMode {
+    pub fn from_str(s: &str) -> Option<ControlMode> {
+        match s {
+            "actuated" => Some(ControlMode::Actuated),
+            "fixed"    => Some(ControlMode::FixedTime),
+            "flashing" => Some(ControlMode::Flashing),
+            _          => None,
+        }
+    }
+}
+
+/// Owns everything about one crossroad, so it can be cloned and moved to another thread.
+#[derive(Debug, Clone)]
+pub struct Crossroad {
+    pub topology: Topology,
+    pub primary_group: SignalGroup,
+    pub primary_traffic: Vec<ControlId>,
+    pub secondary_traffic: Vec<ControlId>,
+    pub priority_traffic: Vec<ControlId>,
+    pub directions: HashMap<Direction, XorConflictsGroup>,
+    pub conflicts: ConflictMatrix,
+    pub intergreen: IntergreenMatrix,
+    pub mode: ControlMode,
+    pub fixed_time: Option<FixedTimePlan>,
+    pub schedule: Option<Schedule>,
+    pub bus_priority: BusPriorityPolicy,
+    pub sensor_health: SensorHealthPolicy,
+    pub watchdog: WatchdogPolicy,
+    /// Where the server writes the event log of its sessions.
+    pub event_log: EventLogConfig,
+    /// Decides the groups of the actuated mode.
+    pub strategy: Box<ControlStrategy>,
+}
 
-        let ref mut sensor_states = *sensor_shared_state.lock().unwrap();
+impl Crossroad {
+
+    /// `time` is the tick number, `now` the clock time of that tick.
+    /// `signals` is what the lights show right now, indexed by light id.
+    /// `priorities` are the decisions of the bus priority policy for this tick, see `bus_priorities`.
+    pub fn run_loop(&self, time: i32,
+                           now: time::Tm,
+                           state: &mut CrossroadState,
+                           sensor_states: &SensorSnapshot,
+                           signals: &Vec<JsonState>,
+                           detectors: &Detectors,
+                           priorities: &Vec<BusPriority>,
+                           changes: &mut Vec<SignalChange>,
+                           clearance: &mut ClearanceState)
+                        -> Option<CrossroadState> {
+
+        let preemption = sensor_states.emergency(now).and_then(|target| {
+            let controls = self.preemption_controls(target);
+            if controls.is_empty() { None } else { Some((target, controls)) }
+        });
+
+        // An emergency vehicle stops the green group through the normal yellow and clearance.
+        if let Some((target, _)) = preemption {
+            match *state {
+                CrossroadState::PrimaryTraffic(ref group) | CrossroadState::SignalGroup(ref group) => {
+                    println!("========== STATE: Emergency vehicle for {:?}, stopping the green group", target);
+                    return Some(CrossroadState::ClearForPreemption(group.clone_with(SignalGroupState::ForceRed)));
+                },
+                _ => (),
+            }
+        }
 
         match *state {
 
             CrossroadState::AllRed => {
                 println!("========== STATE: AllRed");
 
-                if sensor_states.has_any_active_bus(&self.priority_traffic) {
-                    Some(CrossroadState::CreatePriorityGroup)
-                }
-                else if sensor_states.has_any_active(&self.secondary_traffic) {
-                    Some(CrossroadState::CreateSignalGroup)
+                if let Some((target, controls)) = preemption {
+                    println!("========== STATE: Preemption for {:?}", target);
+                    let group = SignalGroup::new(self.topology.lookup(&controls), true);
+                    Some(CrossroadState::Preemption { group: group, target: target, ending: false })
                 }
                 else {
-                    Some(CrossroadState::PrimaryTraffic(self.primary_group.clone()))
+                    let view = self.strategy_view(time, now, sensor_states, signals, detectors, clearance, priorities);
+                    match self.strategy.next_group(&view) {
+                        Decision::Wait => None,
+                        Decision::Primary => Some(CrossroadState::PrimaryTraffic(self.primary_group.clone())),
+                        Decision::Group(group) => Some(CrossroadState::SignalGroup(group)),
+                    }
                 }
             },
 
             CrossroadState::PrimaryTraffic(ref mut group) => {
                 print!("========== STATE: PrimaryTraffic ");
-                let group_is_green = group.controls_have_state(TrafficLightState::Green{start:0});
-                let any_sensor_active = sensor_states.has_any_active(&self.secondary_traffic) || sensor_states.has_any_active(&self.priority_traffic);
+                let stop = {
+                    let view = self.strategy_view(time, now, sensor_states, signals, detectors, clearance, priorities);
+                    self.strategy.stop_group(&view, group, true)
+                };
 
-                if group_is_green && any_sensor_active {
+                if stop {
                     println!(" :)))) Secondary traffic detected. Closing main traffic lanes");
                     Some(CrossroadState::SignalGroup(group.clone_with(SignalGroupState::ForceRed)))
                 }
                 else {
-                    match group.run_loop(time, out_tx, &sensor_states) {
+                    match group.run_loop(time, changes, &self.topology, sensor_states, detectors, clearance, &self.intergreen) {
                         Some(SignalGroupState::Done) => Some(CrossroadState::AllRed),
                         Some(v) => Some(CrossroadState::PrimaryTraffic(group.clone_with(v))),
                         None => None,
@@ -69,58 +184,159 @@ impl<'a> Crossroad<'a> {
                 }
             },
 
-            CrossroadState::CreatePriorityGroup => {
-                println!("========== STATE: CreatePriorityGroup <-------------------------??");
+            CrossroadState::SignalGroup(ref mut group) => {
+                print!("========== STATE: SignalGroup ");
+
+                for c in &mut group.controls {
+                    c.extend_for_bus = priorities.iter().any(|p| p.control == c.control && p.decision == PriorityDecision::GreenExtension);
+                }
+
+                let stop = {
+                    let view = self.strategy_view(time, now, sensor_states, signals, detectors, clearance, priorities);
+                    self.strategy.stop_group(&view, group, false)
+                };
 
-                let mut bus_controls = vec![];
+                if stop {
+                    println!(" Stopped early by the {} strategy", self.strategy.name());
+                    return Some(CrossroadState::SignalGroup(group.clone_with(SignalGroupState::ForceRed)));
+                }
 
-                if let Some(&bcontrol) = self.traffic_controls.get(15) {
-                    if sensor_states.has_active_bus(bcontrol) { bus_controls.push(bcontrol); }
+                match group.run_loop(time, changes, &self.topology, sensor_states, detectors, clearance, &self.intergreen) {
+                    Some(SignalGroupState::Done) => Some(CrossroadState::AllRed),
+                    Some(v) => Some(CrossroadState::SignalGroup(group.clone_with(v))),
+                    None => None,
                 }
-                if let Some(&bcontrol) = self.traffic_controls.get(16) {
-                    if sensor_states.has_active_bus(bcontrol) { bus_controls.push(bcontrol); }
+            },
+
+            CrossroadState::ClearForPreemption(ref mut group) => {
+                print!("========== STATE: ClearForPreemption ");
+                match group.run_loop(time, changes, &self.topology, sensor_states, detectors, clearance, &self.intergreen) {
+                    Some(SignalGroupState::Done) => Some(CrossroadState::AllRed),
+                    Some(v) => Some(CrossroadState::ClearForPreemption(group.clone_with(v))),
+                    None => None,
                 }
+            },
 
-                if bus_controls.len() == 0 {
-                    println!("========== STATE: REDIRECT:::: ");
-                    Some(CrossroadState::CreateSignalGroup)
+            CrossroadState::Preemption { ref mut group, target, ending } => {
+                print!("========== STATE: Preemption ");
+
+                // Cleared, timed out or replaced by a request for another approach.
+                if !ending && preemption.map(|(requested, _)| requested) != Some(target) {
+                    println!("request for {:?} ended, stopping", target);
+                    Some(CrossroadState::Preemption { group: group.clone_with(SignalGroupState::ForceRed), target: target, ending: true })
                 }
                 else {
-                    let group = SignalGroup::new_bus(bus_controls, false);
-                    Some(CrossroadState::SignalGroup(group))
+                    match group.run_loop(time, changes, &self.topology, sensor_states, detectors, clearance, &self.intergreen) {
+                        Some(SignalGroupState::Done) => Some(CrossroadState::AllRed),
+                        Some(v) => Some(CrossroadState::Preemption { group: group.clone_with(v), target: target, ending: ending }),
+                        None => None,
+                    }
                 }
             },
+        }
+    }
 
-            CrossroadState::CreateSignalGroup => {
-                println!("========== STATE: CreateSignalGroup");
-                let group = self.generate_signalgroup(&sensor_states);
-                Some(CrossroadState::SignalGroup(group))
-            },
+    fn strategy_view<'a>(&'a self, time: i32,
+                                   now: time::Tm,
+                                   sensor_states: &'a SensorStates,
+                                   signals: &'a Vec<JsonState>,
+                                   detectors: &'a Detectors,
+                                   clearance: &'a ClearanceState,
+                                   priorities: &'a Vec<BusPriority>)
+                                -> StrategyView<'a> {
+        StrategyView { time: time, now: now, crossroad: self, sensors: sensor_states, signals: signals, detectors: detectors, clearance: clearance, priorities: priorities }
+    }
 
-            CrossroadState::SignalGroup(ref mut group) => {
-                print!("========== STATE: SignalGroup ");
-                match group.run_loop(time, out_tx, &sensor_states) {
-                    Some(SignalGroupState::Done) => Some(CrossroadState::AllRed),
-                    Some(v) => Some(CrossroadState::SignalGroup(group.clone_with(v))),
-                    None => None,
-                }
+    /// The policy decision for every bus lane with a bus, based on how long the conflicting red traffic waited.
+    pub fn bus_priorities(&self, time: i32, now: time::Tm, state: &CrossroadState, sensor_states: &SensorStates, clearance: &ClearanceState) -> Vec<BusPriority> {
+        let green = state.green_controls();
+        let mut priorities = vec![];
+
+        for bus in self.topology.lookup(&self.priority_traffic) {
+            let first_bus = bus.get_ids().into_iter().find(|&id| sensor_states.bus_line(SensorId(id)).is_some());
+
+            if let Some(id) = first_bus {
+                let conflict_wait = self.topology.controls().iter()
+                    .filter(|&c| !green.contains(&c.id) && self.conflicts.controls_conflict(bus, c))
+                    .filter_map(|c| self.waited(c, time, now, sensor_states, clearance))
+                    .max()
+                    .unwrap_or(0);
+                let delay = sensor_states.bus_delay(SensorId(id)).unwrap_or(0);
+
+                priorities.push(BusPriority {
+                    control: bus.id,
+                    line: sensor_states.bus_line(SensorId(id)).unwrap_or(0),
+                    delay: delay,
+                    conflict_wait: conflict_wait,
+                    decision: self.bus_priority.decide(green.contains(&bus.id), delay, conflict_wait),
+                });
+            }
+        }
+        priorities
+    }
+
+    /// The aspect for the route of the first bus at the control, from the route table of the topology.
+    pub fn bus_aspect(&self, control: &Control, sensor_states: &SensorStates) -> JsonState {
+        control.get_ids().iter()
+            .filter_map(|&id| sensor_states.bus_line(SensorId(id)))
+            .next()
+            .map(|line| self.topology.bus_movement(line).aspect())
+            .unwrap_or(JsonState::BusRechtdoorRechtsaf)
+    }
+
+    /// The lights an emergency vehicle gets: the light itself, or the vehicle lanes of the approach
+    /// without pedestrians, cyclists and bus lanes. Lanes of one approach that conflict are left out.
+    pub fn preemption_controls(&self, target: EmergencyTarget) -> Vec<ControlId> {
+        let candidates: Vec<ControlId> = match target {
+            EmergencyTarget::Light(light) => self.topology.control_id_of(light).into_iter().collect(),
+            EmergencyTarget::Approach(direction) => self.topology.controls().iter()
+                .filter(|c| c.direction() == direction && *c.traffic_type() != Type::Rest && !self.priority_traffic.contains(&c.id))
+                .map(|c| c.id)
+                .collect(),
+        };
+
+        let mut controls: Vec<ControlId> = vec![];
+        for id in candidates {
+            let control = self.topology.control(id);
+            if !controls.iter().any(|&other| self.conflicts.controls_conflict(control, self.topology.control(other))) {
+                controls.push(id);
             }
         }
+        controls
     }
 
-    pub fn generate_signalgroup(&'a self, sensor_states: &SensorStates) -> SignalGroup<'a> {
+    /// The controls in `overdue` are served first, regardless of the waiting time of the other controls.
+    /// A control with a queue weighs its waiting time once for every waiting vehicle.
+    /// `first` starts the group instead of the longest waiting sensor when it is active.
+    /// None when no sensor of a control is active.
+    pub fn generate_signalgroup(&self, time: i32, now: time::Tm, sensor_states: &SensorStates, detectors: &Detectors, overdue: &Vec<ControlId>, first: Option<ControlId>) -> Option<SignalGroup> {
         let start = time::PreciseTime::now();
         //
         //
-        let (longest_waiting, other_active_sensors) = sensor_states.active_and_longest_waiting().expect("massive boner get_sensor_control");
-
-        let start_control = self.get_sensor_control(longest_waiting).expect("generate_signalgroup get_sensor_control");
-        let active_controls = self.get_sensor_controls(&other_active_sensors);
+        let (longest_waiting, other_active_sensors) = match sensor_states.active_and_longest_waiting() {
+            Some(active) => active,
+            None => return None,
+        };
+
+        let mut start_control = match self.get_sensor_control(longest_waiting) {
+            Some(control) => control,
+            None => return None,
+        };
+        let mut active_controls = self.get_sensor_controls(&other_active_sensors);
+
+        if let Some(index) = first.and_then(|first| active_controls.iter().position(|c| c.control == first)) {
+            let overdue_control = active_controls.remove(index);
+            if !active_controls.iter().any(|c| c.control == start_control.control) {
+                active_controls.push(start_control);
+            }
+            start_control = overdue_control;
+        }
+        active_controls.retain(|c| c.control != start_control.control);
 
         print!("Start sensor:\n  {:?}\nActive sensors:\n  ", start_control);
         for c in &active_controls { print!("{:?}\n  ", c) };
 
-        let compatible_controls = self.choose_compatible(&start_control, &active_controls);
+        let compatible_controls = self.choose_compatible(&start_control, &active_controls, time, now, detectors, overdue);
         let signal_group = self.fill_signal_group(&start_control, &compatible_controls);
 
         println!("\nFinal group\n {:?}", signal_group);
@@ -129,114 +345,429 @@ impl<'a> Crossroad<'a> {
         print!("\nCalculation done in: {:?} milliseconds", start.to(time::PreciseTime::now()).num_milliseconds());
         print!("\n");
 
-        signal_group
+        Some(signal_group)
     }
 
-    fn choose_compatible<'b>(&'a self, control: &ControlSensor<'a, 'b>,
-                                       choices: &Vec<ControlSensor<'a, 'b>>)
-                                    -> Option<Vec<ControlSensor<'a, 'b>>> {
+    fn choose_compatible(&self, control: &ControlSensor,
+                                choices: &Vec<ControlSensor>,
+                                time: i32,
+                                until_now: time::Tm,
+                                detectors: &Detectors,
+                                overdue: &Vec<ControlId>)
+                             -> Option<Vec<ControlSensor>> {
 
-        let until_now = time::now();
-        let non_conflicting = control.filter_conflicting(choices);
-        let rest_choices = split_by_directions(&non_conflicting);
+        let non_conflicting = control.filter_conflicting(choices, &self.topology);
 
         println!("\nAfter conflicting filter: {:?}", control.conflicting_ids);
         for v in &non_conflicting { println!("  {:?}", v) };
-        println!("\nRetained direction vecs");
-        for v in &rest_choices { println!("{:?}", v) };
-
-        let mut path_results = vec![];
-        for control_path in cartesian::all_possibilities(rest_choices) {
-            let mut conflicts = control.conflicting_ids.clone();
-            let mut path = vec![];
-            let mut acc = time::Duration::zero();
-
-            //print!("\nchecking control path: ");
-            //for c in &control_path { print!("{:?} ", c.inner.get_ids()) };
-
-            for current_control in &control_path {
-                match current_control.inner.contains_one_of(&conflicts) {
-                    true  => { print!("\n{:?} is conflicting, ignore", current_control.inner.get_ids()) },
-                    false => {
-                        acc = acc + current_control.time_waiting(until_now);
-                        conflicts.extend_from_slice(current_control.conflicting_ids.as_slice());
-                        path.push(current_control.clone());
-                        //print!("\n{:?} added. +{:?} seconds. New conflicts: {:?} ", current_control.inner.get_ids(), acc.num_seconds(), conflicts)
-                    }
-                }
-            }
 
-            path_results.push((path.clone(), acc));
-         };
-
-         println!("\n\nPath Results:");
-         for &(ref path, count) in &path_results {
-             print!("Option: Combined waiting time = {:?} seconds for the traffic lights path: ", count.num_seconds());
-             for c in path { print!("{:?} ", c.inner.get_ids()) };
-             print!("\n");
-         }
-         
-         path_results.iter()
-            .max_by_key(|&&(ref path, count)| count)
-            .map(|&(ref path, count)| {
-                path.iter().map(|&c| c.clone()).collect()
-            })
+        let weights = non_conflicting.iter().map(|c| {
+            let queue = ::std::cmp::max(1, detectors.queue(self.topology.control(c.control), time)) as i64;
+            let weight = solver::waiting_weight(c.time_waiting(until_now)) * queue;
+            if overdue.contains(&c.control) { weight + solver::OVERDUE_WEIGHT } else { weight }
+        }).collect();
+        let chosen = solver::max_weight_compatible(weights, |a, b| {
+            self.conflicts.controls_conflict(self.topology.control(non_conflicting[a].control), self.topology.control(non_conflicting[b].control))
+        });
+
+        let path: Vec<ControlSensor> = chosen.iter().map(|&i| non_conflicting[i].clone()).collect();
+        let waiting = path.iter().fold(time::Duration::zero(), |acc, c| acc + c.time_waiting(until_now));
+
+        print!("\nOption: Combined waiting time = {:?} seconds for the traffic lights path: ", waiting.num_seconds());
+        for c in &path { print!("{:?} ", self.topology.control(c.control).get_ids()) };
+        print!("\n");
+
+        Some(path)
     }
 
-    fn fill_signal_group<'b>(&'a self, control: &ControlSensor<'a, 'b>,
-                                       compatibles: &Option<Vec<ControlSensor<'a, 'b>>>)
-                                   ->  SignalGroup<'a> {
+    fn fill_signal_group(&self, control: &ControlSensor,
+                                compatibles: &Option<Vec<ControlSensor>>)
+                            ->  SignalGroup {
 
-        let mut traffic_controls = vec![control.inner];
+        let mut traffic_controls = vec![control.control];
 
         if let &Some(ref compatible_controls) = compatibles {
-            let inners: Vec<_> = compatible_controls.iter().map(|c| c.inner).collect();
-            traffic_controls.extend_from_slice(inners.as_slice());
+            traffic_controls.extend(compatible_controls.iter().map(|c| c.control));
         }
 
-        SignalGroup::new(traffic_controls, false)
+        SignalGroup::new(self.topology.lookup(&traffic_controls), false)
     }
 
-    pub fn get_sensor_control<'b>(&'a self, sensor: &'b Sensor) -> Option<ControlSensor<'a, 'b>> {
-        self.traffic_controls.get(sensor.id).map(|control| {
+    /// Seconds the traffic or bus at a red control has been waiting. Waiting starts when the sensor became
+    /// occupied, or when the control was last green if it stayed occupied. Buses only count on the bus lanes.
+    pub fn waited(&self, control: &Control, time: i32, now: time::Tm, sensor_states: &SensorStates, clearance: &ClearanceState) -> Option<i32> {
+        let bus_lane = self.priority_traffic.contains(&control.id);
+
+        control.get_ids().iter().flat_map(|&id| {
+            let mut sensors = vec![sensor_states.sensor(SensorId(id))];
+            if bus_lane {
+                sensors.push(sensor_states.bus_sensor(SensorId(id)));
+            }
+            sensors.into_iter()
+                .filter_map(|sensor| sensor.and_then(|sensor| if sensor.bezet { sensor.waiting_since } else { None }))
+                .map(|since| {
+                    let requested = time - (now - since).num_seconds() as i32;
+                    time - ::std::cmp::max(requested, clearance.green_ended(id).unwrap_or(requested))
+                })
+                .collect::<Vec<i32>>()
+        }).max()
+    }
+
+    /// The control that comes closest to its `max_waiting`, the first one in the topology on a tie.
+    /// Bus lanes are left out, the bus group serves them before they are overdue.
+    pub fn most_urgent(&self, time: i32, now: time::Tm, sensor_states: &SensorStates, clearance: &ClearanceState) -> Option<ControlId> {
+        self.topology.controls().iter()
+            .filter(|control| !self.priority_traffic.contains(&control.id))
+            .filter_map(|control| self.waited(control, time, now, sensor_states, clearance).map(|waited| (waited - control.timings().max_waiting, control.id)))
+            .fold(None, |urgent: Option<(i32, ControlId)>, (left, id)| match urgent {
+                Some((most, _)) if most >= left => urgent,
+                _ => Some((left, id)),
+            })
+            .map(|(_, id)| id)
+    }
+
+    /// Controls with traffic or a bus that waited at least their `max_waiting`, longest overdue first.
+    /// Only meaningful while every light is red.
+    pub fn overdue_controls(&self, time: i32, now: time::Tm, sensor_states: &SensorStates, clearance: &ClearanceState) -> Vec<ControlId> {
+        let mut overdue: Vec<(i32, ControlId)> = vec![];
+
+        for control in self.topology.controls() {
+            if let Some(waited) = self.waited(control, time, now, sensor_states, clearance) {
+                if waited >= control.timings().max_waiting {
+                    overdue.push((waited - control.timings().max_waiting, control.id));
+                }
+            }
+        }
+
+        overdue.sort_by(|a, b| b.0.cmp(&a.0));
+        overdue.into_iter().map(|(_, id)| id).collect()
+    }
+
+    pub fn get_sensor_control(&self, sensor: &Sensor) -> Option<ControlSensor> {
+        self.topology.sensor_control(sensor.id).map(|control| {
             ControlSensor::new(control, sensor, self.conflicts_for(control))
         })
     }
 
-    pub fn get_sensor_controls<'b>(&'a self, sensors: &Vec<&'b Sensor>) -> Vec<ControlSensor<'a, 'b>> {
-        sensors.iter().filter_map(|sensor| {
-           self.traffic_controls.get(sensor.id).map(|control| {
-               ControlSensor::new(control, sensor, self.conflicts_for(control))
-           })
-        }).collect()
+    /// One ControlSensor per control, a control with several active sensors keeps the longest waiting one.
+    pub fn get_sensor_controls(&self, sensors: &Vec<&Sensor>) -> Vec<ControlSensor> {
+        let mut controls: Vec<ControlSensor> = vec![];
+
+        for sensor in sensors {
+            if let Some(control) = self.get_sensor_control(sensor) {
+                match controls.iter().position(|c| c.control == control.control) {
+                    Some(index) => if control.sensor.last_update < controls[index].sensor.last_update {
+                        controls[index] = control
+                    },
+                    None => controls.push(control),
+                }
+            }
+        }
+
+        controls
+    }
+
+    pub fn conflicts_for(&self, control: &Control) -> Vec<usize> {
+        self.conflicts.conflicts_for(control)
+    }
+
+    pub fn conflict_entry(&self, control: &Control) -> Option<&ConflictEntry> {
+        self.directions
+            .get(&control.direction())
+            .and_then(|xor| xor.entry_for(control.id))
+    }
+
+    pub fn set_all(&self, state: JsonState) -> Vec<SignalChange> {
+        self.topology.controls().iter().flat_map(|c| c.signal_changes(state)).collect()
     }
+}
+
+
+#[cfg(test)]
+fn signal_group_ids(active: Vec<usize>) -> Vec<Vec<usize>> {
+    let crossroad = ::default_crossroad::default_crossroad();
+
+    // Each sensor becomes active one tick after the previous one, so the first one waits longest.
+    let clock = SimulatedClock::new();
+    let mut sensor_states = SensorStates::new();
+    for &id in &active {
+        sensor_states.update(&vec![Baan { id: id, bezet: true }], clock.now());
+        clock.tick();
+    }
+
+    let group = crossroad.generate_signalgroup(0, clock.now(), &sensor_states, &Detectors::new(), &vec![], None).unwrap();
+    let mut ids = group.ids(&crossroad.topology);
+    ids.sort();
+    ids
+}
+
+#[test]
+fn same_approach_controls_share_a_signal_group() {
+    // Left, straight and right from the north approach don't conflict with each other.
+    assert_eq!(signal_group_ids(vec![1, 6, 11]), vec![vec![1], vec![6], vec![11]]);
 
-    pub fn conflicts_for(&'a self, control: &Control<'a>) -> Vec<usize> {
-        //println!("Getting conflicts for: {:?}", control);
-        match self.directions
-                  .get(&control.direction())
-                  .and_then(|xor| xor.get_conflicts_for(control)) {
-            Some(conflicts) => conflicts,
-            None => {
-                println!("!!!!!!!!!!!!!--------------------> no conflicts for id(s): {:?}", control.get_ids());
-                vec![]
+    // East approach, 8 conflicts with the road and the left turn so it has to wait.
+    assert_eq!(signal_group_ids(vec![2, 7, 8, 12]), vec![vec![2, 3], vec![7], vec![12]]);
+}
+
+#[test]
+fn crossing_is_not_combined_with_its_own_approach() {
+    // 5 and 14 may go together, the west crossing conflicts with both. 5 is active first, so it waits longest.
+    let ids = signal_group_ids(vec![5, 14, 17]);
+    assert!(ids.contains(&vec![5]) && ids.contains(&vec![14]));
+    assert!(!ids.contains(&vec![17, 23, 25, 24, 26]));
+}
+
+/// Drives the default crossroad with cars (and buses) arriving as `arrivals` says, a car leaves two ticks after its
+/// light turned green. Every tick the client sends the state of every sensor again, which keeps moving `last_update`.
+/// Returns the longest time any light waited for green, from the arrival or from the end of its last green.
+#[cfg(test)]
+fn longest_waits<F>(ticks: i32, arrivals: F) -> Vec<i32> where F: Fn(i32) -> (Vec<usize>, Vec<usize>) {
+    let mut controller = Controller::new(::default_crossroad::default_crossroad());
+
+    let clock = SimulatedClock::new();
+    let mut sensors = SensorStates::new();
+    let mut bezet = vec![false; BAAN_COUNT];
+    let mut bus_bezet = vec![false; BAAN_COUNT];
+    let mut green_since = vec![None; BAAN_COUNT];
+    let mut waiting_since = vec![None; BAAN_COUNT];
+    let mut longest = vec![0; BAAN_COUNT];
+
+    for _ in 0..ticks {
+        let time = clock.tick();
+
+        let (cars, buses) = arrivals(time);
+        for id in cars { bezet[id] = true; }
+        for id in buses { bus_bezet[id] = true; }
+        for id in 0..BAAN_COUNT {
+            if green_since[id].map_or(false, |since| time >= since + 2) {
+                bezet[id] = false;
+                bus_bezet[id] = false;
+            }
+            if (bezet[id] || bus_bezet[id]) && green_since[id].is_none() && waiting_since[id].is_none() {
+                waiting_since[id] = Some(time);
+            }
+        }
+
+        sensors.update(&(0..BAAN_COUNT).map(|id| Baan { id: id, bezet: bezet[id] }).collect(), clock.now());
+        sensors.update_bussen(&(0..BAAN_COUNT).map(|id| BusBaan { id: id, eerstvolgendelijn: 1, vertraging: 0, bezet: bus_bezet[id] }).collect(), clock.now());
+
+        for change in controller.step(clock.now(), &sensors) {
+            match change.state {
+                JsonState::Rood | JsonState::Geel => green_since[change.id] = None,
+                _ => {
+                    if let Some(since) = waiting_since[change.id].take() {
+                        longest[change.id] = ::std::cmp::max(longest[change.id], time - since);
+                    }
+                    green_since[change.id] = Some(time);
+                },
             }
         }
     }
 
-    pub fn traffic_controls_unique(&'a self) -> HashSet<&'a Control<'a>> {
-        self.traffic_controls.clone().into_iter().collect()
+    // Still waiting at the end counts too.
+    for id in 0..BAAN_COUNT {
+        if let Some(since) = waiting_since[id] {
+            longest[id] = ::std::cmp::max(longest[id], ticks - since);
+        }
     }
+    longest
+}
+
+#[cfg(test)]
+fn assert_waits_bounded(longest: Vec<i32>) {
+    let crossroad = ::default_crossroad::default_crossroad();
+    let topology = &crossroad.topology;
+
+    // A light is served before it is overdue or by the next group: it waits at most for the group that
+    // is green to end and clear. Without the bound these patterns keep lights waiting for the whole run.
+    let group = topology.controls().iter().map(|c| c.timings().max_green + c.timings().yellow).max().unwrap();
+    let intergreen = (0..BAAN_COUNT).flat_map(|from| (0..BAAN_COUNT).map(move |to| (from, to))).map(|(from, to)| crossroad.intergreen.get(from, to)).max().unwrap();
+
+    for (id, &waited) in longest.iter().enumerate() {
+        let bound = topology.control_of(LightId(id)).unwrap().timings().max_waiting + group + intergreen;
+        assert!(waited <= bound, "light {} waited {} seconds, more than {}", id, waited, bound);
+    }
+}
+
+#[test]
+fn buses_every_tick_do_not_starve_the_side_streets() {
+    // Buses keep arriving at both bus lanes, one car waits at each of the conflicting approaches.
+    let longest = longest_waits(600, |time| match time {
+        5 => (vec![1, 5, 8, 12, 13, 17], vec![15, 16]),
+        _ => (vec![], vec![15, 16]),
+    });
+    assert_waits_bounded(longest);
+}
+
+#[test]
+fn heavy_traffic_does_not_starve_a_lonely_request() {
+    // Every other light has traffic all the time, the pedestrians at 28 ask once.
+    let longest = longest_waits(900, |time| {
+        let cars: Vec<usize> = (0..BAAN_COUNT).filter(|&id| id != 28 && (time as usize + id) % 3 == 0).collect();
+        let lonely = if time == 20 { vec![28] } else { vec![] };
+        (cars.into_iter().chain(lonely).collect(), vec![])
+    });
+    assert_waits_bounded(longest);
+}
+
+#[test]
+fn alternating_traffic_does_not_starve_anyone() {
+    // Two conflicting streams take turns arriving just before the other one would be served.
+    let longest = longest_waits(900, |time| {
+        let cars = match (time / 7) % 2 {
+            0 => vec![2, 4, 9, 11, 13],
+            _ => vec![1, 5, 8, 12, 14],
+        };
+        (cars.into_iter().chain(if time % 50 == 0 { vec![17, 20, 22] } else { vec![] }).collect(), if time % 13 == 0 { vec![15] } else { vec![] })
+    });
+    assert_waits_bounded(longest);
+}
 
-    pub fn send_all(&'a self, out_tx: &Sender<String>, state: JsonState) {
-        for control in &self.traffic_controls_unique() {
-            control.send_unsafe(out_tx, state)
+#[cfg(test)]
+fn hulpdienst(richting: Direction, bezet: bool) -> Vec<Hulpdienst> {
+    vec![Hulpdienst { id: None, richting: Some(richting), bezet: bezet }]
+}
+
+#[test]
+fn emergency_vehicle_preempts_the_green_group() {
+    use audit::AuditEvent;
+    use safety_monitor::SafetyMonitor;
+
+    let crossroad = ::default_crossroad::default_crossroad();
+    let east: Vec<usize> = crossroad.preemption_controls(EmergencyTarget::Approach(Direction::East)).iter()
+        .flat_map(|&id| crossroad.topology.control(id).get_ids())
+        .collect();
+
+    let mut controller = Controller::new(crossroad);
+    let mut monitor = SafetyMonitor::new(controller.crossroad());
+    let clock = SimulatedClock::new();
+    let mut sensors = SensorStates::new();
+    let mut status = vec![JsonState::Rood; BAAN_COUNT];
+
+    let mut step = |sensors: &SensorStates, status: &mut Vec<JsonState>| {
+        let time = clock.tick();
+        let changes = controller.step(clock.now(), sensors);
+        monitor.check(time, clock.now(), &changes).unwrap();
+        for change in changes { status[change.id] = change.state; }
+        (time, clock.now())
+    };
+
+    // The primary lanes are green, 4 is one of them and doesn't belong to the east approach.
+    for _ in 0..10 { step(&sensors, &mut status); }
+    assert_eq!(status[4], JsonState::Groen);
+
+    // A bus is waiting too, the emergency vehicle still goes first.
+    let (_, now) = step(&sensors, &mut status);
+    sensors.update_bussen(&vec![BusBaan { id: 15, eerstvolgendelijn: 1, vertraging: 0, bezet: true }], now);
+    sensors.update_hulpdiensten(&hulpdienst(Direction::East, true), now);
+
+    // The primary group goes through yellow to red first, then only the east lanes turn green.
+    let mut cleared = false;
+    for _ in 0..60 {
+        step(&sensors, &mut status);
+        let green: Vec<usize> = (0..BAAN_COUNT).filter(|&id| status[id] != JsonState::Rood).collect();
+        cleared |= green.is_empty();
+        if cleared {
+            assert!(green.iter().all(|id| east.contains(id)), "{:?} are not red during the preemption", green);
         }
     }
+    assert!(cleared && east.iter().all(|&id| status[id] == JsonState::Groen));
+
+    // Cleared: the east lanes stop and the crossroad continues, with the bus.
+    let (_, now) = step(&sensors, &mut status);
+    sensors.update_hulpdiensten(&hulpdienst(Direction::East, false), now);
+    let mut bus_green = false;
+    for _ in 0..40 {
+        step(&sensors, &mut status);
+        bus_green |= status[15] == JsonState::BusRechtdoorRechtsaf;
+    }
+    assert!(bus_green);
 
-    pub fn send_all_bulk(&'a self, out_tx: &Sender<String>, state: JsonState) {
-        let all_objs = self.traffic_controls_unique().iter().flat_map(|c| c.json_objs(state)).collect();
-        let json_str = out_compat_json_str(all_objs);
-        out_tx.send(json_str).unwrap();
+    let events: Vec<AuditEvent> = controller.audit().entries().iter().map(|e| e.event.clone()).collect();
+    let target = EmergencyTarget::Approach(Direction::East);
+    assert_eq!(events, vec![AuditEvent::PreemptionStarted { target: target }, AuditEvent::PreemptionEnded { target: target, timed_out: false }]);
+}
+
+#[test]
+fn emergency_request_times_out() {
+    use audit::AuditEvent;
+
+    let mut controller = Controller::new(::default_crossroad::default_crossroad());
+
+    let clock = SimulatedClock::new();
+    let mut sensors = SensorStates::new();
+    sensors.update_hulpdiensten(&vec![Hulpdienst { id: Some(13), richting: None, bezet: true }], clock.now());
+
+    let mut green_13 = vec![];
+    for _ in 0..(PREEMPTION_TIMEOUT as i32 + 30) {
+        let time = clock.tick();
+        for change in controller.step(clock.now(), &sensors) {
+            if change.id == 13 && change.state == JsonState::Groen { green_13.push(time); }
+        }
     }
+
+    // Green once, and not again while the request is still there but timed out.
+    assert_eq!(green_13.len(), 1);
+    let events: Vec<AuditEvent> = controller.audit().entries().iter().map(|e| e.event.clone()).collect();
+    assert_eq!(events.last(), Some(&AuditEvent::PreemptionEnded { target: EmergencyTarget::Light(LightId(13)), timed_out: true }));
+}
+
+#[test]
+fn bus_lanes_show_the_movement_of_their_line() {
+    use config::CrossroadConfig;
+
+    let json = include_str!("../default_crossroad.json").replace("\"priority\":  [15, 16],", r#""priority":  [15, 16],
+    "bus_routes": [{ "lines": [1], "movement": "Straight" }, { "lines": [3], "movement": "Right" }],"#);
+    let config = CrossroadConfig::from_str(&json).unwrap();
+    let crossroad = config.create_crossroad(config.create_traffic_controls(config.create_traffic_lights())).unwrap();
+
+    let aspects = |bus_15: i32, bus_16: i32| {
+        let mut controller = Controller::new(crossroad.clone());
+        let clock = SimulatedClock::new();
+        let mut sensors = SensorStates::new();
+        sensors.update_bussen(&vec![BusBaan { id: 15, eerstvolgendelijn: bus_15, vertraging: 0, bezet: true }, BusBaan { id: 16, eerstvolgendelijn: bus_16, vertraging: 0, bezet: true }], clock.now());
+
+        let mut aspects = vec![None; BAAN_COUNT];
+        for _ in 0..30 {
+            clock.tick();
+            for change in controller.step(clock.now(), &sensors) {
+                if change.state != JsonState::Rood && change.state != JsonState::Geel { aspects[change.id] = Some(change.state); }
+            }
+        }
+        (aspects[15], aspects[16])
+    };
+
+    assert_eq!(aspects(1, 3), (Some(JsonState::BusRechtdoor), Some(JsonState::BusRechtsaf)));
+    // A line that isn't in the table may go both ways.
+    assert_eq!(aspects(3, 7), (Some(JsonState::BusRechtsaf), Some(JsonState::BusRechtdoorRechtsaf)));
+}
+
+#[test]
+fn late_bus_goes_before_traffic_that_waited_a_while() {
+    let crossroad = ::default_crossroad::default_crossroad();
+
+    // A car at 6 waited 30 seconds when a bus arrives at the conflicting 15.
+    let first_green = |delay: i32| {
+        let mut controller = Controller::new(crossroad.clone());
+        let clock = SimulatedClock::new();
+        let mut sensors = SensorStates::new();
+        sensors.update(&vec![Baan { id: 6, bezet: true }], clock.now());
+        clock.advance(::time::Duration::seconds(30));
+        sensors.update_bussen(&vec![BusBaan { id: 15, eerstvolgendelijn: 1, vertraging: delay, bezet: true }], clock.now());
+
+        for _ in 0..60 {
+            clock.tick();
+            for change in controller.step(clock.now(), &sensors) {
+                if (change.id == 6 || change.id == 15) && change.state != JsonState::Rood && change.state != JsonState::Geel {
+                    let decisions: Vec<PriorityDecision> = controller.bus_priority_log().records().iter().map(|r| r.priority.decision).collect();
+                    return (change.id, decisions);
+                }
+            }
+        }
+        panic!("neither 6 nor 15 turned green");
+    };
+
+    assert_eq!(first_green(0), (6, vec![PriorityDecision::NoPriority]));
+    assert_eq!(first_green(120), (15, vec![PriorityDecision::EarlyGreen]));
 }
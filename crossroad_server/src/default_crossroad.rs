@@ -3,6 +3,15 @@ use std::collections::HashMap;
 use traffic_controls::*;
 use signal_group::*;
 use crossroad::*;
+use conflict_matrix::ConflictMatrix;
+use intergreen::*;
+use config::{FixedTimeConfig, StageConfig};
+use fixed_time::FixedTimePlan;
+use bus_priority::BusPriorityPolicy;
+use strategy::DefaultStrategy;
+use sensor_health::SensorHealthPolicy;
+use watchdog::WatchdogPolicy;
+use event_log::EventLogConfig;
 
 
 pub fn create_traffic_lights() -> TrafficLightsBuilder {
@@ -15,8 +24,8 @@ pub fn create_traffic_lights() -> TrafficLightsBuilder {
          .set_type_range(Type::Rest, 17, 34)
 }
 
-pub fn create_traffic_controls(traffic_lights: &TrafficLightsBuilder) -> Vec<Control> {
-    ControlsBuilder::new(&traffic_lights)
+pub fn create_traffic_controls(traffic_lights: TrafficLightsBuilder) -> Topology {
+    ControlsBuilder::new(traffic_lights)
          .add_group(vec![ 2, 3], Direction::East, Type::Primary)
          .add_group(vec![ 9,10], Direction::West, Type::Primary)
 
@@ -31,12 +40,14 @@ pub fn create_traffic_controls(traffic_lights: &TrafficLightsBuilder) -> Vec<Con
          .create_controls()
 }
 
-pub fn create_crossroad<'a>(traffic_controls: &'a Vec<Control<'a>>) -> Crossroad<'a> {
+pub fn create_crossroad(topology: Topology) -> Crossroad {
 
-    let indexed_controls = index_controls(&traffic_controls);
+    let indexed_controls = topology.indexed_controls();
 
     let road_east_2_3                 = indexed_controls[ 2];
     let road_west_9_10                = indexed_controls[ 9];
+    let bus_east                      = indexed_controls[15];
+    let bus_west                      = indexed_controls[16];
     let west_bicycle_and_pedestrain   = indexed_controls[17];
     //let west_inner                    = indexed_controls[24];
     let south_bicycle_and_pedestrain  = indexed_controls[19];
@@ -44,6 +55,8 @@ pub fn create_crossroad<'a>(traffic_controls: &'a Vec<Control<'a>>) -> Crossroad
     let east_bicycle_and_pedestrain   = indexed_controls[21];
     //let east_inner                    = indexed_controls[32];
 
+    // Controls only conflict with what they list, lanes of the same approach may be green together.
+    // The crossings conflict with every lane on their own approach.
     let mut directions = HashMap::new();
     directions.insert(Direction::North,
         XorConflictsGroup::new("noord".to_string(), vec![
@@ -51,19 +64,21 @@ pub fn create_crossroad<'a>(traffic_controls: &'a Vec<Control<'a>>) -> Crossroad
                 east_bicycle_and_pedestrain
             ]),
             indexed_controls[6].conflicting_with(vec![
-                &indexed_controls[8], road_west_9_10,    // noord A
-                &indexed_controls[12],                   // noord B
+                indexed_controls[8], road_west_9_10,    // noord A
+                indexed_controls[12],                   // noord B
                 road_east_2_3,
 
-                south_bicycle_and_pedestrain
+                south_bicycle_and_pedestrain,
+                bus_east, bus_west
             ]),
             indexed_controls[1].conflicting_with(vec![
-                &indexed_controls[8], road_west_9_10,    // noord A
-                &indexed_controls[12],                   // noord B
-                &indexed_controls[13],
-                &indexed_controls[5],
+                indexed_controls[8], road_west_9_10,    // noord A
+                indexed_controls[12],                   // noord B
+                indexed_controls[13],
+                indexed_controls[5],
 
-                west_bicycle_and_pedestrain
+                west_bicycle_and_pedestrain,
+                bus_west
             ]),
         ])
     );
@@ -71,25 +86,41 @@ pub fn create_crossroad<'a>(traffic_controls: &'a Vec<Control<'a>>) -> Crossroad
     directions.insert(Direction::East,
         XorConflictsGroup::new("oost".to_string(), vec![
             indexed_controls[7].conflicting_with(vec![
-                south_bicycle_and_pedestrain
+                south_bicycle_and_pedestrain,
+                east_bicycle_and_pedestrain
             ]),
             road_east_2_3.conflicting_with(vec![
-                &indexed_controls[5], &indexed_controls[6], // oost A
-                &indexed_controls[8],                       // oost B
-                &indexed_controls[13],
+                indexed_controls[5], indexed_controls[6], // oost A
+                indexed_controls[8],                       // oost B
+                indexed_controls[13],
 
-                west_bicycle_and_pedestrain
+                west_bicycle_and_pedestrain,
+                east_bicycle_and_pedestrain
             ]),
             indexed_controls[12].conflicting_with(vec![
-                &indexed_controls[5], &indexed_controls[6], // oost A
-                &indexed_controls[8],                       // oost B
+                indexed_controls[5], indexed_controls[6], // oost A
+                indexed_controls[8],                       // oost B
                 road_west_9_10,
-                &indexed_controls[1]
+                indexed_controls[1],
+                bus_west,
+                east_bicycle_and_pedestrain
             ]),
             east_bicycle_and_pedestrain.conflicting_with(vec![
-                &indexed_controls[8],
+                indexed_controls[8],
                 road_west_9_10,
-                &indexed_controls[11],
+                indexed_controls[11],
+                bus_west,
+
+                indexed_controls[7], road_east_2_3,        // own approach
+                indexed_controls[12], bus_east
+            ]),
+            bus_east.conflicting_with(vec![
+                indexed_controls[5], indexed_controls[6],
+                indexed_controls[8],
+                indexed_controls[13],
+
+                west_bicycle_and_pedestrain,
+                east_bicycle_and_pedestrain
             ]),
         ])
     );
@@ -97,85 +128,162 @@ pub fn create_crossroad<'a>(traffic_controls: &'a Vec<Control<'a>>) -> Crossroad
     directions.insert(Direction::South,
         XorConflictsGroup::new("zuid".to_string(), vec![
             indexed_controls[4].conflicting_with(vec![
-                west_bicycle_and_pedestrain
+                west_bicycle_and_pedestrain,
+                south_bicycle_and_pedestrain
             ]),
             indexed_controls[13].conflicting_with(vec![
-                &indexed_controls[1], road_east_2_3,    // zuid A
-                &indexed_controls[5],                   // zuid B
-                &indexed_controls[13]
+                indexed_controls[1], road_east_2_3,    // zuid A
+                indexed_controls[5],                   // zuid B
+                road_west_9_10,
+                bus_east, bus_west,
+                south_bicycle_and_pedestrain
             ]),
             indexed_controls[8].conflicting_with(vec![
-                &indexed_controls[1], road_east_2_3,    // zuid A
-                &indexed_controls[5],                   // zuid B
-                &indexed_controls[6],
-                &indexed_controls[12],
+                indexed_controls[1], road_east_2_3,    // zuid A
+                indexed_controls[5],                   // zuid B
+                indexed_controls[6],
+                indexed_controls[12],
 
                 east_bicycle_and_pedestrain,
+                bus_east,
+                south_bicycle_and_pedestrain
             ]),
             south_bicycle_and_pedestrain.conflicting_with(vec![
-                &indexed_controls[5],
-                &indexed_controls[6],
-                &indexed_controls[7],
+                indexed_controls[5],
+                indexed_controls[6],
+                indexed_controls[7],
+
+                indexed_controls[4], indexed_controls[13], // own approach
+                indexed_controls[8], indexed_controls[0],
+                indexed_controls[18]
+            ]),
+            indexed_controls[0].conflicting_with(vec![
+                south_bicycle_and_pedestrain
             ]),
-            indexed_controls[0].conflicting_with(vec![])
+            indexed_controls[18].conflicting_with(vec![
+                south_bicycle_and_pedestrain
+            ])
         ]),
     );
 
     directions.insert(Direction::West,
         XorConflictsGroup::new("west".to_string(), vec![
-            indexed_controls[14].conflicting_with(vec![]),
+            indexed_controls[14].conflicting_with(vec![
+                west_bicycle_and_pedestrain
+            ]),
             road_west_9_10.conflicting_with(vec![
-                &indexed_controls[12], &indexed_controls[13], // west A
-                &indexed_controls[1],                         // west B
-                &indexed_controls[6],
+                indexed_controls[12], indexed_controls[13], // west A
+                indexed_controls[1],                         // west B
+                indexed_controls[6],
 
-                east_bicycle_and_pedestrain
+                east_bicycle_and_pedestrain,
+                west_bicycle_and_pedestrain
             ]),
             indexed_controls[5].conflicting_with(vec![
-                &indexed_controls[12], &indexed_controls[13], // west A
-                &indexed_controls[1],                         // west B
+                indexed_controls[12], indexed_controls[13], // west A
+                indexed_controls[1],                         // west B
                 road_east_2_3,
-                &indexed_controls[8],
+                indexed_controls[8],
 
-                south_bicycle_and_pedestrain
+                south_bicycle_and_pedestrain,
+                bus_east,
+                west_bicycle_and_pedestrain
             ]),
             west_bicycle_and_pedestrain.conflicting_with(vec![
-                &indexed_controls[1],
+                indexed_controls[1],
                 road_east_2_3,
-                &indexed_controls[4],
+                indexed_controls[4],
+                bus_east,
+
+                indexed_controls[14], road_west_9_10,        // own approach
+                indexed_controls[5], bus_west
+            ]),
+            bus_west.conflicting_with(vec![
+                indexed_controls[1], indexed_controls[6],
+                indexed_controls[12],
+                indexed_controls[13],
+
+                east_bicycle_and_pedestrain,
+                west_bicycle_and_pedestrain
             ]),
         ])
     );
 
     let primary_traffic = vec![
         road_east_2_3,
-        &indexed_controls[4],
+        indexed_controls[4],
         road_west_9_10,
-        &indexed_controls[11],
+        indexed_controls[11],
     ];
 
+    let conflicts = ConflictMatrix::from_directions(&topology, &directions);
+    let mut intergreen = IntergreenMatrix::new(&conflicts, DEFAULT_INTERGREEN);
+
+    // Pedestrians and cyclists need more time to clear the crossing
+    for &crossing in &[west_bicycle_and_pedestrain, south_bicycle_and_pedestrain, east_bicycle_and_pedestrain] {
+        let crossing = topology.control(crossing);
+        for other in topology.controls() {
+            if conflicts.controls_conflict(crossing, other) {
+                intergreen.set_controls(crossing, other, 8);
+            }
+        }
+    }
+
+    let fixed_time = FixedTimePlan::new(&create_fixed_time_config(), &topology, &intergreen).unwrap();
+
     Crossroad {
-        traffic_controls: indexed_controls.clone(),
-        primary_group: SignalGroup::new(primary_traffic.clone(), true),
-        primary_traffic: primary_traffic.clone(),
+        primary_group: SignalGroup::new(topology.lookup(&primary_traffic), true),
+        topology: topology,
+        primary_traffic: primary_traffic,
         secondary_traffic: vec![
-            &indexed_controls[0],
-            &indexed_controls[1],
-            &indexed_controls[5],
-            &indexed_controls[6],
-            &indexed_controls[7],
-            &indexed_controls[8],
-            &indexed_controls[12],
-            &indexed_controls[13],
-            &indexed_controls[14],
-            &east_bicycle_and_pedestrain,
-            &south_bicycle_and_pedestrain,
-            &west_bicycle_and_pedestrain,
+            indexed_controls[0],
+            indexed_controls[1],
+            indexed_controls[5],
+            indexed_controls[6],
+            indexed_controls[7],
+            indexed_controls[8],
+            indexed_controls[12],
+            indexed_controls[13],
+            indexed_controls[14],
+            east_bicycle_and_pedestrain,
+            south_bicycle_and_pedestrain,
+            west_bicycle_and_pedestrain,
         ],
         priority_traffic: vec![
-            &indexed_controls[15],
-            &indexed_controls[16],
+            indexed_controls[15],
+            indexed_controls[16],
         ],
         directions: directions,
+        conflicts: conflicts,
+        intergreen: intergreen,
+        mode: ControlMode::Actuated,
+        fixed_time: Some(fixed_time),
+        schedule: None,
+        bus_priority: BusPriorityPolicy::default(),
+        sensor_health: SensorHealthPolicy::default(),
+        watchdog: WatchdogPolicy::default(),
+        event_log: EventLogConfig::default(),
+        strategy: Box::new(DefaultStrategy),
+    }
+}
+
+/// Every lane gets green once per cycle, the primary road first.
+pub fn create_fixed_time_config() -> FixedTimeConfig {
+    FixedTimeConfig {
+        cycle: 120,
+        offset: 0,
+        stages: vec![
+            StageConfig { ids: vec![ 2,4,9,11,  7,14,0,18,  15,16 ], green: 20 },
+            StageConfig { ids: vec![ 1,6,  11,4,7,14 ],  green: 10 },
+            StageConfig { ids: vec![ 8,13, 11,4,7,14 ],  green: 10 },
+            StageConfig { ids: vec![ 2,12, 11,4,7,14 ],  green: 10 },
+            StageConfig { ids: vec![ 5,9,  11,4,7,14 ],  green: 10 },
+            StageConfig { ids: vec![ 17,19,21 ],         green: 10 },
+        ],
     }
 }
+
+/// The default crossroad in one call.
+pub fn default_crossroad() -> Crossroad {
+    create_crossroad(create_traffic_controls(create_traffic_lights()))
+}
@@ -0,0 +1,243 @@
+use std::cmp;
+use time;
+
+use traffic_protocol::*;
+use traffic_controls::*;
+
+/// Seconds between two vehicles of a queue driving over the detector at green.
+pub const SATURATION_HEADWAY: i32 = 2;
+
+/// Seconds over which occupancy and vehicle counts are measured.
+pub const MEASURE_WINDOW: i32 = 60;
+
+
+// -------------------------------------------------------------------------------
+// LaneDetector
+// -------------------------------------------------------------------------------
+
+/// What the on/off changes of the detector of one lane tell about its traffic.
+/// Every change to occupied is a vehicle, a vehicle that came and went between two ticks is
+/// noticed by the `last_update` of the sensor.
+#[derive(Debug, Clone)]
+pub struct LaneDetector {
+    /// Tick at which the detector became occupied.
+    pub occupied_since: Option<i32>,
+    /// Tick at which the last vehicle left the detector.
+    pub last_vehicle: Option<i32>,
+    /// Replaces the estimate of `extends_green` while the sensor is faulty.
+    pub fixed_extension: Option<bool>,
+    last_update: time::Tm,
+    arrivals: Vec<i32>,
+    occupied: Vec<(i32, i32)>,
+}
+
+impl LaneDetector {
+
+    pub fn new() -> LaneDetector {
+        LaneDetector { occupied_since: None, last_vehicle: None, fixed_extension: None, last_update: time::empty_tm(), arrivals: vec![], occupied: vec![] }
+    }
+
+    pub fn observe(&mut self, time: i32, sensor: &Sensor) {
+        let updated = sensor.last_update != self.last_update;
+
+        match (self.occupied_since, sensor.bezet) {
+            (None, true) => {
+                self.occupied_since = Some(time);
+                self.arrivals.push(time);
+            },
+            (Some(since), false) => {
+                self.occupied.push((since, time));
+                self.occupied_since = None;
+                self.last_vehicle = Some(time);
+            },
+            (None, false) if updated => {
+                self.arrivals.push(time);
+                self.last_vehicle = Some(time);
+            },
+            _ => (),
+        }
+
+        self.last_update = sensor.last_update;
+        self.arrivals.retain(|&t| t > time - MEASURE_WINDOW);
+        self.occupied.retain(|&(_, end)| end > time - MEASURE_WINDOW);
+    }
+
+    /// Vehicles over the last `MEASURE_WINDOW` seconds.
+    pub fn count(&self) -> i32 {
+        self.arrivals.len() as i32
+    }
+
+    /// Part of the last `MEASURE_WINDOW` seconds the detector was occupied, from 0 to 1.
+    pub fn occupancy(&self, time: i32) -> f64 {
+        let from = time - MEASURE_WINDOW;
+        let closed: i32 = self.occupied.iter().map(|&(start, end)| end - cmp::max(start, from)).sum();
+        let open = self.occupied_since.map_or(0, |since| time - cmp::max(since, from));
+        (closed + open) as f64 / MEASURE_WINDOW as f64
+    }
+
+    /// Seconds since the last vehicle left, while the detector is free.
+    pub fn gap(&self, time: i32) -> Option<i32> {
+        match self.occupied_since {
+            Some(_) => None,
+            None => self.last_vehicle.map(|last| time - last),
+        }
+    }
+
+    /// Seconds the detector has been occupied without a break.
+    pub fn occupied_for(&self, time: i32) -> Option<i32> {
+        self.occupied_since.map(|since| time - since)
+    }
+
+    /// Vehicles waiting in the lane. The detector only sees the first one, the ones behind it are
+    /// estimated from the vehicle count: while it was occupied, vehicles kept arriving at that rate.
+    pub fn queue(&self, time: i32) -> i32 {
+        match self.occupied_for(time) {
+            Some(occupied) => 1 + self.count() * occupied / MEASURE_WINDOW,
+            None => 0,
+        }
+    }
+
+    /// Whether the green should be extended for this lane: vehicles are moving over the detector, or
+    /// one just passed. A detector that stays occupied longer than the estimated queue needs to drive
+    /// off, plus `max_gap`, has a vehicle standing still on it and no longer extends the green.
+    pub fn extends_green(&self, time: i32, green_start: i32, max_gap: i32) -> bool {
+        if let Some(extends) = self.fixed_extension {
+            return extends;
+        }
+
+        match self.occupied_since {
+            Some(since) => {
+                let discharge = self.queue(time) * SATURATION_HEADWAY + max_gap;
+                time - cmp::max(since, green_start) < discharge
+            },
+            None => self.gap(time) == Some(0),
+        }
+    }
+}
+
+
+// -------------------------------------------------------------------------------
+// Detectors
+// -------------------------------------------------------------------------------
+
+/// A `LaneDetector` for every sensor, updated by the controller every tick.
+#[derive(Debug, Clone)]
+pub struct Detectors {
+    lanes: Vec<LaneDetector>,
+}
+
+impl Detectors {
+
+    pub fn new() -> Detectors {
+        Detectors { lanes: vec![LaneDetector::new(); BAAN_COUNT] }
+    }
+
+    pub fn update(&mut self, time: i32, sensors: &SensorStates) {
+        for (id, lane) in self.lanes.iter_mut().enumerate() {
+            if let Some(sensor) = sensors.sensor(SensorId(id)) {
+                lane.observe(time, sensor);
+            }
+        }
+    }
+
+    pub fn set_fixed_extension(&mut self, id: SensorId, extends: Option<bool>) {
+        if let Some(lane) = self.lanes.get_mut(id.0) {
+            lane.fixed_extension = extends;
+        }
+    }
+
+    pub fn lane(&self, id: SensorId) -> Option<&LaneDetector> {
+        self.lanes.get(id.0)
+    }
+
+    /// Vehicles waiting at all lanes of the control.
+    pub fn queue(&self, control: &Control, time: i32) -> i32 {
+        control.get_ids().iter().filter_map(|&id| self.lane(SensorId(id))).map(|lane| lane.queue(time)).sum()
+    }
+
+    pub fn extends_green(&self, control: &Control, time: i32, green_start: i32, max_gap: i32) -> bool {
+        control.get_ids().iter().filter_map(|&id| self.lane(SensorId(id))).any(|lane| lane.extends_green(time, green_start, max_gap))
+    }
+}
+
+
+#[cfg(test)]
+fn observe_all(detector: &mut LaneDetector, occupied: Vec<bool>) {
+    let mut sensor = SensorStates::new();
+    for (time, bezet) in occupied.into_iter().enumerate() {
+        let now = time::at_utc(time::Timespec::new(time as i64, 0));
+        sensor.update(&vec![Baan { id: 0, bezet: bezet }], now);
+        detector.observe(time as i32, sensor.sensor(SensorId(0)).unwrap());
+    }
+}
+
+#[test]
+fn detector_counts_vehicles_and_gaps() {
+    let mut detector = LaneDetector::new();
+    observe_all(&mut detector, vec![false, true, true, false, false, true, false, false, false]);
+
+    assert_eq!(detector.count(), 2);
+    assert_eq!(detector.gap(9), Some(3));
+    assert!((detector.occupancy(9) - 3.0 / MEASURE_WINDOW as f64).abs() < 1e-9);
+    assert_eq!(detector.queue(9), 0);
+
+    // A vehicle that came and went between two ticks.
+    let mut sensors = SensorStates::new();
+    sensors.update(&vec![Baan { id: 0, bezet: true }], time::at_utc(time::Timespec::new(20, 0)));
+    sensors.update(&vec![Baan { id: 0, bezet: false }], time::at_utc(time::Timespec::new(20, 500_000_000)));
+    detector.observe(21, sensors.sensor(SensorId(0)).unwrap());
+    assert_eq!(detector.count(), 3);
+    assert_eq!(detector.gap(21), Some(0));
+}
+
+#[test]
+fn queue_grows_with_the_vehicle_count() {
+    // Busy lane: ten vehicles in the last minute, then the detector stays occupied for 30 seconds.
+    let mut busy = LaneDetector::new();
+    let mut occupied: Vec<bool> = (0..20).map(|t| t % 2 == 0).collect();
+    occupied.extend(vec![true; 31]);
+    observe_all(&mut busy, occupied);
+
+    // Quiet lane: one vehicle, occupied just as long.
+    let mut quiet = LaneDetector::new();
+    let mut occupied = vec![false; 20];
+    occupied.extend(vec![true; 31]);
+    observe_all(&mut quiet, occupied);
+
+    assert_eq!(quiet.queue(50), 1);
+    assert!(busy.queue(50) > 5, "queue {}", busy.queue(50));
+
+    // At green the quiet lane stops extending once its one vehicle had time to leave, the busy one keeps going.
+    assert!(!quiet.extends_green(50 + 3 + SATURATION_HEADWAY, 50, 3));
+    assert!(busy.extends_green(50 + 3 + SATURATION_HEADWAY, 50, 3));
+}
+
+#[test]
+fn standing_vehicle_does_not_hold_the_green_until_max_green() {
+    use clock::*;
+    use controller::Controller;
+
+    let mut controller = Controller::new(::default_crossroad::default_crossroad());
+    let max_green = controller.crossroad().topology.control_of(LightId(13)).unwrap().timings().max_green;
+
+    // The detector of 13 stays occupied the whole time, without any other vehicle.
+    let clock = SimulatedClock::new();
+    let mut sensors = SensorStates::new();
+    sensors.update(&vec![Baan { id: 13, bezet: true }], clock.now());
+
+    let mut green = None;
+    for _ in 0..60 {
+        let time = clock.tick();
+        for change in controller.step(clock.now(), &sensors).into_iter().filter(|c| c.id == 13) {
+            match (green, change.state) {
+                (None, JsonState::Groen) => green = Some(time),
+                (Some(start), JsonState::Geel) => {
+                    assert!(time - start < max_green, "green from {} to {}", start, time);
+                    return;
+                },
+                _ => (),
+            }
+        }
+    }
+    panic!("13 didn't turn green and yellow");
+}
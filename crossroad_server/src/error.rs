@@ -16,6 +16,8 @@ pub enum Error {
     Io(IoError),
     Serde(SerdeError),
     SerdeJson(JsonError),
+    Config(String),
+    Scenario(String),
     Other(String),
 }
 
@@ -25,6 +27,8 @@ impl fmt::Display for Error {
             Error::Io(ref err) => fmt::Display::fmt(err, f),
             Error::Serde(ref err) => fmt::Display::fmt(err, f),
             Error::SerdeJson(ref err) => fmt::Display::fmt(err, f),
+            Error::Config(ref err) => write!(f, "Invalid crossroad config: {}", err),
+            Error::Scenario(ref err) => write!(f, "Invalid scenario: {}", err),
             Error::Other(ref err) => err.fmt(f),
         }
     }
@@ -36,6 +40,12 @@ impl From<IoError> for Error {
     }   
 }
 
+impl From<SerdeError> for Error {
+    fn from(err: SerdeError) -> Error {
+        Error::Serde(err)
+    }
+}
+
 impl From<JsonError> for Error {
     fn from(err: JsonError) -> Error {
         Error::SerdeJson(err)
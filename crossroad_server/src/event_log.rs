@@ -0,0 +1,268 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use serde_json::{self, Value};
+use time;
+
+use error::{Result, Error};
+use controller::ControllerEvent;
+
+/// Raised whenever a field of `LogRecord` is added, removed or changes meaning.
+pub const EVENT_LOG_VERSION: u32 = 1;
+
+
+// -------------------------------------------------------------------------------
+// LogRecord
+// -------------------------------------------------------------------------------
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum RecordKind {
+    /// A message from the client.
+    #[serde(rename = "in")]
+    In,
+    /// A message to the client.
+    #[serde(rename = "out")]
+    Out,
+    /// A `ControllerEvent`.
+    #[serde(rename = "decision")]
+    Decision,
+    /// A `SessionEvent`.
+    #[serde(rename = "session")]
+    Session,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum SessionEvent {
+    Connected { peer: String },
+    Disconnected,
+}
+
+/// One line of the event log.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LogRecord {
+    pub version: u32,
+    pub session: String,
+    /// Counts the records of the session from 0.
+    pub seq: u64,
+    /// Milliseconds since the session started, from a monotonic clock.
+    pub elapsed_ms: i64,
+    /// The time of the controller's clock, rfc3339.
+    pub at: String,
+    /// The last tick of the controller. A message that came in after tick 4 is handled by tick 5.
+    pub tick: i32,
+    pub kind: RecordKind,
+    /// The parsed message or the event. A received line that isn't json is kept as a string.
+    pub payload: Value,
+}
+
+/// Reads an event log back, every record has to be of this version.
+pub fn read_records<P: AsRef<Path>>(path: P) -> Result<Vec<LogRecord>> {
+    let mut text = String::new();
+    try!(File::open(path).and_then(|mut f| f.read_to_string(&mut text)));
+
+    let mut records = vec![];
+    for (number, line) in text.lines().enumerate().filter(|&(_, line)| !line.trim().is_empty()) {
+        let record: LogRecord = try!(serde_json::from_str(line));
+        if record.version != EVENT_LOG_VERSION {
+            return Err(Error::Other(format!("line {}: event log version {}, expected {}", number + 1, record.version, EVENT_LOG_VERSION)));
+        }
+        records.push(record);
+    }
+    Ok(records)
+}
+
+
+// -------------------------------------------------------------------------------
+// RotatingFile
+// -------------------------------------------------------------------------------
+
+/// Where the event log goes and when it is rotated.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EventLogConfig {
+    /// Directory of `events.jsonl`, created when it doesn't exist.
+    pub dir: String,
+    /// The log is rotated before it grows past this many bytes, 0 never rotates it.
+    pub max_bytes: u64,
+    /// Rotated logs that are kept, `events.1.jsonl` is the newest one.
+    pub keep: usize,
+}
+
+impl Default for EventLogConfig {
+    fn default() -> EventLogConfig {
+        EventLogConfig { dir: ".".to_string(), max_bytes: 10 * 1024 * 1024, keep: 5 }
+    }
+}
+
+impl EventLogConfig {
+    /// The current log for 0, otherwise the rotated one.
+    pub fn path(&self, rotated: usize) -> PathBuf {
+        match rotated {
+            0 => Path::new(&self.dir).join("events.jsonl"),
+            n => Path::new(&self.dir).join(format!("events.{}.jsonl", n)),
+        }
+    }
+}
+
+/// The event log file, shared by every session of the server.
+pub struct RotatingFile {
+    config: EventLogConfig,
+    file: File,
+    written: u64,
+}
+
+impl RotatingFile {
+
+    /// Appends to the current log.
+    pub fn open(config: &EventLogConfig) -> io::Result<RotatingFile> {
+        try!(fs::create_dir_all(&config.dir));
+        let file = try!(OpenOptions::new().create(true).append(true).open(config.path(0)));
+        let written = try!(file.metadata()).len();
+        Ok(RotatingFile { config: config.clone(), file: file, written: written })
+    }
+
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let size = line.len() as u64 + 1;
+        if self.config.max_bytes > 0 && self.written > 0 && self.written + size > self.config.max_bytes {
+            try!(self.rotate());
+        }
+
+        try!(self.file.write_all(format!("{}\n", line).as_bytes()));
+        self.written += size;
+        Ok(())
+    }
+
+    /// Every rotated log moves up one, the oldest one is dropped.
+    fn rotate(&mut self) -> io::Result<()> {
+        match self.config.keep {
+            0 => try!(fs::remove_file(self.config.path(0))),
+            keep => for n in (1..keep + 1).rev() {
+                if self.config.path(n - 1).exists() {
+                    try!(fs::rename(self.config.path(n - 1), self.config.path(n)));
+                }
+            },
+        }
+
+        self.file = try!(OpenOptions::new().create(true).append(true).open(self.config.path(0)));
+        self.written = 0;
+        Ok(())
+    }
+}
+
+
+// -------------------------------------------------------------------------------
+// EventLog
+// -------------------------------------------------------------------------------
+
+/// Writes the records of one session.
+pub struct EventLog {
+    session: String,
+    file: Arc<Mutex<RotatingFile>>,
+    start: time::SteadyTime,
+    seq: u64,
+    tick: i32,
+}
+
+impl EventLog {
+
+    pub fn new(session: String, file: Arc<Mutex<RotatingFile>>) -> EventLog {
+        EventLog { session: session, file: file, start: time::SteadyTime::now(), seq: 0, tick: 0 }
+    }
+
+    pub fn session(&self) -> &str {
+        &self.session
+    }
+
+    /// Called by the main loop at the start of every tick.
+    pub fn set_tick(&mut self, tick: i32) {
+        self.tick = tick;
+    }
+
+    pub fn received(&mut self, now: time::Tm, line: &str) {
+        let payload = serde_json::from_str(line).unwrap_or(Value::String(line.to_string()));
+        self.record(now, RecordKind::In, payload);
+    }
+
+    pub fn sent(&mut self, now: time::Tm, message: &str) {
+        let payload = serde_json::from_str(message).unwrap_or(Value::String(message.to_string()));
+        self.record(now, RecordKind::Out, payload);
+    }
+
+    pub fn decision(&mut self, now: time::Tm, event: &ControllerEvent) {
+        self.record(now, RecordKind::Decision, serde_json::to_value(event).unwrap_or(Value::Null));
+    }
+
+    pub fn session_event(&mut self, now: time::Tm, event: &SessionEvent) {
+        self.record(now, RecordKind::Session, serde_json::to_value(event).unwrap_or(Value::Null));
+    }
+
+    fn record(&mut self, now: time::Tm, kind: RecordKind, payload: Value) {
+        let record = LogRecord {
+            version: EVENT_LOG_VERSION,
+            session: self.session.clone(),
+            seq: self.seq,
+            elapsed_ms: (time::SteadyTime::now() - self.start).num_milliseconds(),
+            at: now.rfc3339().to_string(),
+            tick: self.tick,
+            kind: kind,
+            payload: payload,
+        };
+        self.seq += 1;
+
+        // A full disk shouldn't stop the lights.
+        if let Err(err) = self.file.lock().unwrap().write_line(&serde_json::to_string(&record).unwrap()) {
+            println!("Could not write the event log: {}", err);
+        }
+    }
+}
+
+
+#[cfg(test)]
+fn test_dir(name: &str) -> EventLogConfig {
+    let dir = ::std::env::temp_dir().join(format!("crossroad_event_log_{}_{}", name, time::get_time().nsec));
+    EventLogConfig { dir: dir.to_str().unwrap().to_string(), max_bytes: 0, keep: 2 }
+}
+
+#[test]
+fn event_log_writes_versioned_records() {
+    let config = test_dir("records");
+    let file = Arc::new(Mutex::new(RotatingFile::open(&config).unwrap()));
+    let mut log = EventLog::new("test".to_string(), file);
+    let now = time::at_utc(time::Timespec::new(0, 0));
+
+    log.session_event(now, &SessionEvent::Connected { peer: "127.0.0.1:1234".to_string() });
+    log.set_tick(4);
+    log.received(now, "{\"banen\":[{\"id\":13,\"bezet\":true}]}");
+    log.received(now, "not json");
+    log.set_tick(5);
+    log.decision(now, &ControllerEvent::GroupChosen { lights: vec![13], bus: false });
+    log.sent(now, "{\"stoplichten\":[{\"id\":13,\"status\":2}]}");
+
+    let records = read_records(config.path(0)).unwrap();
+    assert_eq!(records.iter().map(|r| (r.seq, r.tick, r.kind)).collect::<Vec<_>>(),
+        vec![(0, 0, RecordKind::Session), (1, 4, RecordKind::In), (2, 4, RecordKind::In), (3, 5, RecordKind::Decision), (4, 5, RecordKind::Out)]);
+    assert!(records.iter().all(|r| r.version == EVENT_LOG_VERSION && r.session == "test"));
+    assert_eq!(records[1].payload, serde_json::from_str::<Value>("{\"banen\":[{\"id\":13,\"bezet\":true}]}").unwrap());
+    assert_eq!(records[2].payload, Value::String("not json".to_string()));
+
+    fs::remove_dir_all(&config.dir).unwrap();
+}
+
+#[test]
+fn event_log_rotates_and_keeps_the_newest() {
+    let mut config = test_dir("rotation");
+    config.max_bytes = 25;
+
+    let mut file = RotatingFile::open(&config).unwrap();
+    for line in &["first line of ten", "second line", "third line", "fourth line"] {
+        file.write_line(line).unwrap();
+    }
+
+    let read = |path: PathBuf| { let mut text = String::new(); File::open(path).unwrap().read_to_string(&mut text).unwrap(); text };
+    assert_eq!(read(config.path(0)), "fourth line\n");
+    assert_eq!(read(config.path(1)), "second line\nthird line\n");
+    assert_eq!(read(config.path(2)), "first line of ten\n");
+    assert!(!config.path(3).exists());
+
+    fs::remove_dir_all(&config.dir).unwrap();
+}
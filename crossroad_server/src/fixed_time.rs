@@ -0,0 +1,252 @@
+use std::cmp;
+
+use error::{Result, Error};
+use traffic_protocol::*;
+use traffic_controls::*;
+use crossroad::*;
+use config::FixedTimeConfig;
+use controller::SignalChange;
+use intergreen::*;
+
+
+// -------------------------------------------------------------------------------
+// FixedTimePlan
+// -------------------------------------------------------------------------------
+
+/// A stage is green from `start` until `end`, in seconds since the start of the cycle.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stage {
+    pub controls: Vec<ControlId>,
+    pub start: i32,
+    pub end: i32,
+}
+
+/// Runs the stages in order, without looking at the sensors.
+/// Between two stages there is just enough time for the intergreen of the lights that stop,
+/// time left over at the end of the cycle goes to the last stage.
+#[derive(Debug, Clone)]
+pub struct FixedTimePlan {
+    pub cycle: i32,
+    pub offset: i32,
+    pub stages: Vec<Stage>,
+}
+
+impl FixedTimePlan {
+
+    pub fn new(config: &FixedTimeConfig, topology: &Topology, intergreen: &IntergreenMatrix) -> Result<FixedTimePlan> {
+        let indexed_controls = topology.indexed_controls();
+
+        let controls: Vec<Vec<ControlId>> = config.stages.iter().map(|stage| {
+            let mut controls: Vec<ControlId> = vec![];
+            for &id in &stage.ids {
+                if !controls.contains(&indexed_controls[id]) {
+                    controls.push(indexed_controls[id]);
+                }
+            }
+            controls
+        }).collect();
+
+        let mut stages = vec![];
+        let mut position = 0;
+
+        for (index, stage) in config.stages.iter().enumerate() {
+            let next = &controls[(index + 1) % controls.len()];
+            stages.push(Stage { controls: controls[index].clone(), start: position, end: position + stage.green });
+            position += stage.green + clearance_time(topology, intergreen, &controls[index], next);
+        }
+
+        if position > config.cycle {
+            return Err(Error::Config(format!("fixed time cycle of {} seconds is too short, the stages need {} seconds", config.cycle, position)));
+        }
+
+        if let Some(last) = stages.last_mut() {
+            last.end += config.cycle - position;
+        }
+
+        Ok(FixedTimePlan { cycle: config.cycle, offset: config.offset, stages: stages })
+    }
+
+    /// Seconds since the start of the current cycle.
+    pub fn cycle_position(&self, time: i32) -> i32 {
+        ((time - self.offset) % self.cycle + self.cycle) % self.cycle
+    }
+
+    /// The controls that should be green at `time`, none while the plan is between stages.
+    pub fn green_at(&self, time: i32) -> Vec<ControlId> {
+        let position = self.cycle_position(time);
+        self.stages.iter()
+            .find(|stage| position >= stage.start && position < stage.end)
+            .map(|stage| stage.controls.clone())
+            .unwrap_or(vec![])
+    }
+
+    /// Stops the controls that are not in the current stage first, so the lights that start never see
+    /// a conflicting light that is still green. Bus lanes show the movement of their first bus.
+    pub fn run_loop(&self, time: i32,
+                           crossroad: &Crossroad,
+                           sensor_states: &SensorStates,
+                           state: &mut FixedTimeState,
+                           changes: &mut Vec<SignalChange>,
+                           clearance: &mut ClearanceState) {
+
+        let green = self.green_at(time);
+
+        for control in crossroad.topology.controls() {
+            let aspect = state.aspects[control.id.0];
+
+            let new_aspect = match aspect {
+                FixedAspect::Green if !green.contains(&control.id) => {
+                    changes.extend(control.signal_changes(JsonState::Geel));
+                    clearance.end_green(control, time);
+                    Some(FixedAspect::Yellow { start: time })
+                },
+                FixedAspect::Yellow { start } if time >= start + control.timings().yellow => {
+                    changes.extend(control.signal_changes(JsonState::Rood));
+                    clearance.start_red(control, time);
+                    Some(FixedAspect::Red)
+                },
+                _ => None,
+            };
+
+            if let Some(v) = new_aspect { state.aspects[control.id.0] = v };
+        }
+
+        for &id in &green {
+            let control = crossroad.topology.control(id);
+
+            if state.aspects[id.0] == FixedAspect::Red && clearance.may_start(control, time, &crossroad.intergreen) {
+                let aspect = match crossroad.priority_traffic.contains(&id) {
+                    true  => crossroad.bus_aspect(control, sensor_states),
+                    false => JsonState::Groen,
+                };
+                changes.extend(control.signal_changes(aspect));
+                state.aspects[id.0] = FixedAspect::Green;
+            }
+        }
+    }
+}
+
+/// The longest intergreen from a light that stops after `from` to a light that starts in `to`.
+fn clearance_time(topology: &Topology, intergreen: &IntergreenMatrix, from: &Vec<ControlId>, to: &Vec<ControlId>) -> i32 {
+    let mut time = 0;
+
+    for stopping in from.iter().filter(|id| !to.contains(id)) {
+        for starting in to.iter().filter(|id| !from.contains(id)) {
+            for light_from in topology.control(*stopping).get_ids() {
+                for light_to in topology.control(*starting).get_ids() {
+                    time = cmp::max(time, intergreen.get(light_from, light_to));
+                }
+            }
+        }
+    }
+
+    time
+}
+
+
+// -------------------------------------------------------------------------------
+// FixedTimeState
+// -------------------------------------------------------------------------------
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FixedAspect {
+    Red,
+    Green,
+    Yellow { start: i32 },
+}
+
+/// The aspect of every control, indexed by control id. Everything starts at red.
+#[derive(Debug, Clone)]
+pub struct FixedTimeState {
+    aspects: Vec<FixedAspect>,
+}
+
+impl FixedTimeState {
+    pub fn new(topology: &Topology) -> FixedTimeState {
+        FixedTimeState::with_green(topology, &vec![])
+    }
+
+    /// Takes over from another mode that left these controls green.
+    pub fn with_green(topology: &Topology, green: &Vec<ControlId>) -> FixedTimeState {
+        let mut state = FixedTimeState { aspects: vec![FixedAspect::Red; topology.controls().len()] };
+        for id in green {
+            state.aspects[id.0] = FixedAspect::Green;
+        }
+        state
+    }
+
+    pub fn all_red(&self) -> bool {
+        self.aspects.iter().all(|&aspect| aspect == FixedAspect::Red)
+    }
+}
+
+
+#[test]
+fn fixed_time_plan_runs_every_stage_without_conflicts() {
+    use clock::*;
+    use controller::Controller;
+
+    let mut crossroad = ::default_crossroad::default_crossroad();
+    crossroad.mode = ControlMode::FixedTime;
+
+    let plan = crossroad.fixed_time.clone().unwrap();
+    let conflicts = crossroad.conflicts.clone();
+    let mut controller = Controller::new(crossroad);
+
+    let clock = SimulatedClock::new();
+    let sensors = SensorStates::new();
+    let mut status = vec![JsonState::Rood; BAAN_COUNT];
+    let mut turned_green = vec![false; BAAN_COUNT];
+
+    for _ in 0..plan.cycle * 2 {
+        let time = clock.tick();
+
+        for change in controller.step(clock.now(), &sensors) {
+            // Always green, yellow, red and green again.
+            match (status[change.id], change.state) {
+                (JsonState::Rood, JsonState::Groen) | (JsonState::Rood, JsonState::BusRechtdoorRechtsaf) => turned_green[change.id] = true,
+                (JsonState::Groen, JsonState::Geel) | (JsonState::BusRechtdoorRechtsaf, JsonState::Geel) => (),
+                (JsonState::Geel, JsonState::Rood) => (),
+                (from, to) => panic!("light {} went from {:?} to {:?} at {}", change.id, from, to, time),
+            }
+            status[change.id] = change.state;
+        }
+
+        let active: Vec<usize> = (0..BAAN_COUNT).filter(|&id| status[id] != JsonState::Rood).collect();
+        for &a in &active {
+            for &b in &active {
+                assert!(!conflicts.is_conflicting(a, b), "{} and {} are not red together at {}", a, b, time);
+            }
+        }
+    }
+
+    assert!(turned_green.iter().all(|&green| green));
+}
+
+#[test]
+fn fixed_time_bus_lane_shows_the_movement_of_its_line() {
+    use clock::*;
+    use controller::Controller;
+
+    let mut crossroad = ::default_crossroad::default_crossroad();
+    crossroad.mode = ControlMode::FixedTime;
+    crossroad.topology.set_bus_routes(vec![BusRoute { line: 3, movement: BusMovement::Right }]);
+
+    let cycle = crossroad.fixed_time.clone().unwrap().cycle;
+    let mut controller = Controller::new(crossroad);
+
+    let clock = SimulatedClock::new();
+    let mut sensors = SensorStates::new();
+    sensors.update_bussen(&vec![BusBaan { id: 15, eerstvolgendelijn: 3, vertraging: 0, bezet: true }], clock.now());
+
+    let mut aspects = vec![];
+    for _ in 0..cycle {
+        clock.tick();
+        for change in controller.step(clock.now(), &sensors) {
+            if change.id == 15 && change.state != JsonState::Rood && change.state != JsonState::Geel { aspects.push(change.state); }
+        }
+    }
+
+    assert!(!aspects.is_empty());
+    assert!(aspects.iter().all(|&aspect| aspect == JsonState::BusRechtsaf), "{:?}", aspects);
+}
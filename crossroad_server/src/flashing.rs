@@ -0,0 +1,235 @@
+use traffic_protocol::*;
+use traffic_controls::*;
+use crossroad::*;
+use controller::SignalChange;
+use intergreen::ClearanceState;
+
+/// Seconds every light stays red after flashing, before the first light may turn green.
+pub const START_UP_ALL_RED: i32 = 6;
+
+
+// -------------------------------------------------------------------------------
+// FlashingState
+// -------------------------------------------------------------------------------
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FlashingPhase {
+    /// Lights that are still green or yellow go through yellow to red first.
+    Clearing,
+    /// Every light flashes yellow.
+    Flashing,
+    /// Every light stays red, the safe state of a stopped controller.
+    AllRed,
+    /// Every light is red since `start`, afterwards the crossroad continues in `next`.
+    StartUp { start: i32, next: ControlMode },
+}
+
+/// Night operation and the fail-safe state. Entered through a yellow to red clearance of the lights
+/// that were green, left through an all red start-up. The lights only flash when `flash` is set,
+/// otherwise they stay red after the clearance.
+#[derive(Debug, Clone)]
+pub struct FlashingState {
+    pub phase: FlashingPhase,
+    flash: bool,
+    leave_to: Option<ControlMode>,
+    yellow_start: Vec<Option<i32>>,
+}
+
+impl FlashingState {
+
+    pub fn new(topology: &Topology) -> FlashingState {
+        FlashingState { phase: FlashingPhase::Clearing, flash: true, leave_to: None, yellow_start: vec![None; topology.controls().len()] }
+    }
+
+    /// Clears the lights like `new`, but keeps them red instead of flashing.
+    pub fn all_red(topology: &Topology) -> FlashingState {
+        FlashingState { flash: false, ..FlashingState::new(topology) }
+    }
+
+    /// Starts the all red start-up as soon as every light flashes or is red.
+    pub fn leave(&mut self, next: ControlMode) {
+        self.leave_to = Some(next);
+    }
+
+    pub fn is_flashing(&self) -> bool {
+        self.phase == FlashingPhase::Flashing
+    }
+
+    /// The clearance is done, every light flashes or is red.
+    pub fn is_cleared(&self) -> bool {
+        self.phase == FlashingPhase::Flashing || self.phase == FlashingPhase::AllRed
+    }
+
+    /// `signals` is what the lights show right now, indexed by light id.
+    /// Returns the mode to continue in once the start-up is done.
+    pub fn run_loop(&mut self, time: i32,
+                               crossroad: &Crossroad,
+                               signals: &Vec<JsonState>,
+                               changes: &mut Vec<SignalChange>,
+                               clearance: &mut ClearanceState)
+                               -> Option<ControlMode> {
+
+        match self.phase {
+            FlashingPhase::Clearing => {
+                let mut all_red = true;
+
+                for control in crossroad.topology.controls() {
+                    let showing = control.get_ids().iter().any(|&id| signals.get(id).map_or(false, |&s| s != JsonState::Rood));
+
+                    match self.yellow_start[control.id.0] {
+                        None if showing => {
+                            // A light that is already yellow keeps its yellow, counted from now.
+                            if control.get_ids().iter().any(|&id| signals[id] != JsonState::Geel) {
+                                changes.extend(control.signal_changes(JsonState::Geel));
+                                clearance.end_green(control, time);
+                            }
+                            self.yellow_start[control.id.0] = Some(time);
+                            all_red = false;
+                        },
+                        Some(start) if time >= start + control.timings().yellow => {
+                            changes.extend(control.signal_changes(JsonState::Rood));
+                            clearance.start_red(control, time);
+                            self.yellow_start[control.id.0] = None;
+                        },
+                        Some(_) => all_red = false,
+                        None => (),
+                    }
+                }
+
+                if all_red && self.flash {
+                    println!("========== FLASHING: all lights flash yellow");
+                    changes.extend(crossroad.set_all(JsonState::GeelKnipperend));
+                    self.phase = FlashingPhase::Flashing;
+                }
+                else if all_red {
+                    println!("========== FLASHING: all lights stay red");
+                    self.phase = FlashingPhase::AllRed;
+                }
+                None
+            },
+
+            FlashingPhase::Flashing => {
+                if let Some(next) = self.leave_to.take() {
+                    println!("========== FLASHING: all red start-up, continuing {:?} at {}", next, time + START_UP_ALL_RED);
+                    changes.extend(crossroad.set_all(JsonState::Rood));
+
+                    // Traffic may have entered the crossroad on flashing yellow, it has to clear like after a green.
+                    for control in crossroad.topology.controls() {
+                        clearance.end_green(control, time);
+                        clearance.start_red(control, time);
+                    }
+                    self.phase = FlashingPhase::StartUp { start: time, next: next };
+                }
+                None
+            },
+
+            FlashingPhase::AllRed => {
+                if let Some(next) = self.leave_to.take() {
+                    println!("========== FLASHING: all red start-up, continuing {:?} at {}", next, time + START_UP_ALL_RED);
+                    self.phase = FlashingPhase::StartUp { start: time, next: next };
+                }
+                None
+            },
+
+            FlashingPhase::StartUp { start, next } => {
+                match time >= start + START_UP_ALL_RED {
+                    true  => Some(next),
+                    false => None,
+                }
+            },
+        }
+    }
+}
+
+
+#[cfg(test)]
+fn night_crossroad() -> Crossroad {
+    let json = include_str!("../default_crossroad.json").replace("\"mode\": \"Actuated\",", r#""mode": "Actuated",
+    "schedule": {
+        "default": "daytime",
+        "plans": [
+            { "name": "daytime", "mode": "Actuated" },
+            { "name": "night", "mode": "Flashing" }
+        ],
+        "windows": [
+            { "days": ["Monday"], "from": "00:00", "to": "05:00", "plan": "night" }
+        ]
+    },"#);
+    let config = ::config::CrossroadConfig::from_str(&json).unwrap();
+    config.create_crossroad(config.create_traffic_controls(config.create_traffic_lights())).unwrap()
+}
+
+#[test]
+fn fail_safe_clears_green_lights_before_flashing() {
+    use clock::*;
+    use controller::Controller;
+
+    let mut controller = Controller::new(::default_crossroad::default_crossroad());
+
+    let clock = SimulatedClock::new();
+    let sensors = SensorStates::new();
+    let mut status = vec![JsonState::Rood; BAAN_COUNT];
+
+    // The primary lanes are green.
+    for _ in 0..5 {
+        clock.tick();
+        for change in controller.step(clock.now(), &sensors) {
+            status[change.id] = change.state;
+        }
+    }
+    assert_eq!(status[2], JsonState::Groen);
+
+    controller.fail_safe("test".to_string());
+
+    for _ in 0..10 {
+        let time = clock.tick();
+        for change in controller.step(clock.now(), &sensors) {
+            match (status[change.id], change.state) {
+                (JsonState::Groen, JsonState::Geel) | (JsonState::Geel, JsonState::Rood) | (JsonState::Rood, JsonState::GeelKnipperend) => (),
+                (from, to) => panic!("light {} went from {:?} to {:?} at {}", change.id, from, to, time),
+            }
+            status[change.id] = change.state;
+        }
+    }
+
+    assert!(status.iter().all(|&s| s == JsonState::GeelKnipperend));
+    assert_eq!(controller.fault(), Some(&"test".to_string()));
+}
+
+#[test]
+fn leaving_night_mode_starts_with_all_red() {
+    use clock::*;
+    use controller::Controller;
+
+    let mut controller = Controller::new(night_crossroad());
+
+    // 2017-05-01 04:58 was a monday night.
+    let clock = SimulatedClock::starting_at(::time::at_utc(::time::Timespec::new(1493596800 + 4 * 3600 + 58 * 60, 0)));
+    let sensors = SensorStates::new();
+    let mut status = vec![JsonState::Rood; BAAN_COUNT];
+    let mut all_red_since = None;
+
+    for _ in 0..180 {
+        let time = clock.tick();
+        let changes = controller.step(clock.now(), &sensors);
+
+        for change in &changes {
+            status[change.id] = change.state;
+        }
+
+        if changes.iter().any(|c| c.state == JsonState::Groen) {
+            let since = all_red_since.expect("a light turned green without an all red start-up");
+            assert!(time >= since + START_UP_ALL_RED, "first green at {}, all red since {}", time, since);
+            break;
+        }
+        if clock.now().tm_hour < 5 {
+            assert!(status.iter().all(|&s| s == JsonState::GeelKnipperend), "not flashing at {}", time);
+        }
+        if all_red_since.is_none() && status.iter().all(|&s| s == JsonState::Rood) {
+            all_red_since = Some(time);
+        }
+    }
+
+    assert_eq!(controller.crossroad().mode, ControlMode::Actuated);
+    assert!(status.iter().any(|&s| s == JsonState::Groen));
+}
@@ -0,0 +1,149 @@
+use std::fmt;
+
+use traffic_controls::*;
+use conflict_matrix::ConflictMatrix;
+
+/// Seconds between the end of green of a light and the start of green of a conflicting light.
+/// Includes the yellow time, so this leaves 2 seconds of all red.
+pub const DEFAULT_INTERGREEN: i32 = 6;
+
+
+// -------------------------------------------------------------------------------
+// IntergreenMatrix
+// -------------------------------------------------------------------------------
+
+/// Clearance times between conflicting lights, indexed by [clearing light][entering light].
+/// Non conflicting lights don't need any clearance time.
+#[derive(Clone)]
+pub struct IntergreenMatrix {
+    size: usize,
+    times: Vec<i32>,
+}
+
+impl IntergreenMatrix {
+
+    pub fn new(conflicts: &ConflictMatrix, default: i32) -> IntergreenMatrix {
+        let size = conflicts.size();
+        let mut times = vec![0; size * size];
+
+        for from in 0..size {
+            for to in 0..size {
+                if conflicts.is_conflicting(from, to) {
+                    times[from * size + to] = default;
+                }
+            }
+        }
+
+        IntergreenMatrix { size: size, times: times }
+    }
+
+    /// Sets the clearance time from every light of `from` to every light of `to`.
+    pub fn set_controls(&mut self, from: &Control, to: &Control, seconds: i32) {
+        for id_from in from.get_ids() {
+            for id_to in to.get_ids() {
+                self.times[id_from * self.size + id_to] = seconds;
+            }
+        }
+    }
+
+    pub fn get(&self, from: usize, to: usize) -> i32 {
+        if from < self.size && to < self.size { self.times[from * self.size + to] } else { 0 }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+impl fmt::Debug for IntergreenMatrix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for from in 0..self.size {
+            try!(write!(f, "\n{:>3} ", from));
+            for to in 0..self.size {
+                try!(write!(f, "{:>3}", self.get(from, to)));
+            }
+        }
+        Ok(())
+    }
+}
+
+
+// -------------------------------------------------------------------------------
+// ClearanceState
+// -------------------------------------------------------------------------------
+
+/// Remembers when each light ended its green and turned red, so a light is held until
+/// its conflicting lights have cleared and it has been red for its minimum red time.
+#[derive(Debug, Clone)]
+pub struct ClearanceState {
+    green_ended: Vec<Option<i32>>,
+    red_started: Vec<Option<i32>>,
+}
+
+impl ClearanceState {
+
+    pub fn new(size: usize) -> ClearanceState {
+        ClearanceState { green_ended: vec![None; size], red_started: vec![None; size] }
+    }
+
+    pub fn end_green(&mut self, control: &Control, time: i32) {
+        for id in control.get_ids() {
+            if let Some(ended) = self.green_ended.get_mut(id) {
+                *ended = Some(time);
+            }
+        }
+    }
+
+    pub fn green_ended(&self, light: usize) -> Option<i32> {
+        self.green_ended.get(light).and_then(|&ended| ended)
+    }
+
+    pub fn start_red(&mut self, control: &Control, time: i32) {
+        for id in control.get_ids() {
+            if let Some(started) = self.red_started.get_mut(id) {
+                *started = Some(time);
+            }
+        }
+    }
+
+    /// Time at which every light of this control has cleared all conflicting lights and had its minimum red.
+    pub fn cleared_at(&self, control: &Control, intergreen: &IntergreenMatrix) -> i32 {
+        let ids = control.get_ids();
+        let min_red = control.timings().min_red;
+
+        let conflicts_cleared = self.green_ended.iter().enumerate()
+            .filter_map(|(from, ended)| ended.map(|end| {
+                ids.iter().map(|&to| end + intergreen.get(from, to)).max().unwrap_or(end)
+            }))
+            .max()
+            .unwrap_or(0);
+
+        let min_red_done = ids.iter()
+            .filter_map(|&id| self.red_started.get(id).and_then(|&started| started))
+            .map(|started| started + min_red)
+            .max()
+            .unwrap_or(0);
+
+        ::std::cmp::max(conflicts_cleared, min_red_done)
+    }
+
+    pub fn may_start(&self, control: &Control, time: i32, intergreen: &IntergreenMatrix) -> bool {
+        time >= self.cleared_at(control, intergreen)
+    }
+}
+
+
+#[test]
+fn conflicting_light_waits_for_clearance() {
+    let crossroad = ::default_crossroad::default_crossroad();
+
+    let control = |id| crossroad.topology.control_of(LightId(id)).unwrap();
+
+    let mut clearance = ClearanceState::new(crossroad.intergreen.size());
+    clearance.end_green(control(6), 10);
+
+    // 8 conflicts with 6, 4 doesn't.
+    assert!(!clearance.may_start(control(8), 10 + DEFAULT_INTERGREEN - 1, &crossroad.intergreen));
+    assert!(clearance.may_start(control(8), 10 + DEFAULT_INTERGREEN, &crossroad.intergreen));
+    assert!(clearance.may_start(control(4), 10, &crossroad.intergreen));
+}
@@ -6,16 +6,35 @@ extern crate serde_derive;
 extern crate serde_json;
 extern crate serde;
 extern crate time;
-#[macro_use] extern crate itertools;
-extern crate permutohedron;
+extern crate schedule_recv;
 
 pub mod traffic_protocol;
 pub mod traffic_controls;
 pub mod crossroad;
 pub mod default_crossroad;
+pub mod config;
+pub mod conflict_matrix;
+pub mod intergreen;
+pub mod solver;
 pub mod error;
-pub mod cartesian;
 pub mod signal_group;
+pub mod clock;
+pub mod controller;
+pub mod fixed_time;
+pub mod flashing;
+pub mod safety_monitor;
+pub mod audit;
+pub mod bus_priority;
+pub mod schedule;
+pub mod strategy;
+pub mod planner;
+pub mod detector;
+pub mod sensor_health;
+pub mod watchdog;
+pub mod scenario;
+pub mod session;
+pub mod replay;
+pub mod event_log;
 
 trait BoolToOpt {
     fn to_opt(&self) -> Option<()>;
@@ -12,7 +12,7 @@ use serde::ser;
 use schedule_recv as sched;
 use time::*;
 
-use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs, Shutdown};
 use std::fmt::Display;
 use std::io::{self, BufRead, Write, BufReader, BufWriter};
 use std::thread;
@@ -29,6 +29,16 @@ use crossroad_server::crossroad::*;
 use crossroad_server::traffic_protocol::*;
 use crossroad_server::traffic_controls::*;
 use crossroad_server::default_crossroad;
+use crossroad_server::config::CrossroadConfig;
+use crossroad_server::conflict_matrix;
+use crossroad_server::controller::{Controller, SignalChange};
+use crossroad_server::safety_monitor::SafetyMonitor;
+use crossroad_server::strategy::{ControlStrategy, StrategyRegistry};
+use crossroad_server::clock::{Clock, WallClock, SimulatedClock, TICK_MS};
+use crossroad_server::watchdog::{Watchdog, WatchdogEvent};
+use crossroad_server::session::Session;
+use crossroad_server::replay::{self, Replay};
+use crossroad_server::event_log::{self, EventLog, RotatingFile, SessionEvent};
 use crossroad_server::error::{Result, Error, JsonError};
 
 
@@ -37,8 +47,15 @@ fn main() {
         (version: "1.0")
         (author: "Rutger S.")
         (about: "Awesome crossroad simulator!")
-        (@arg ip: +required "Runs the server on this ip")
+        (@arg ip: "Runs the server on this ip, not needed for a replay")
         (@arg port: -p --port +takes_value "Sets the port")
+        (@arg config: -c --config +takes_value "Loads the crossroad layout from this json file instead of the built-in default crossroad")
+        (@arg mode: -m --mode +takes_value "Overrides the control mode of the crossroad, actuated, fixed (runs the fixed time plan) or flashing")
+        (@arg strategy: -s --strategy +takes_value "Picks the strategy that forms the groups in the actuated mode by name, an unknown name prints the available ones")
+        (@arg replay: -r --replay +takes_value "Replays a session of an events.jsonl, or an old *_received.log, instead of running the server. Writes a *_replayed.log and lists how it differs from what was sent")
+        (@arg session: --session +takes_value "The session of the event log to replay, by default the first one")
+        (@arg recorded: --recorded +takes_value "The sent log to compare the replay of a *_received.log with, by default the *_sent.log next to it")
+        (@arg speed: --speed +takes_value "Ticks of the replay per second, 1 is the original time. Without it the replay runs as fast as possible")
         (@arg json: -j --json +takes_value "Determines how the json output is encoded. Takes none, null or empty as the value.
             none:  Sends only the {banan} json vec.
             null:  Sends the complete {banen, busbanen, stoplichten} json, where the empty ones will be null.
@@ -53,25 +70,187 @@ fn main() {
         None => println!("Incorrect -j value!"),
     }
 
-    let ip = matches.value_of("ip").unwrap();
+    let config = match matches.value_of("config") {
+        Some(path) => match CrossroadConfig::from_file(path) {
+            Ok(config) => Some(config),
+            Err(err) => {
+                println!("Could not load crossroad config {:?}: {}", path, err);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+
+    let mode = match matches.value_of("mode").map(ControlMode::from_str) {
+        Some(None) => {
+            println!("Incorrect -m value!");
+            std::process::exit(1);
+        },
+        Some(mode) => mode,
+        None => None,
+    };
+
+    let registry = StrategyRegistry::new();
+    let strategy = match matches.value_of("strategy").map(|name| (name, registry.create(name))) {
+        Some((name, None)) => {
+            println!("Unknown strategy {:?}, choose from {:?}", name, registry.names());
+            std::process::exit(1);
+        },
+        Some((_, strategy)) => strategy,
+        None => None,
+    };
+
+    let crossroad = match create_crossroad(&config, mode, strategy).and_then(check_crossroad) {
+        Ok(crossroad) => crossroad,
+        Err(err) => {
+            println!("Refusing to start: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(received) = matches.value_of("replay") {
+        let speed = match matches.value_of("speed").map(|s| s.parse::<f64>()) {
+            Some(Ok(speed)) if speed > 0.0 => Some(speed),
+            Some(_) => {
+                println!("Incorrect --speed value!");
+                std::process::exit(1);
+            },
+            None => None,
+        };
+        let replayed = match received.ends_with(".jsonl") {
+            true  => run_event_log_replay(received, matches.value_of("session"), speed, crossroad),
+            false => run_replay(received, matches.value_of("recorded"), speed, crossroad),
+        };
+        match replayed {
+            Ok(0) => std::process::exit(0),
+            Ok(_) => std::process::exit(2),
+            Err(err) => {
+                println!("Could not replay {:?}: {}", received, err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let ip = match matches.value_of("ip") {
+        Some(ip) => ip,
+        None => {
+            println!("Needs the ip to run the server on, see --help");
+            std::process::exit(1);
+        }
+    };
     let port = matches.value_of("port").unwrap_or("9990");
     let address = format!("{}:{}", ip, port);
 
     println!("\nJson compatibility level = {:?} ", j_str);
-    run_server(&*address).unwrap();
+    run_server(&*address, crossroad).unwrap();
+}
+
+fn create_crossroad(config: &Option<CrossroadConfig>, mode: Option<ControlMode>, strategy: Option<Box<ControlStrategy>>) -> Result<Crossroad> {
+    let mut crossroad = match *config {
+        Some(ref c) => try!(c.create_crossroad(c.create_traffic_controls(c.create_traffic_lights()))),
+        None => default_crossroad::default_crossroad(),
+    };
+
+    if let Some(mode) = mode {
+        crossroad.mode = mode;
+    }
+    if let Some(strategy) = strategy {
+        crossroad.strategy = strategy;
+    }
+    if crossroad.mode == ControlMode::FixedTime && crossroad.fixed_time.is_none() {
+        return Err(Error::Config("fixed time mode, but the crossroad has no fixed time plan".to_string()));
+    }
+
+    println!("Control mode = {:?}, strategy = {}", crossroad.mode, crossroad.strategy.name());
+    Ok(crossroad)
+}
+
+/// Validates the conflicts and the fixed time stages once, so mistakes are reported at startup instead of on the first client.
+fn check_crossroad(crossroad: Crossroad) -> Result<Crossroad> {
+    let issues = conflict_matrix::validate(&crossroad);
+    for issue in &issues {
+        println!("Conflict error: {}", issue);
+    }
+
+    match issues.len() {
+        0 => Ok(crossroad),
+        n => Err(Error::Config(format!("found {} conflict error(s)", n))),
+    }
 }
 
-fn run_server<A>(address: A) -> io::Result<()> where A: ToSocketAddrs + Display {
+/// Returns the number of differences with the recorded sent log.
+fn run_replay(received_path: &str, recorded_path: Option<&str>, speed: Option<f64>, crossroad: Crossroad) -> Result<usize> {
+    let recorded_path = recorded_path.map(|p| p.to_string()).unwrap_or(received_path.replace("_received.log", "_sent.log"));
+    let replayed_path = match recorded_path.ends_with("_sent.log") {
+        true  => recorded_path.replace("_sent.log", "_replayed.log"),
+        false => format!("{}_replayed.log", recorded_path),
+    };
+
+    let received = try!(replay::read_log(received_path));
+    let recorded = try!(replay::read_log(&recorded_path));
+    let day = replay::log_day(received_path).unwrap_or(time::at_utc(Timespec::new(0, 0)));
+
+    let mut replayer = Replay::new(received, &recorded, day);
+    replayer.tick_ms = speed.map(|speed| (TICK_MS as f64 / speed) as u32);
+
+    println!("========== REPLAY: {} from {} until {}", received_path, day.strftime("%F").unwrap(), replay::LogEntry { at: replayer.until, message: String::new() }.timestamp());
+    let replayed = replayer.run(crossroad);
+    compare_replay(&recorded, &recorded_path, &replayed, &replayed_path)
+}
+
+/// Replays one session of an event log and compares it with the messages that session sent.
+fn run_event_log_replay(path: &str, session: Option<&str>, speed: Option<f64>, crossroad: Crossroad) -> Result<usize> {
+    let replayed_path = format!("{}_replayed.log", path.trim_right_matches(".jsonl"));
+
+    let records = try!(event_log::read_records(path));
+    let session = match session.or(records.first().map(|r| &*r.session)) {
+        Some(session) => session.to_string(),
+        None => return Err(Error::Other("the event log is empty".to_string())),
+    };
+    let (mut replayer, recorded) = try!(replay::from_event_log(&records, &session));
+    replayer.tick_ms = speed.map(|speed| (TICK_MS as f64 / speed) as u32);
+
+    println!("========== REPLAY: session {} of {} from {} {} until {}", session, path,
+        replayer.day.strftime("%F").unwrap(), replay::LogEntry { at: replayer.start, message: String::new() }.timestamp(),
+        replay::LogEntry { at: replayer.until, message: String::new() }.timestamp());
+
+    // The event log holds the messages as json values, so the replayed ones are written the same way.
+    let replayed = replayer.run(crossroad).into_iter().map(replay::normalized).collect();
+    compare_replay(&recorded, path, &replayed, &replayed_path)
+}
+
+/// Writes the replayed log and returns the number of differences with the recorded messages.
+fn compare_replay(recorded: &Vec<replay::LogEntry>, recorded_path: &str, replayed: &Vec<replay::LogEntry>, replayed_path: &str) -> Result<usize> {
+    try!(File::create(replayed_path).and_then(|mut f| f.write_all(replay::write_log(replayed).as_bytes())));
+
+    let differences = replay::diff(recorded, replayed, replay::DEFAULT_TOLERANCE);
+    for difference in &differences {
+        println!("{}", difference);
+    }
+
+    println!("========== REPLAY: {} messages recorded in {}, {} replayed into {}, {} difference(s)",
+        recorded.len(), recorded_path, replayed.len(), replayed_path, differences.len());
+    Ok(differences.len())
+}
+
+fn run_server<A>(address: A, crossroad: Crossroad) -> io::Result<()> where A: ToSocketAddrs + Display {
 
     let listener = try!(TcpListener::bind(&address));
     println!("Server listening on: {}", address);
 
+    // All sessions write to the same event log.
+    let events = Arc::new(Mutex::new(try!(RotatingFile::open(&crossroad.event_log))));
+    println!("Event log: {}", crossroad.event_log.path(0).display());
+
     // Infinite loop.
     for tcp_stream in listener.incoming().filter_map(|i| i.ok()) {
+        // Every client gets its own copy of the crossroad.
+        let client_crossroad = crossroad.clone();
+        let client_events = events.clone();
         thread::spawn(move || {
             println!("Connecting a new client");
 
-            match handle_client(tcp_stream) {
+            match handle_client(tcp_stream, client_crossroad, client_events) {
                 Ok(_) => println!("Client disconnected normally."),
                 Err(v) => println!("Client error {:?}", v),
             };
@@ -81,124 +260,124 @@ fn run_server<A>(address: A) -> io::Result<()> where A: ToSocketAddrs + Display
     Ok(())
 }
 
-fn handle_client(client_stream: TcpStream) -> io::Result<()> {
-
-    let (log_file_recv, log_file_sent) = create_log_files(&client_stream).expect("log files");
+fn handle_client(client_stream: TcpStream, crossroad: Crossroad, events: Arc<Mutex<RotatingFile>>) -> io::Result<()> {
 
     // Convert stream to buffered streams
     let client_reader = BufReader::new(try!(client_stream.try_clone()));
-    let client_writer = BufWriter::new(client_stream);
+    let client_writer = BufWriter::new(try!(client_stream.try_clone()));
 
     // Main thread uses this channel to send (json) updates to the client.
     let (out_transmitter, out_receiver) = channel::<String>();
-    let (exit_main_loop_tx, exit_main_loop_rx) = channel();
 
     // Getting updates from the simulator(client) via a socket, so make it safe with reference counter + a mutex.
     let client_baan_sensor_states = Arc::new(Mutex::new(SensorStates::new()));
 
+    // Sensor timestamps and the main loop share one clock.
+    let clock: Arc<Clock> = Arc::new(WallClock::new());
+
+    // The receiver reports every message and the end of the connection to it, the main loop checks it every tick.
+    let watchdog = Arc::new(Mutex::new(Watchdog::new(crossroad.watchdog, clock.now())));
+
+    // The receiver logs the incoming messages, the main loop the outgoing ones and the decisions.
+    let peer = try!(client_stream.peer_addr()).to_string();
+    let mut log = EventLog::new(format!("{}@{}", peer, time::get_time().sec), events);
+    log.session_event(clock.now(), &SessionEvent::Connected { peer: peer });
+    println!("Session {}", log.session());
+    let log = Arc::new(Mutex::new(log));
+
     // Run seperate threads
-    let client_receiver_handle = spawn_client_sensor_receiver(client_reader, client_baan_sensor_states.clone(), log_file_recv);
-    let client_updater_handle = spawn_client_updater(client_writer, out_receiver, log_file_sent);
-    let verkeersregelinstallatie_handle = spawn_main_loop(out_transmitter, exit_main_loop_rx, client_baan_sensor_states.clone());
+    let client_receiver_handle = spawn_client_sensor_receiver(client_reader, client_baan_sensor_states.clone(), watchdog.clone(), clock.clone(), log.clone());
+    let client_updater_handle = spawn_client_updater(client_writer, out_receiver);
+    let verkeersregelinstallatie_handle = spawn_main_loop(clock.clone(), out_transmitter, watchdog.clone(), client_baan_sensor_states.clone(), log.clone(), crossroad);
 
     println!("Connection established");
 
-    // Wait for threads to exit
+    // The main loop ends once the connection is gone and the lights are safe. That drops its sender, which ends
+    // the updater, and shutting the socket down ends a receiver that still waits for a line.
+    if let Err(v) = verkeersregelinstallatie_handle.join() {
+        println!("main loop panicked {:?}", v);
+    }
+    client_stream.shutdown(Shutdown::Both);
+
     if let Err(v) = client_updater_handle.join().and(client_receiver_handle.join()) {
         println!("client disconnected, error {:?}", v);
     }
-
-    exit_main_loop_tx.send(0);
-    verkeersregelinstallatie_handle.join();
     Ok(())
 }
 
-fn create_log_files(client_stream: &TcpStream) -> io::Result<(File, File)> {
-    let ip = try!(client_stream.local_addr().map(|sock| sock.ip()));
-    let file_name = format!("{}_{}",  time::now().strftime("%e-%m-%G_%k%M_").unwrap(), ip);
-    let path_in = format!("{}_{}.log", file_name, "received");
-    let path_out = format!("{}_{}.log", file_name, "sent");
-
-    let mut o = OpenOptions::new();
-    let u = o.create(true).append(true);
-
-    let log_file_recv = try!(u.open(Path::new(&path_in)));
-    let log_file_sent = try!(u.open(Path::new(&path_out)));
-
-    Ok((log_file_recv, log_file_sent))
-}
-
-fn spawn_main_loop( out_tx: Sender<String>,
-                    exit_rx: Receiver<u8>,
-                    sensor_shared_state: Arc<Mutex<SensorStates>>)
+fn spawn_main_loop( clock: Arc<Clock>,
+                    out_tx: Sender<String>,
+                    watchdog: Arc<Mutex<Watchdog>>,
+                    sensor_shared_state: Arc<Mutex<SensorStates>>,
+                    log: Arc<Mutex<EventLog>>,
+                    crossroad: Crossroad)
                     -> JoinHandle<Result<()>>
  {
     thread::spawn(move || {
 
-        let traffic_lights = default_crossroad::create_traffic_lights();
-        let traffic_controls = default_crossroad::create_traffic_controls(&traffic_lights);
-        let crossroad = default_crossroad::create_crossroad(&traffic_controls);
-
-        let mut crossroad_state = CrossroadState::AllRed;
-        let mut time = 0; // seconds
-
-        let frequency_scheduler = sched::periodic_ms(1000);
+        let mut session = Session::new(crossroad);
 
         if true { // TESTS
-            // crossroad.send_all_bulk(&out_tx, JsonState::Groen);
-            // crossroad.send_all(&out_tx, JsonState::Geel);
-            // crossroad.send_all_bulk(&out_tx, JsonState::Rood);
+            // out_tx.send(changes_json_str(&controller.crossroad().set_all(JsonState::Groen)));
+            // out_tx.send(changes_json_str(&controller.crossroad().set_all(JsonState::Rood)));
         }
 
         loop {
-            time = time + 1;
-            frequency_scheduler.recv().unwrap();
-            if let Ok(exit_loop) = exit_rx.try_recv() {
-                break;
-            }
+            let time = clock.tick(); // seconds
+            log.lock().unwrap().set_tick(time);
+
+            let event = watchdog.lock().unwrap().check(clock.now());
 
             print!("\n     {:?} ", time);
 
-            match crossroad.run_loop(time, &mut crossroad_state, sensor_shared_state.clone(), &out_tx) {
-                Some(newstate) => crossroad_state = newstate,
-                None => (),
-            };
+            let sensors = sensor_shared_state.lock().unwrap().snapshot();
+            let messages = session.tick(time, clock.now(), &sensors, event);
+
+            {
+                let ref mut log = *log.lock().unwrap();
+                for decision in session.take_events() {
+                    log.decision(clock.now(), &decision);
+                }
+                for msg in &messages {
+                    log.sent(clock.now(), msg);
+                }
+            }
+
+            // The updater only stops when it can't write to the client anymore.
+            if messages.into_iter().any(|msg| out_tx.send(msg).is_err()) {
+                watchdog.lock().unwrap().disconnect();
+            }
+
+            if !watchdog.lock().unwrap().is_connected() && session.controller().in_safe_state() {
+                println!("========== SESSION ENDED: the lights are {:?} at {}", session.controller().crossroad().watchdog.safe_state, time);
+                break;
+            }
         }
 
         Ok(())
     })
 }
 
-fn spawn_client_sensor_receiver(mut reader: BufReader<TcpStream>, sensor_data: Arc<Mutex<SensorStates>>, mut log_file: File) -> JoinHandle<Result<()>> {
+fn spawn_client_sensor_receiver(mut reader: BufReader<TcpStream>, sensor_data: Arc<Mutex<SensorStates>>, watchdog: Arc<Mutex<Watchdog>>, clock: Arc<Clock>, log: Arc<Mutex<EventLog>>) -> JoinHandle<Result<()>> {
 
     thread::spawn(move || {
         loop {
             let mut line = String::new();
 
-            try!(reader.read_line(&mut line));
+            // Nothing read means the client closed the connection, it is logged so a replay disconnects as well.
+            let read = reader.read_line(&mut line);
+            if read.as_ref().map_or(true, |&n| n == 0) {
+                log.lock().unwrap().session_event(clock.now(), &SessionEvent::Disconnected);
+                watchdog.lock().unwrap().disconnect();
+                return read.map(|_| ()).map_err(Error::from);
+            }
+            log.lock().unwrap().received(clock.now(), line.trim_right());
             let ref mut traffic_state = *sensor_data.lock().unwrap();
 
-            log_file.write(format!("\n{}\n", time::now().strftime("%T").unwrap()).as_bytes());
-            log_file.write_all(&line.as_bytes());
-
             match serde_json::from_str::<ProtocolJson>(&line) {
                 Ok(protocol_obj) => {
-
-                    if let Some(ref banen) = protocol_obj.banen {
-
-                        if banen.len() > 0 {
-                            traffic_state.update(banen);
-                            //println!("Client->Server: received baan sensor update: {:?} new_state = {:?}", banen, traffic_state)
-                        }
-                    }
-
-                    if let Some(ref busbanen) = protocol_obj.busbanen {
-
-                        if busbanen.len() > 0 {
-                            traffic_state.update_bussen(busbanen);
-                            println!("Client->Server: received BUSBAAN sensor update: {:?} new_state = {:?}", busbanen, traffic_state)
-                        }
-                    }
+                    watchdog.lock().unwrap().message(clock.now());
+                    traffic_state.receive(&protocol_obj, clock.now());
                 },
                 Err(err) => println!("Client->Server: received faulty json string {:?}", line),
             }
@@ -206,14 +385,11 @@ fn spawn_client_sensor_receiver(mut reader: BufReader<TcpStream>, sensor_data: A
     })
 }
 
-fn spawn_client_updater(mut writer: BufWriter<TcpStream>, rx: Receiver<String>, mut log_file: File) -> thread::JoinHandle<Result<()>> {
+fn spawn_client_updater(mut writer: BufWriter<TcpStream>, rx: Receiver<String>) -> thread::JoinHandle<Result<()>> {
     thread::spawn(move || {
         loop {
             match rx.recv() {
                 Ok(msg) => {
-                    log_file.write(format!("\n\n{}\n", time::now().strftime("%T").unwrap()).as_bytes());
-                    log_file.write_all(&msg.as_bytes());
-
                     try!(writer.write(format!("{}\r\n", &msg).as_bytes()));
                     try!(writer.flush());
                     println!("Server->Client: sent new stoplicht state {:?}", msg);
@@ -235,71 +411,6 @@ fn send_json() {
     println!("before:\n{:?}\n\nafter:\n{:?}", stoplicht, stringified);
 }
 
-#[test]
-fn main_loop() {
-
-    let (out_transmitter, out_receiver) = channel::<String>();
-    let (exit_main_loop_tx, exit_main_loop_rx) = channel();
-    let client_baan_sensor_states = Arc::new(Mutex::new(SensorStates::new()));
-
-    let sensor_shared_state = client_baan_sensor_states.clone();
-    {
-        let ref mut init_state = *sensor_shared_state.lock().unwrap();
-
-        let now = time::now();
-
-        init_state._debug_update_directly(vec![
-            /*
-            Sensor { id: 6,  bezet: true, last_update: now - Duration::seconds(1000) },
-            Sensor { id: 5,  bezet: true, last_update: now - Duration::seconds(5) },
-            Sensor { id: 11, bezet: true, last_update: now - Duration::seconds(10) },
-            Sensor { id: 4,  bezet: true, last_update: now - Duration::seconds(50) },
-            Sensor { id: 9,  bezet: true, last_update: now - Duration::seconds(14) },
-            Sensor { id: 7,  bezet: true, last_update: now - Duration::seconds(5) },
-            Sensor { id: 13, bezet: true, last_update: now - Duration::seconds(6) },*/
-        ]);
-    }
-
-    let sensor_shared_state_copy = client_baan_sensor_states.clone();
-    thread::spawn(move || {
-
-        thread::sleep_ms(13000);
-        {
-            println!("Sending simulated state #1: sensor 13 = true");
-            let ref mut traffic_state = *sensor_shared_state_copy.lock().unwrap();
-            traffic_state._debug_update_directly(vec![Sensor { id: 13, bezet: true, last_update: time::now() },]);
-        }
-
-        thread::sleep_ms(16000);
-        {
-            println!("Sending simulated state #2: sensor 13 = false");
-            let ref mut traffic_state = *sensor_shared_state_copy.lock().unwrap();
-            traffic_state._debug_update_directly(vec![Sensor { id: 13, bezet: false, last_update: time::now() },]);
-        }
-
-        thread::sleep_ms(25000);
-        {
-            println!("Sending simulated state #3: sensor 9 = true");
-            let ref mut traffic_state = *sensor_shared_state_copy.lock().unwrap();
-            traffic_state._debug_update_directly(vec![Sensor { id: 9, bezet: true, last_update: time::now() },]);
-        }
-    });
-
-    let verkeer = spawn_main_loop(out_transmitter, exit_main_loop_rx, client_baan_sensor_states.clone());
-
-    loop {
-        match out_receiver.recv() {
-            Ok(msg) => {
-                println!("Server->Client: sent new stoplicht state {:?}", msg)
-            },
-            Err(err) => {
-                println!("{:?}", err);
-                break;
-            }
-        };
-    }
-}
-
 #[test]
 fn time_max() {
 
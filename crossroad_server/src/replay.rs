@@ -0,0 +1,362 @@
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+use serde_json::{self, Value};
+use time;
+
+use error::{Result, Error};
+use traffic_protocol::*;
+use crossroad::Crossroad;
+use clock::{Clock, SimulatedClock};
+use session::Session;
+use watchdog::Watchdog;
+use event_log::{LogRecord, RecordKind};
+
+/// Seconds a replayed message may be sent earlier or later than the recorded one and still match it.
+/// The logs only have whole seconds, and the server's ticks don't start at a whole second.
+pub const DEFAULT_TOLERANCE: i32 = 1;
+
+/// Written to the received log when the client disconnects, stands for the disconnect of an event log.
+pub const DISCONNECTED: &'static str = "-- disconnected --";
+
+const DAY: i32 = 24 * 3600;
+
+
+// -------------------------------------------------------------------------------
+// Session logs
+// -------------------------------------------------------------------------------
+
+/// One message of a session log, `at` in seconds since midnight of the day the session started.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogEntry {
+    pub at: i32,
+    pub message: String,
+}
+
+impl LogEntry {
+    /// The time of day as the server writes it, "HH:MM:SS".
+    pub fn timestamp(&self) -> String {
+        let second = self.at % DAY;
+        format!("{:02}:{:02}:{:02}", second / 3600, second / 60 % 60, second % 60)
+    }
+}
+
+/// Reads a `*_received.log` or `*_sent.log`: a timestamp line before every message, blank lines between them.
+/// A timestamp earlier than the one before it is on the next day.
+pub fn parse_log(text: &str) -> Result<Vec<LogEntry>> {
+    let mut entries = vec![];
+    let mut at = None;
+    let mut day = 0;
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+
+        match (parse_timestamp(line), at) {
+            (_, _) if line.is_empty() => (),
+            (Some(second), Some(previous)) if second + day < previous => {
+                day += DAY;
+                at = Some(second + day);
+            },
+            (Some(second), _) => at = Some(second + day),
+            (None, Some(at)) => entries.push(LogEntry { at: at, message: line.to_string() }),
+            (None, None) => return Err(Error::Other(format!("line {}: expected a timestamp before {:?}", number + 1, line))),
+        }
+    }
+    Ok(entries)
+}
+
+pub fn read_log<P: AsRef<Path>>(path: P) -> Result<Vec<LogEntry>> {
+    let mut text = String::new();
+    try!(File::open(path).and_then(|mut f| f.read_to_string(&mut text)));
+    parse_log(&text)
+}
+
+/// Writes entries like the server writes its `*_sent.log`.
+pub fn write_log(entries: &Vec<LogEntry>) -> String {
+    entries.iter().map(|e| format!("\n\n{}\n{}", e.timestamp(), e.message)).collect()
+}
+
+fn parse_timestamp(line: &str) -> Option<i32> {
+    let parts: Vec<&str> = line.split(':').collect();
+    if parts.len() != 3 || parts.iter().any(|p| p.len() != 2) {
+        return None;
+    }
+
+    match (parts[0].parse::<i32>(), parts[1].parse::<i32>(), parts[2].parse::<i32>()) {
+        (Ok(h), Ok(m), Ok(s)) if h < 24 && m < 60 && s < 60 => Some(h * 3600 + m * 60 + s),
+        _ => None,
+    }
+}
+
+/// Midnight of the day in the name the server gives its logs, "%e-%m-%G_%k%M_..."
+pub fn log_day(path: &str) -> Option<time::Tm> {
+    let name = match Path::new(path).file_name().and_then(|n| n.to_str()) {
+        Some(name) => name,
+        None => return None,
+    };
+
+    let date: Vec<i32> = name.split('_').next().unwrap_or("").trim().split('-').filter_map(|p| p.parse().ok()).collect();
+    match date.len() {
+        3 => {
+            let tm = time::Tm { tm_mday: date[0], tm_mon: date[1] - 1, tm_year: date[2] - 1900, ..time::empty_tm() };
+            Some(time::at_utc(tm.to_timespec()))
+        },
+        _ => None,
+    }
+}
+
+/// The messages one session of an event log received and sent, with the second of the tick that handled them.
+/// A message that came in after tick 4 is handled by tick 5, the session starts at tick 0.
+pub fn from_event_log(records: &Vec<LogRecord>, session: &str) -> Result<(Replay, Vec<LogEntry>)> {
+    let records: Vec<&LogRecord> = records.iter().filter(|r| r.session == session).collect();
+
+    // The event log uses the clock of the controller, so the seconds line up with its ticks.
+    let started = match records.first().and_then(|r| time::strptime(&r.at.chars().take(19).collect::<String>(), "%Y-%m-%dT%H:%M:%S").ok()) {
+        Some(started) => started,
+        None => return Err(Error::Other(format!("no session {:?} in the event log", session))),
+    };
+    let start = (started.tm_hour * 60 + started.tm_min) * 60 + started.tm_sec - records[0].tick;
+    let day = time::at_utc(time::Tm { tm_hour: 0, tm_min: 0, tm_sec: 0, ..started }.to_timespec());
+
+    let mut received = vec![];
+    let mut recorded = vec![];
+    for record in records {
+        let at = start + record.tick;
+        match (record.kind, &record.payload) {
+            (RecordKind::In, payload) => received.push(LogEntry { at: at + 1, message: message_of(payload) }),
+            (RecordKind::Out, payload) => recorded.push(LogEntry { at: at, message: message_of(payload) }),
+            (RecordKind::Session, &Value::String(ref event)) if event == "Disconnected" =>
+                received.push(LogEntry { at: at + 1, message: DISCONNECTED.to_string() }),
+            _ => (),
+        }
+    }
+
+    let until = received.iter().chain(recorded.iter()).map(|e| e.at).max().unwrap_or(start);
+    Ok((Replay { received: received, day: day, start: start, until: until, tick_ms: None }, recorded))
+}
+
+/// A replayed message written like the event log writes it, as a json value.
+pub fn normalized(entry: LogEntry) -> LogEntry {
+    match serde_json::from_str::<Value>(&entry.message) {
+        Ok(value) => LogEntry { message: message_of(&value), ..entry },
+        Err(_) => entry,
+    }
+}
+
+/// A received line that wasn't json is stored as a string, it is replayed as it came in.
+fn message_of(payload: &Value) -> String {
+    match *payload {
+        Value::String(ref line) => line.clone(),
+        ref value => serde_json::to_string(value).unwrap(),
+    }
+}
+
+
+// -------------------------------------------------------------------------------
+// Replay
+// -------------------------------------------------------------------------------
+
+/// Feeds the messages of a received log into a new session at the second they were received,
+/// and records what the session sends back.
+#[derive(Debug, Clone)]
+pub struct Replay {
+    pub received: Vec<LogEntry>,
+    /// Midnight of the day the session started.
+    pub day: time::Tm,
+    /// The second before the first tick.
+    pub start: i32,
+    /// The second of the last tick.
+    pub until: i32,
+    /// Real time per tick, None replays as fast as possible.
+    pub tick_ms: Option<u32>,
+}
+
+impl Replay {
+
+    /// The session runs from the first message of either log until the last one.
+    pub fn new(received: Vec<LogEntry>, recorded: &Vec<LogEntry>, day: time::Tm) -> Replay {
+        let times: Vec<i32> = received.iter().chain(recorded.iter()).map(|e| e.at).collect();
+        let start = times.iter().cloned().min().unwrap_or(0) - 1;
+        let until = times.iter().cloned().max().unwrap_or(0);
+
+        Replay { received: received, day: day, start: start, until: until, tick_ms: None }
+    }
+
+    pub fn run(&self, crossroad: Crossroad) -> Vec<LogEntry> {
+        let mut session = Session::new(crossroad);
+        let clock = SimulatedClock::starting_at(self.day + time::Duration::seconds(self.start as i64));
+        let mut watchdog = Watchdog::new(session.controller().crossroad().watchdog, clock.now());
+        let mut sensors = SensorStates::new();
+        let mut received = self.received.iter().peekable();
+        let mut sent = vec![];
+
+        for _ in self.start..self.until {
+            let time = clock.tick();
+            let second = self.start + time;
+
+            while received.peek().map_or(false, |e| e.at <= second) {
+                let entry = received.next().unwrap();
+
+                // A faulty message is skipped, like the server does.
+                if entry.message == DISCONNECTED {
+                    watchdog.disconnect();
+                }
+                else if let Ok(message) = serde_json::from_str::<ProtocolJson>(&entry.message) {
+                    watchdog.message(clock.now());
+                    sensors.receive(&message, clock.now());
+                }
+            }
+
+            let event = watchdog.check(clock.now());
+            for message in session.tick(time, clock.now(), &sensors, event) {
+                sent.push(LogEntry { at: second, message: message });
+            }
+
+            // Like the server, the session ends once the lights are safe after a disconnect.
+            if !watchdog.is_connected() && session.controller().in_safe_state() {
+                break;
+            }
+            if let Some(ms) = self.tick_ms {
+                thread::sleep(Duration::from_millis(ms as u64));
+            }
+        }
+        sent
+    }
+}
+
+
+// -------------------------------------------------------------------------------
+// Diff
+// -------------------------------------------------------------------------------
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LogDifference {
+    /// Recorded, but the replay didn't send it within the tolerance.
+    Missing(LogEntry),
+    /// Sent by the replay, but not recorded.
+    Extra(LogEntry),
+}
+
+impl fmt::Display for LogDifference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LogDifference::Missing(ref e) => write!(f, "- {} {}", e.timestamp(), e.message),
+            LogDifference::Extra(ref e) => write!(f, "+ {} {}", e.timestamp(), e.message),
+        }
+    }
+}
+
+/// Matches every recorded message with the same replayed message at most `tolerance` seconds away,
+/// the ones without a match are the differences, in order of time.
+pub fn diff(recorded: &Vec<LogEntry>, replayed: &Vec<LogEntry>, tolerance: i32) -> Vec<LogDifference> {
+    let mut matched = vec![false; replayed.len()];
+    let mut differences = vec![];
+    let mut first = 0;
+
+    for entry in recorded {
+        while first < replayed.len() && replayed[first].at < entry.at - tolerance {
+            first += 1;
+        }
+
+        let found = (first..replayed.len())
+            .take_while(|&i| replayed[i].at <= entry.at + tolerance)
+            .find(|&i| !matched[i] && replayed[i].message == entry.message);
+
+        match found {
+            Some(i) => matched[i] = true,
+            None => differences.push(LogDifference::Missing(entry.clone())),
+        }
+    }
+
+    differences.extend(replayed.iter().zip(matched.iter()).filter(|&(_, &m)| !m).map(|(e, _)| LogDifference::Extra(e.clone())));
+    differences.sort_by_key(|d| match *d {
+        LogDifference::Missing(ref e) | LogDifference::Extra(ref e) => e.at,
+    });
+    differences
+}
+
+
+#[cfg(test)]
+fn received_log() -> String {
+    // Like the server logs it, the line of the client still ends in a newline.
+    "\n12:00:13\n{\"banen\":[{\"id\":13,\"bezet\":true}]}\n\n12:00:29\n{\"banen\":[{\"id\":13,\"bezet\":false}]}\n\n12:00:40\nnot json\n".to_string()
+}
+
+#[test]
+fn session_logs_are_read_back() {
+    let entries = parse_log(&received_log()).unwrap();
+    assert_eq!(entries.len(), 3);
+    assert_eq!(entries[0], LogEntry { at: 12 * 3600 + 13, message: "{\"banen\":[{\"id\":13,\"bezet\":true}]}".to_string() });
+    assert_eq!(entries[2].timestamp(), "12:00:40");
+
+    // Past midnight.
+    let entries = parse_log("\n23:59:59\na\n\n00:00:01\nb").unwrap();
+    assert_eq!(entries.iter().map(|e| e.at).collect::<Vec<_>>(), vec![DAY - 1, DAY + 1]);
+    assert_eq!(parse_log(&write_log(&entries)).unwrap(), entries);
+
+    assert!(parse_log("no timestamp").is_err());
+    assert_eq!(log_day("logs/ 8-05-2017_ 958__127.0.0.1_received.log").map(|tm| (tm.tm_mday, tm.tm_mon, tm.tm_wday)), Some((8, 4, 1)));
+}
+
+#[test]
+fn replay_reproduces_its_own_recording() {
+    let create = ::default_crossroad::default_crossroad;
+
+    let received = parse_log(&received_log()).unwrap();
+    let mut replay = Replay::new(received, &vec![], time::empty_tm());
+    replay.until += 20;
+    let recorded = replay.run(create());
+
+    // 13 turned green and red again.
+    assert!(recorded.iter().any(|e| e.message.contains("{\"id\":13,\"status\":2}")));
+    assert_eq!(diff(&recorded, &replay.run(create()), DEFAULT_TOLERANCE), vec![]);
+
+    // A message a second later still matches, one that is gone or two seconds later doesn't.
+    let mut changed = recorded.clone();
+    changed[0].at += 1;
+    let moved = changed.remove(1);
+    changed.push(LogEntry { at: moved.at + 2, ..moved.clone() });
+    changed.sort_by_key(|e| e.at);
+
+    assert_eq!(diff(&recorded, &changed, DEFAULT_TOLERANCE), vec![
+        LogDifference::Missing(moved.clone()),
+        LogDifference::Extra(LogEntry { at: moved.at + 2, ..moved }),
+    ]);
+}
+
+#[test]
+fn event_log_sessions_are_replayed() {
+    use event_log::EVENT_LOG_VERSION;
+
+    let record = |session: &str, tick: i32, kind: RecordKind, payload: &str| LogRecord {
+        version: EVENT_LOG_VERSION, session: session.to_string(), seq: 0, elapsed_ms: 0,
+        at: format!("2017-05-08T12:00:{:02}+02:00", tick), tick: tick, kind: kind,
+        payload: serde_json::from_str(payload).unwrap(),
+    };
+    let records = vec![
+        record("a", 0, RecordKind::Session, "{\"Connected\":{\"peer\":\"127.0.0.1:1234\"}}"),
+        record("b", 0, RecordKind::In, "{\"banen\":[]}"),
+        record("a", 12, RecordKind::In, "{\"banen\":[{\"id\":13,\"bezet\":true}]}"),
+        record("a", 12, RecordKind::In, "\"not json\""),
+        record("a", 20, RecordKind::Out, "{\"stoplichten\":[{\"id\":13,\"status\":2}]}"),
+        record("a", 30, RecordKind::Session, "\"Disconnected\""),
+    ];
+
+    let (replay, recorded) = from_event_log(&records, "a").unwrap();
+    let noon = 12 * 3600;
+    assert_eq!((replay.day.tm_mday, replay.start, replay.until), (8, noon, noon + 31));
+    assert_eq!(replay.received.iter().map(|e| (e.at, &*e.message)).collect::<Vec<_>>(), vec![
+        (noon + 13, "{\"banen\":[{\"bezet\":true,\"id\":13}]}"),
+        (noon + 13, "not json"),
+        (noon + 31, DISCONNECTED),
+    ]);
+    assert_eq!(recorded, vec![LogEntry { at: noon + 20, message: "{\"stoplichten\":[{\"id\":13,\"status\":2}]}".to_string() }]);
+    assert!(from_event_log(&records, "c").is_err());
+
+    // The replay sends what the server would, 13 turns green after the primary lanes cleared.
+    let replayed: Vec<LogEntry> = replay.run(::default_crossroad::default_crossroad()).into_iter().map(normalized).collect();
+    assert!(replayed.iter().any(|e| e.message.contains("{\"id\":13,\"status\":2}")));
+}
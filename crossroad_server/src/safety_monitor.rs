@@ -0,0 +1,223 @@
+use std::fmt;
+use time;
+
+use traffic_protocol::*;
+use crossroad::*;
+use conflict_matrix::ConflictMatrix;
+use intergreen::IntergreenMatrix;
+use controller::{Controller, SignalChange};
+
+
+// -------------------------------------------------------------------------------
+// Incident
+// -------------------------------------------------------------------------------
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Violation {
+    /// `light` would be green while the conflicting `other` is not red.
+    Conflict { light: usize, other: usize },
+    /// `light` would be green before `other` has cleared, which it does at `cleared_at`.
+    Intergreen { light: usize, other: usize, cleared_at: i32 },
+}
+
+/// A message the monitor didn't let through.
+#[derive(Debug, Clone)]
+pub struct Incident {
+    pub time: i32,
+    pub at: time::Tm,
+    pub violation: Violation,
+    pub blocked: Vec<SignalChange>,
+}
+
+impl fmt::Display for Incident {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.violation {
+            Violation::Conflict { light, other } =>
+                write!(f, "light {} would turn green while conflicting light {} is not red at {}", light, other, self.time),
+            Violation::Intergreen { light, other, cleared_at } =>
+                write!(f, "light {} would turn green at {} before light {} has cleared at {}", light, self.time, other, cleared_at),
+        }
+    }
+}
+
+
+// -------------------------------------------------------------------------------
+// SafetyMonitor
+// -------------------------------------------------------------------------------
+
+/// Sits between the controller and the client. Keeps its own image of what every light shows and
+/// checks every outgoing message against the conflict matrix and the intergreen times,
+/// without using any of the controller's state.
+pub struct SafetyMonitor {
+    conflicts: ConflictMatrix,
+    intergreen: IntergreenMatrix,
+    aspects: Vec<JsonState>,
+    green_ended: Vec<Option<i32>>,
+    incidents: Vec<Incident>,
+}
+
+impl SafetyMonitor {
+
+    pub fn new(crossroad: &Crossroad) -> SafetyMonitor {
+        let size = crossroad.intergreen.size();
+        SafetyMonitor {
+            conflicts: crossroad.conflicts.clone(),
+            intergreen: crossroad.intergreen.clone(),
+            aspects: vec![JsonState::Rood; size],
+            green_ended: vec![None; size],
+            incidents: vec![],
+        }
+    }
+
+    /// What every light shows according to the messages that were let through, indexed by light id.
+    pub fn aspects(&self) -> &Vec<JsonState> {
+        &self.aspects
+    }
+
+    pub fn incidents(&self) -> &Vec<Incident> {
+        &self.incidents
+    }
+
+    /// Checks one message. It is let through completely or not at all, a blocked message is recorded.
+    pub fn check(&mut self, time: i32, now: time::Tm, changes: &Vec<SignalChange>) -> Result<(), Incident> {
+        let mut aspects = self.aspects.clone();
+        let mut green_ended = self.green_ended.clone();
+
+        for change in changes {
+            if is_green(change.state) && !is_green(aspects[change.id]) {
+                if let Some(violation) = self.violation(change.id, time, &aspects, &green_ended) {
+                    let incident = Incident { time: time, at: now, violation: violation, blocked: changes.clone() };
+                    println!("========== SAFETY MONITOR: blocked {:?}, {}", changes, incident);
+                    self.incidents.push(incident.clone());
+                    return Err(incident);
+                }
+            }
+
+            // Traffic that drove on flashing yellow clears like traffic that drove on green.
+            let was_moving = is_green(aspects[change.id]) || aspects[change.id] == JsonState::GeelKnipperend;
+            if was_moving && !is_green(change.state) && change.state != JsonState::GeelKnipperend {
+                green_ended[change.id] = Some(time);
+            }
+            aspects[change.id] = change.state;
+        }
+
+        self.aspects = aspects;
+        self.green_ended = green_ended;
+        Ok(())
+    }
+
+    /// Checks a step of the controller. A blocked message puts the controller in the fail-safe state,
+    /// starting from what the lights really show.
+    pub fn guard(&mut self, time: i32, now: time::Tm, controller: &mut Controller, changes: Vec<SignalChange>) -> Vec<SignalChange> {
+        match self.check(time, now, &changes) {
+            Ok(()) => changes,
+            Err(incident) => {
+                controller.fail_safe(format!("{}", incident));
+                controller.restore_signals(&self.aspects);
+                vec![]
+            },
+        }
+    }
+
+    fn violation(&self, light: usize, time: i32, aspects: &Vec<JsonState>, green_ended: &Vec<Option<i32>>) -> Option<Violation> {
+        for other in (0..aspects.len()).filter(|&other| self.conflicts.is_conflicting(light, other)) {
+            if aspects[other] != JsonState::Rood {
+                return Some(Violation::Conflict { light: light, other: other });
+            }
+            if let Some(ended) = green_ended[other] {
+                let cleared_at = ended + self.intergreen.get(other, light);
+                if time < cleared_at {
+                    return Some(Violation::Intergreen { light: light, other: other, cleared_at: cleared_at });
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Traffic may drive.
+fn is_green(state: JsonState) -> bool {
+    match state {
+        JsonState::Rood | JsonState::Geel | JsonState::GeelKnipperend => false,
+        _ => true,
+    }
+}
+
+
+#[test]
+fn monitor_blocks_conflicting_green() {
+    let crossroad = ::default_crossroad::default_crossroad();
+    let now = time::empty_tm();
+
+    let mut monitor = SafetyMonitor::new(&crossroad);
+    assert!(monitor.check(1, now, &vec![SignalChange { id: 2, state: JsonState::Groen }]).is_ok());
+
+    let incident = monitor.check(2, now, &vec![SignalChange { id: 13, state: JsonState::Groen }]).unwrap_err();
+    assert_eq!(incident.violation, Violation::Conflict { light: 13, other: 2 });
+    assert_eq!(monitor.aspects()[13], JsonState::Rood);
+
+    // Yellow still conflicts, red doesn't until the intergreen time has passed.
+    monitor.check(10, now, &vec![SignalChange { id: 2, state: JsonState::Geel }]).unwrap();
+    assert!(monitor.check(11, now, &vec![SignalChange { id: 13, state: JsonState::Groen }]).is_err());
+    monitor.check(14, now, &vec![SignalChange { id: 2, state: JsonState::Rood }]).unwrap();
+
+    let cleared_at = 10 + crossroad.intergreen.get(2, 13);
+    let incident = monitor.check(cleared_at - 1, now, &vec![SignalChange { id: 13, state: JsonState::Groen }]).unwrap_err();
+    assert_eq!(incident.violation, Violation::Intergreen { light: 13, other: 2, cleared_at: cleared_at });
+    assert!(monitor.check(cleared_at, now, &vec![SignalChange { id: 13, state: JsonState::Groen }]).is_ok());
+    assert_eq!(monitor.incidents().len(), 3);
+}
+
+#[test]
+fn monitor_lets_the_controller_through() {
+    use clock::*;
+
+    let mut controller = Controller::new(::default_crossroad::default_crossroad());
+    let mut monitor = SafetyMonitor::new(controller.crossroad());
+
+    let clock = SimulatedClock::new();
+    let mut sensors = SensorStates::new();
+
+    for _ in 0..600 {
+        let time = clock.tick();
+        let active: Vec<Baan> = (0..BAAN_COUNT).map(|id| Baan { id: id, bezet: (time as usize + id * 7) % 45 < 15 }).collect();
+        sensors.update(&active, clock.now());
+
+        let changes = controller.step(clock.now(), &sensors);
+        monitor.guard(time, clock.now(), &mut controller, changes);
+    }
+
+    assert_eq!(monitor.incidents().len(), 0);
+    assert!(controller.fault().is_none());
+}
+
+#[test]
+fn blocked_message_puts_the_crossroad_in_fail_safe() {
+    use clock::*;
+    use traffic_controls::LightId;
+    use fixed_time::Stage;
+
+    let mut crossroad = ::default_crossroad::default_crossroad();
+
+    // A broken plan that starts 2 and 13 together.
+    let conflicting = vec![crossroad.topology.control_id_of(LightId(2)).unwrap(), crossroad.topology.control_id_of(LightId(13)).unwrap()];
+    crossroad.mode = ControlMode::FixedTime;
+    crossroad.fixed_time.as_mut().unwrap().stages = vec![Stage { controls: conflicting, start: 0, end: 120 }];
+
+    let mut controller = Controller::new(crossroad);
+    let mut monitor = SafetyMonitor::new(controller.crossroad());
+    let clock = SimulatedClock::new();
+    let sensors = SensorStates::new();
+    let mut sent = vec![];
+
+    for _ in 0..10 {
+        let time = clock.tick();
+        let changes = controller.step(clock.now(), &sensors);
+        sent.extend(monitor.guard(time, clock.now(), &mut controller, changes));
+    }
+
+    assert!(!sent.iter().any(|c| c.state == JsonState::Groen));
+    assert_eq!(monitor.incidents().len(), 1);
+    assert!(controller.fault().is_some());
+    assert!(monitor.aspects().iter().all(|&s| s == JsonState::GeelKnipperend));
+}
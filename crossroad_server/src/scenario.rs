@@ -0,0 +1,345 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use error::{Result, Error};
+use traffic_protocol::*;
+use crossroad::*;
+use session::Session;
+use clock::{Clock, SimulatedClock};
+use strategy::StrategyRegistry;
+
+/// Ticks a scenario runs after its last event and expectation, unless it says `run`.
+pub const DEFAULT_RUN_AFTER: i32 = 30;
+
+
+// -------------------------------------------------------------------------------
+// Scenario
+// -------------------------------------------------------------------------------
+
+/// What the client tells the controller at one tick of a scenario.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScenarioEvent {
+    Sensor { id: usize, bezet: bool },
+    Bus { id: usize, bezet: bool, line: i32, delay: i32 },
+    Emergency { id: usize, bezet: bool },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expectation {
+    /// The light turns green at a tick from `from` until `to`, both included.
+    GreenBetween { light: usize, from: i32, to: i32 },
+    /// The light shows `state` at tick `time`.
+    StateAt { light: usize, state: JsonState, time: i32 },
+    /// The two lights are never green at the same tick.
+    NeverGreenTogether { a: usize, b: usize },
+    /// The light doesn't turn green during the whole run.
+    NeverGreen { light: usize },
+    /// The controller didn't fall back to flashing.
+    NoFault,
+}
+
+/// Sensor events at simulated ticks and what the lights have to do, run against the default crossroad.
+///
+/// One statement per line, `#` starts a comment:
+///
+/// ```text
+/// mode actuated                  # actuated, fixed or flashing
+/// strategy lookahead
+/// run 60                         # ticks, defaults to 30 after the last tick mentioned
+///
+/// t=13 sensor 13 on
+/// t=20 bus 15 on line 3 delay 70
+/// t=40 emergency 8 on
+///
+/// expect light 13 green between t=14 and t=30
+/// expect light 13 red at t=40
+/// expect never green together: 6 and 8
+/// expect light 5 never green
+/// expect no fault
+/// ```
+#[derive(Debug, Clone)]
+pub struct Scenario {
+    pub name: String,
+    pub mode: Option<ControlMode>,
+    pub strategy: Option<String>,
+    pub ticks: i32,
+    pub events: Vec<(i32, ScenarioEvent)>,
+    pub expectations: Vec<Expectation>,
+}
+
+impl Scenario {
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Scenario> {
+        let mut text = String::new();
+        try!(File::open(&path).and_then(|mut f| f.read_to_string(&mut text)));
+        Scenario::from_str(&path.as_ref().display().to_string(), &text)
+    }
+
+    pub fn from_str(name: &str, text: &str) -> Result<Scenario> {
+        let mut scenario = Scenario { name: name.to_string(), mode: None, strategy: None, ticks: 0, events: vec![], expectations: vec![] };
+        let mut run = None;
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let error = |message: &str| Error::Scenario(format!("{}:{}: {} in '{}'", name, number + 1, message, line));
+
+            if words.is_empty() {
+                continue;
+            }
+
+            match words[0] {
+                "mode" if words.len() == 2 => scenario.mode = Some(try!(ControlMode::from_str(words[1]).ok_or(error("unknown mode")))),
+                "strategy" if words.len() == 2 => scenario.strategy = Some(words[1].to_string()),
+                "run" if words.len() == 2 => run = Some(try!(number_of(words[1]).ok_or(error("expected a number of ticks")))),
+                "expect" => scenario.expectations.push(try!(parse_expectation(&words[1..]).ok_or(error("unknown expectation")))),
+                at if at.starts_with("t=") => {
+                    let time = try!(tick(at).ok_or(error("expected t=<tick>")));
+                    scenario.events.push((time, try!(parse_event(&words[1..]).ok_or(error("unknown event")))));
+                },
+                _ => return Err(error("unknown statement")),
+            }
+        }
+
+        let last = scenario.events.iter().map(|&(time, _)| time)
+            .chain(scenario.expectations.iter().map(|e| match *e {
+                Expectation::GreenBetween { to, .. } => to,
+                Expectation::StateAt { time, .. } => time,
+                _ => 0,
+            }))
+            .max().unwrap_or(0);
+        scenario.ticks = run.unwrap_or(last + DEFAULT_RUN_AFTER);
+        Ok(scenario)
+    }
+
+    /// Runs the scenario on `crossroad` under a simulated clock, events of a tick are sent before its step.
+    /// Steps go through a session, like the server does, so the safety monitor checks them.
+    pub fn run(&self, crossroad: Crossroad) -> Result<Timeline> {
+        let mut crossroad = crossroad;
+        if let Some(mode) = self.mode {
+            crossroad.mode = mode;
+        }
+        if let Some(ref name) = self.strategy {
+            crossroad.strategy = try!(StrategyRegistry::new().create(name).ok_or(Error::Scenario(format!("{}: unknown strategy {:?}", self.name, name))));
+        }
+
+        let mut session = Session::new(crossroad);
+        let clock = SimulatedClock::new();
+        let mut sensors = SensorStates::new();
+        let mut signals = vec![session.controller().signals().clone()];
+
+        for _ in 0..self.ticks {
+            let time = clock.tick();
+            for &(_, ref event) in self.events.iter().filter(|&&(at, _)| at == time) {
+                match *event {
+                    ScenarioEvent::Sensor { id, bezet } => sensors.update(&vec![Baan { id: id, bezet: bezet }], clock.now()),
+                    ScenarioEvent::Bus { id, bezet, line, delay } =>
+                        sensors.update_bussen(&vec![BusBaan { id: id, eerstvolgendelijn: line, bezet: bezet, vertraging: delay }], clock.now()),
+                    ScenarioEvent::Emergency { id, bezet } =>
+                        sensors.update_hulpdiensten(&vec![Hulpdienst { id: Some(id), richting: None, bezet: bezet }], clock.now()),
+                }
+            }
+
+            session.tick(time, clock.now(), &sensors, None);
+            signals.push(session.controller().signals().clone());
+        }
+
+        Ok(Timeline { signals: signals, fault: session.controller().fault().cloned() })
+    }
+
+    /// Runs the scenario on the default crossroad, returns a message for every expectation that failed.
+    pub fn verify(&self) -> Result<Vec<String>> {
+        let timeline = try!(self.run(::default_crossroad::default_crossroad()));
+
+        Ok(self.expectations.iter()
+            .filter_map(|e| timeline.check(e).err().map(|message| format!("{}: {:?}: {}", self.name, e, message)))
+            .collect())
+    }
+}
+
+fn number_of(word: &str) -> Option<i32> {
+    word.parse().ok()
+}
+
+fn tick(word: &str) -> Option<i32> {
+    match word.starts_with("t=") {
+        true  => number_of(&word[2..]),
+        false => None,
+    }
+}
+
+fn light(word: &str) -> Option<usize> {
+    match word.parse() {
+        Ok(id) if id < BAAN_COUNT => Some(id),
+        _ => None,
+    }
+}
+
+fn on_off(word: &str) -> Option<bool> {
+    match word {
+        "on"  => Some(true),
+        "off" => Some(false),
+        _     => None,
+    }
+}
+
+fn state(word: &str) -> Option<JsonState> {
+    match word {
+        "red"      => Some(JsonState::Rood),
+        "yellow"   => Some(JsonState::Geel),
+        "green"    => Some(JsonState::Groen),
+        "flashing" => Some(JsonState::GeelKnipperend),
+        _          => None,
+    }
+}
+
+/// The words at the `_` of `pattern`, when the other words are the same as in `pattern`.
+fn fields<'a>(words: &[&'a str], pattern: &str) -> Option<Vec<&'a str>> {
+    let pattern: Vec<&str> = pattern.split_whitespace().collect();
+    if words.len() != pattern.len() {
+        return None;
+    }
+
+    let mut fields = vec![];
+    for (&word, &expected) in words.iter().zip(pattern.iter()) {
+        match expected {
+            "_" => fields.push(word),
+            _ if word == expected => (),
+            _ => return None,
+        }
+    }
+    Some(fields)
+}
+
+macro_rules! some {
+    ($e:expr) => (match $e { Some(v) => v, None => return None })
+}
+
+fn parse_event(words: &[&str]) -> Option<ScenarioEvent> {
+    if let Some(f) = fields(words, "sensor _ _") {
+        return Some(ScenarioEvent::Sensor { id: some!(light(f[0])), bezet: some!(on_off(f[1])) });
+    }
+    if let Some(f) = fields(words, "emergency _ _") {
+        return Some(ScenarioEvent::Emergency { id: some!(light(f[0])), bezet: some!(on_off(f[1])) });
+    }
+
+    // The line and the delay of a bus are optional.
+    let bus = fields(words, "bus _ _ line _ delay _")
+        .or_else(|| fields(words, "bus _ _ line _").map(|mut f| { f.push("0"); f }))
+        .or_else(|| fields(words, "bus _ _").map(|mut f| { f.extend(vec!["0", "0"]); f }));
+
+    bus.and_then(|f| Some(ScenarioEvent::Bus {
+        id: some!(light(f[0])),
+        bezet: some!(on_off(f[1])),
+        line: some!(number_of(f[2])),
+        delay: some!(number_of(f[3])),
+    }))
+}
+
+fn parse_expectation(words: &[&str]) -> Option<Expectation> {
+    if let Some(f) = fields(words, "light _ green between _ and _") {
+        return Some(Expectation::GreenBetween { light: some!(light(f[0])), from: some!(tick(f[1])), to: some!(tick(f[2])) });
+    }
+    if let Some(f) = fields(words, "light _ never green") {
+        return Some(Expectation::NeverGreen { light: some!(light(f[0])) });
+    }
+    if let Some(f) = fields(words, "light _ _ at _") {
+        return Some(Expectation::StateAt { light: some!(light(f[0])), state: some!(state(f[1])), time: some!(tick(f[2])) });
+    }
+    if let Some(f) = fields(words, "never green together: _ and _") {
+        return Some(Expectation::NeverGreenTogether { a: some!(light(f[0])), b: some!(light(f[1])) });
+    }
+    fields(words, "no fault").map(|_| Expectation::NoFault)
+}
+
+
+// -------------------------------------------------------------------------------
+// Timeline
+// -------------------------------------------------------------------------------
+
+/// What every light showed after every tick of a run, tick 0 is before the first step.
+#[derive(Debug, Clone)]
+pub struct Timeline {
+    pub signals: Vec<Vec<JsonState>>,
+    pub fault: Option<String>,
+}
+
+impl Timeline {
+
+    pub fn state(&self, light: usize, time: i32) -> Option<JsonState> {
+        self.signals.get(time as usize).and_then(|signals| signals.get(light)).cloned()
+    }
+
+    /// Green or one of the bus aspects.
+    pub fn is_green(&self, light: usize, time: i32) -> bool {
+        match self.state(light, time) {
+            Some(JsonState::Rood) | Some(JsonState::Geel) | Some(JsonState::GeelKnipperend) | None => false,
+            _ => true,
+        }
+    }
+
+    /// The ticks at which the light turned green.
+    pub fn green_starts(&self, light: usize) -> Vec<i32> {
+        (1..self.signals.len() as i32).filter(|&t| self.is_green(light, t) && !self.is_green(light, t - 1)).collect()
+    }
+
+    pub fn check(&self, expectation: &Expectation) -> ::std::result::Result<(), String> {
+        match *expectation {
+            Expectation::GreenBetween { light, from, to } => {
+                match self.green_starts(light).iter().any(|&t| t >= from && t <= to) {
+                    true  => Ok(()),
+                    false => Err(format!("turned green at {:?}", self.green_starts(light))),
+                }
+            },
+            Expectation::StateAt { light, state, time } => {
+                match self.state(light, time) {
+                    Some(shown) if shown == state => Ok(()),
+                    shown => Err(format!("showed {:?}", shown)),
+                }
+            },
+            Expectation::NeverGreenTogether { a, b } => {
+                match (0..self.signals.len() as i32).find(|&t| self.is_green(a, t) && self.is_green(b, t)) {
+                    Some(t) => Err(format!("both green at {}", t)),
+                    None => Ok(()),
+                }
+            },
+            Expectation::NeverGreen { light } => {
+                match self.green_starts(light).first() {
+                    Some(t) => Err(format!("turned green at {}", t)),
+                    None => Ok(()),
+                }
+            },
+            Expectation::NoFault => {
+                match self.fault {
+                    Some(ref fault) => Err(format!("fell back to flashing: {}", fault)),
+                    None => Ok(()),
+                }
+            },
+        }
+    }
+}
+
+
+#[test]
+fn scenario_parses_events_and_expectations() {
+    let scenario = Scenario::from_str("test", "
+        # A car at 13.
+        t=13 sensor 13 on
+        t=29 sensor 13 off   # gone again
+        expect light 13 green between t=14 and t=30
+        expect never green together: 2 and 13
+    ").unwrap();
+
+    assert_eq!(scenario.events, vec![(13, ScenarioEvent::Sensor { id: 13, bezet: true }), (29, ScenarioEvent::Sensor { id: 13, bezet: false })]);
+    assert_eq!(scenario.expectations, vec![
+        Expectation::GreenBetween { light: 13, from: 14, to: 30 },
+        Expectation::NeverGreenTogether { a: 2, b: 13 },
+    ]);
+    assert_eq!(scenario.ticks, 30 + DEFAULT_RUN_AFTER);
+
+    match Scenario::from_str("test", "t=13 sensor 13 maybe") {
+        Err(Error::Scenario(message)) => assert_eq!(message, "test:1: unknown event in 't=13 sensor 13 maybe'"),
+        other => panic!("expected a scenario error, got {:?}", other.map(|_| ())),
+    }
+}
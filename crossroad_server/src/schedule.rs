@@ -0,0 +1,290 @@
+use time;
+
+use error::{Result, Error};
+use traffic_controls::*;
+use crossroad::*;
+use config::ScheduleConfig;
+
+
+// -------------------------------------------------------------------------------
+// Weekday
+// -------------------------------------------------------------------------------
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Weekday {
+    Monday, Tuesday, Wednesday, Thursday, Friday, Saturday, Sunday
+}
+
+impl Weekday {
+    pub fn from_tm(tm: &time::Tm) -> Weekday {
+        match tm.tm_wday {
+            1 => Weekday::Monday,
+            2 => Weekday::Tuesday,
+            3 => Weekday::Wednesday,
+            4 => Weekday::Thursday,
+            5 => Weekday::Friday,
+            6 => Weekday::Saturday,
+            _ => Weekday::Sunday,
+        }
+    }
+
+    pub fn previous(&self) -> Weekday {
+        match *self {
+            Weekday::Monday    => Weekday::Sunday,
+            Weekday::Tuesday   => Weekday::Monday,
+            Weekday::Wednesday => Weekday::Tuesday,
+            Weekday::Thursday  => Weekday::Wednesday,
+            Weekday::Friday    => Weekday::Thursday,
+            Weekday::Saturday  => Weekday::Friday,
+            Weekday::Sunday    => Weekday::Saturday,
+        }
+    }
+}
+
+
+// -------------------------------------------------------------------------------
+// Schedule
+// -------------------------------------------------------------------------------
+
+/// A control mode with its own timings, the timings are set on top of the ones the crossroad was built with.
+#[derive(Debug, Clone)]
+pub struct Plan {
+    pub name: String,
+    pub mode: ControlMode,
+    pub timings: Vec<(ControlId, Timings)>,
+}
+
+/// On the listed days from `from` until `to`, in minutes since midnight.
+/// When `to` is before `from` the window runs past midnight into the next day.
+#[derive(Debug, Clone)]
+pub struct TimeWindow {
+    pub days: Vec<Weekday>,
+    pub from: i32,
+    pub to: i32,
+    pub plan: usize,
+}
+
+impl TimeWindow {
+    pub fn contains(&self, day: Weekday, minute: i32) -> bool {
+        if self.from <= self.to {
+            self.days.contains(&day) && minute >= self.from && minute < self.to
+        }
+        else {
+            (self.days.contains(&day) && minute >= self.from) || (self.days.contains(&day.previous()) && minute < self.to)
+        }
+    }
+}
+
+/// Picks the plan for the time of day, the first matching window wins and `default` runs outside all windows.
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    pub plans: Vec<Plan>,
+    pub windows: Vec<TimeWindow>,
+    pub default: usize,
+    base_timings: Vec<Timings>,
+}
+
+impl Schedule {
+
+    pub fn new(config: &ScheduleConfig, topology: &Topology) -> Result<Schedule> {
+        let mut plans: Vec<Plan> = vec![];
+
+        for plan in &config.plans {
+            if plans.iter().any(|p| p.name == plan.name) {
+                return Err(Error::Config(format!("schedule has two plans named '{}'", plan.name)));
+            }
+
+            let mut timings = vec![];
+            for entry in &plan.timings {
+                for &id in &entry.ids {
+                    let control = try!(topology.control_of(LightId(id)).ok_or(Error::Config(format!("unknown traffic light id {} in plan '{}'", id, plan.name))));
+                    timings.push((control.id, entry.timings));
+                }
+            }
+
+            plans.push(Plan { name: plan.name.clone(), mode: plan.mode, timings: timings });
+        }
+
+        let mut windows = vec![];
+        let default;
+        {
+            let find_plan = |name: &String| plans.iter().position(|p| &p.name == name).ok_or(Error::Config(format!("schedule refers to unknown plan '{}'", name)));
+
+            for window in &config.windows {
+                windows.push(TimeWindow {
+                    days: window.days.clone(),
+                    from: try!(parse_minutes(&window.from)),
+                    to: try!(parse_minutes(&window.to)),
+                    plan: try!(find_plan(&window.plan)),
+                });
+            }
+            default = try!(find_plan(&config.default));
+        }
+
+        Ok(Schedule {
+            plans: plans,
+            windows: windows,
+            default: default,
+            base_timings: topology.controls().iter().map(|c| c.timings).collect(),
+        })
+    }
+
+    pub fn plan_at(&self, now: &time::Tm) -> usize {
+        let day = Weekday::from_tm(now);
+        let minute = now.tm_hour * 60 + now.tm_min;
+
+        self.windows.iter()
+            .find(|window| window.contains(day, minute))
+            .map(|window| window.plan)
+            .unwrap_or(self.default)
+    }
+
+    pub fn plan(&self, index: usize) -> &Plan {
+        &self.plans[index]
+    }
+
+    /// Restores the timings the crossroad was built with, then sets the timings of the plan on top.
+    pub fn apply(&self, index: usize, topology: &mut Topology) {
+        for (position, &timings) in self.base_timings.iter().enumerate() {
+            topology.set_timings(ControlId(position), timings);
+        }
+        for &(id, timings) in &self.plan(index).timings {
+            let combined = topology.control(id).timings.with(&timings);
+            topology.set_timings(id, combined);
+        }
+    }
+}
+
+/// "HH:MM" to minutes since midnight.
+pub fn parse_minutes(s: &str) -> Result<i32> {
+    let parts: Vec<&str> = s.split(':').collect();
+    let error = || Error::Config(format!("invalid time '{}', expected HH:MM", s));
+
+    if parts.len() != 2 {
+        return Err(error());
+    }
+
+    let hours: i32 = try!(parts[0].trim().parse().map_err(|_| error()));
+    let minutes: i32 = try!(parts[1].trim().parse().map_err(|_| error()));
+
+    // 24:00 is the end of the day.
+    if hours < 0 || minutes < 0 || minutes > 59 || hours * 60 + minutes > 24 * 60 {
+        return Err(error());
+    }
+
+    Ok(hours * 60 + minutes)
+}
+
+
+// -------------------------------------------------------------------------------
+// PlanTransition
+// -------------------------------------------------------------------------------
+
+#[derive(Debug, Clone)]
+pub struct PlanTransition {
+    pub time: i32,
+    pub at: time::Tm,
+    pub from: Option<String>,
+    pub to: String,
+}
+
+
+#[cfg(test)]
+fn schedule_json() -> String {
+    include_str!("../default_crossroad.json").replace("\"mode\": \"Actuated\",", r#""mode": "Actuated",
+    "schedule": {
+        "default": "daytime",
+        "plans": [
+            { "name": "rush hour", "mode": "Actuated", "timings": [{ "ids": [2, 4, 9, 11], "timings": { "min_green": 30 } }] },
+            { "name": "daytime", "mode": "Actuated" },
+            { "name": "midday", "mode": "FixedTime" }
+        ],
+        "windows": [
+            { "days": ["Monday", "Tuesday", "Wednesday", "Thursday", "Friday"], "from": "07:00", "to": "09:00", "plan": "rush hour" },
+            { "days": ["Monday", "Tuesday", "Wednesday", "Thursday", "Friday"], "from": "11:30", "to": "13:30", "plan": "midday" },
+            { "days": ["Saturday"], "from": "23:00", "to": "02:00", "plan": "midday" }
+        ]
+    },"#)
+}
+
+#[cfg(test)]
+fn schedule_crossroad() -> Crossroad {
+    let config = ::config::CrossroadConfig::from_str(&schedule_json()).unwrap();
+    config.create_crossroad(config.create_traffic_controls(config.create_traffic_lights())).unwrap()
+}
+
+/// 2017-05-01 was a monday.
+#[cfg(test)]
+fn monday_at(hour: i64, minute: i64) -> time::Tm {
+    time::at_utc(time::Timespec::new(1493596800 + hour * 3600 + minute * 60, 0))
+}
+
+#[test]
+fn schedule_picks_plan_by_weekday_and_time() {
+    let crossroad = schedule_crossroad();
+    let schedule = crossroad.schedule.as_ref().unwrap();
+    let name = |tm: time::Tm| schedule.plan(schedule.plan_at(&tm)).name.clone();
+
+    assert_eq!(name(monday_at(6, 59)), "daytime");
+    assert_eq!(name(monday_at(7, 0)), "rush hour");
+    assert_eq!(name(monday_at(12, 0)), "midday");
+    assert_eq!(name(monday_at(13, 30)), "daytime");
+
+    // Saturday night runs into sunday, but sunday night doesn't.
+    assert_eq!(name(monday_at(24 * 5 + 23, 30)), "midday");
+    assert_eq!(name(monday_at(24 * 6 + 1, 0)), "midday");
+    assert_eq!(name(monday_at(24 * 6 + 23, 30)), "daytime");
+    assert_eq!(name(monday_at(24 * 7 + 7, 30)), "rush hour");
+}
+
+#[test]
+fn plan_timings_are_set_on_top_of_the_crossroad_timings() {
+    let mut crossroad = schedule_crossroad();
+    let schedule = crossroad.schedule.clone().unwrap();
+    let rush_hour = schedule.plans.iter().position(|p| p.name == "rush hour").unwrap();
+    let daytime = schedule.plans.iter().position(|p| p.name == "daytime").unwrap();
+
+    schedule.apply(rush_hour, &mut crossroad.topology);
+    assert_eq!(crossroad.topology.control_of(LightId(2)).unwrap().timings().min_green, 30);
+    assert_eq!(crossroad.topology.control_of(LightId(6)).unwrap().timings().min_green, 5);
+
+    schedule.apply(daytime, &mut crossroad.topology);
+    assert_eq!(crossroad.topology.control_of(LightId(2)).unwrap().timings().min_green, 5);
+}
+
+#[test]
+fn controller_switches_plans_at_safe_points() {
+    use traffic_protocol::*;
+    use clock::*;
+    use controller::Controller;
+
+    let mut controller = Controller::new(schedule_crossroad());
+    let clock = SimulatedClock::starting_at(monday_at(11, 29));
+    let mut sensors = SensorStates::new();
+    let mut status = vec![JsonState::Rood; BAAN_COUNT];
+
+    // Traffic keeps the actuated state machine busy with signal groups when midday starts.
+    for _ in 0..300 {
+        let time = clock.tick();
+        let bezet = time % 40 < 20;
+        sensors.update(&vec![Baan { id: 1, bezet: bezet }, Baan { id: 8, bezet: !bezet }], clock.now());
+
+        let was_fixed_time = controller.crossroad().mode == ControlMode::FixedTime;
+        let was_all_red = status.iter().all(|&s| s == JsonState::Rood);
+
+        for change in controller.step(clock.now(), &sensors) {
+            status[change.id] = change.state;
+        }
+
+        // The first switch to the fixed time plan happens when every light is red.
+        if !was_fixed_time && controller.crossroad().mode == ControlMode::FixedTime {
+            assert!(was_all_red, "switched to the fixed time plan with lights that were not red at {}", time);
+        }
+    }
+
+    let transitions = controller.transitions();
+    assert_eq!(transitions.len(), 2);
+    assert_eq!((transitions[0].from.clone(), transitions[0].to.clone()), (None, "daytime".to_string()));
+    assert_eq!((transitions[1].from.clone(), transitions[1].to.clone()), (Some("daytime".to_string()), "midday".to_string()));
+    assert!(transitions[1].at.tm_hour == 11 && transitions[1].at.tm_min >= 30);
+}
@@ -0,0 +1,308 @@
+use time;
+
+use error::Result;
+use traffic_protocol::*;
+use traffic_controls::*;
+use config::SensorHealthConfig;
+use schedule::parse_minutes;
+
+
+// -------------------------------------------------------------------------------
+// SensorHealthPolicy
+// -------------------------------------------------------------------------------
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SensorFault {
+    /// Occupied for longer than `max_occupied`.
+    StuckOn,
+    /// No vehicle for longer than the `max_idle` of the time of day.
+    StuckOff,
+}
+
+/// What a faulty sensor is replaced by until it works again.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Fallback {
+    /// The lane always requests green, the green isn't extended.
+    Request,
+    /// The lane always requests green, the green is extended up to its max green.
+    FixedExtension,
+    /// The sensor counts as free.
+    Ignore,
+}
+
+impl Default for Fallback {
+    fn default() -> Fallback {
+        Fallback::Request
+    }
+}
+
+/// From `from` until `to` in minutes since midnight, past midnight when `to` is before `from`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct IdleWindow {
+    pub from: i32,
+    pub to: i32,
+    pub max_idle: i32,
+}
+
+impl IdleWindow {
+    pub fn contains(&self, minute: i32) -> bool {
+        match self.from <= self.to {
+            true  => minute >= self.from && minute < self.to,
+            false => minute >= self.from || minute < self.to,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SensorHealthPolicy {
+    pub max_occupied: i32,
+    pub idle: Vec<IdleWindow>,
+    pub fallback: Fallback,
+}
+
+/// Occupied for five minutes, or no vehicle for half an hour during the day.
+impl Default for SensorHealthPolicy {
+    fn default() -> SensorHealthPolicy {
+        SensorHealthPolicy {
+            max_occupied: 300,
+            idle: vec![IdleWindow { from: 7 * 60, to: 19 * 60, max_idle: 1800 }],
+            fallback: Fallback::Request,
+        }
+    }
+}
+
+impl SensorHealthPolicy {
+
+    pub fn new(config: &SensorHealthConfig) -> Result<SensorHealthPolicy> {
+        let mut idle = vec![];
+        for window in &config.idle {
+            idle.push(IdleWindow { from: try!(parse_minutes(&window.from)), to: try!(parse_minutes(&window.to)), max_idle: window.max_idle });
+        }
+        Ok(SensorHealthPolicy { max_occupied: config.max_occupied, idle: idle, fallback: config.fallback })
+    }
+
+    /// The longest a sensor may go without a vehicle at `now`, the first matching window wins.
+    /// None when no traffic is expected.
+    pub fn max_idle(&self, now: &time::Tm) -> Option<i32> {
+        let minute = now.tm_hour * 60 + now.tm_min;
+        self.idle.iter().find(|w| w.contains(minute)).map(|w| w.max_idle)
+    }
+}
+
+
+// -------------------------------------------------------------------------------
+// SensorHealth
+// -------------------------------------------------------------------------------
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SensorStatus {
+    pub sensor: SensorId,
+    pub fault: SensorFault,
+    pub since: time::Tm,
+    pub fallback: Fallback,
+}
+
+/// Watches the sensors of every control for faults and replaces faulty ones by the fallback of the policy.
+/// A bus on a bus lane counts as traffic for the sensor of the lane.
+#[derive(Debug, Clone)]
+pub struct SensorHealth {
+    sensors: Vec<SensorId>,
+    last_active: Vec<Option<time::Tm>>,
+    last_update: Vec<time::Tm>,
+    last_bus_update: Vec<time::Tm>,
+    faults: Vec<SensorStatus>,
+}
+
+impl SensorHealth {
+
+    pub fn new(topology: &Topology) -> SensorHealth {
+        let sensors = (0..BAAN_COUNT).map(SensorId).filter(|&id| topology.sensor_control(id).is_some()).collect();
+        SensorHealth {
+            sensors: sensors,
+            last_active: vec![None; BAAN_COUNT],
+            last_update: vec![time::empty_tm(); BAAN_COUNT],
+            last_bus_update: vec![time::empty_tm(); BAAN_COUNT],
+            faults: vec![],
+        }
+    }
+
+    /// The sensors that are faulty now.
+    pub fn faults(&self) -> &Vec<SensorStatus> {
+        &self.faults
+    }
+
+    pub fn fault(&self, id: SensorId) -> Option<&SensorStatus> {
+        self.faults.iter().find(|s| s.sensor == id)
+    }
+
+    /// Checks every sensor, returns the ones that became faulty and the ones that recovered.
+    pub fn check(&mut self, now: time::Tm, policy: &SensorHealthPolicy, sensors: &SensorStates) -> (Vec<SensorStatus>, Vec<SensorStatus>) {
+        let mut started = vec![];
+        let mut ended = vec![];
+
+        for &id in &self.sensors {
+            let sensor = match sensors.sensor(id) {
+                Some(sensor) => sensor,
+                None => continue,
+            };
+
+            let bus = sensors.bus_sensor(id).map_or(false, |bus| bus.bezet || bus.last_update != self.last_bus_update[id.0]);
+            if sensor.bezet || bus || sensor.last_update != self.last_update[id.0] || self.last_active[id.0].is_none() {
+                self.last_active[id.0] = Some(now);
+            }
+            self.last_update[id.0] = sensor.last_update;
+            if let Some(bus) = sensors.bus_sensor(id) { self.last_bus_update[id.0] = bus.last_update; }
+
+            let stuck_on = sensor.bezet && sensor.waiting_since.map_or(false, |since| (now - since).num_seconds() > policy.max_occupied as i64);
+            let idle = self.last_active[id.0].map_or(0, |active| (now - active).num_seconds());
+            let stuck_off = policy.max_idle(&now).map_or(false, |max_idle| idle > max_idle as i64);
+
+            let fault = match (stuck_on, stuck_off) {
+                (true, _) => Some(SensorFault::StuckOn),
+                (_, true) => Some(SensorFault::StuckOff),
+                _ => None,
+            };
+
+            // A stuck-off sensor only recovers on a vehicle, not when the time of day stops expecting one.
+            let current = self.faults.iter().position(|s| s.sensor == id);
+            match (current, fault) {
+                (None, Some(fault)) => {
+                    let status = SensorStatus { sensor: id, fault: fault, since: now, fallback: policy.fallback };
+                    println!("========== SENSOR FAULT: sensor {} is {:?}, replaced by {:?}", id.0, fault, policy.fallback);
+                    self.faults.push(status);
+                    started.push(status);
+                },
+                (Some(index), None) if self.faults[index].fault == SensorFault::StuckOn || sensor.bezet || idle == 0 => {
+                    let status = self.faults.remove(index);
+                    println!("========== SENSOR FAULT: sensor {} works again", id.0);
+                    ended.push(status);
+                },
+                _ => (),
+            }
+        }
+
+        (started, ended)
+    }
+
+    /// The sensors with every faulty sensor replaced by its fallback.
+    pub fn apply(&self, sensors: &SensorStates) -> SensorStates {
+        let mut sensors = sensors.clone();
+
+        for status in &self.faults {
+            match (status.fault, status.fallback) {
+                (SensorFault::StuckOn, Fallback::Ignore) => sensors.force(status.sensor, false, status.since),
+                (SensorFault::StuckOff, Fallback::Request) |
+                (SensorFault::StuckOff, Fallback::FixedExtension) => sensors.force(status.sensor, true, status.since),
+                _ => (),
+            }
+        }
+        sensors
+    }
+
+    /// How the green of a faulty sensor is extended, None for a sensor that works.
+    pub fn extension(&self, id: SensorId) -> Option<bool> {
+        self.fault(id).and_then(|status| match status.fallback {
+            Fallback::Request => Some(false),
+            Fallback::FixedExtension => Some(true),
+            Fallback::Ignore => None,
+        })
+    }
+}
+
+
+#[cfg(test)]
+fn test_policy(fallback: Fallback) -> SensorHealthPolicy {
+    SensorHealthPolicy { max_occupied: 60, idle: vec![IdleWindow { from: 0, to: 24 * 60, max_idle: 120 }], fallback: fallback }
+}
+
+#[cfg(test)]
+fn controller_with(policy: SensorHealthPolicy) -> ::controller::Controller {
+    let mut crossroad = ::default_crossroad::default_crossroad();
+    crossroad.sensor_health = policy;
+    ::controller::Controller::new(crossroad)
+}
+
+#[test]
+fn idle_windows_follow_the_time_of_day() {
+    let policy = SensorHealthPolicy::default();
+    let at = |hour: i32| { let mut tm = time::empty_tm(); tm.tm_hour = hour; tm };
+
+    assert_eq!(policy.max_idle(&at(12)), Some(1800));
+    assert_eq!(policy.max_idle(&at(3)), None);
+    assert!(IdleWindow { from: 22 * 60, to: 6 * 60, max_idle: 60 }.contains(2 * 60));
+}
+
+#[test]
+fn stuck_on_sensor_is_ignored_and_recovers() {
+    use clock::*;
+    use audit::AuditEvent;
+
+    let mut policy = test_policy(Fallback::Ignore);
+    policy.idle = vec![];
+    let mut controller = controller_with(policy);
+    let clock = SimulatedClock::new();
+    let mut sensors = SensorStates::new();
+    sensors.update(&vec![Baan { id: 13, bezet: true }], clock.now());
+    let mut status = vec![JsonState::Rood; BAAN_COUNT];
+
+    for _ in 0..120 {
+        clock.tick();
+        for change in controller.step(clock.now(), &sensors) { status[change.id] = change.state; }
+    }
+
+    // Ignored, so the primary lanes are green again, 2 conflicts with 13.
+    assert_eq!(controller.sensor_health().faults().iter().map(|s| (s.sensor, s.fault)).collect::<Vec<_>>(), vec![(SensorId(13), SensorFault::StuckOn)]);
+    assert_eq!(status[2], JsonState::Groen);
+
+    sensors.update(&vec![Baan { id: 13, bezet: false }], clock.now());
+    clock.tick();
+    controller.step(clock.now(), &sensors);
+    assert!(controller.sensor_health().faults().is_empty());
+
+    let events: Vec<AuditEvent> = controller.audit().entries().iter().map(|e| e.event.clone()).collect();
+    assert_eq!(events, vec![
+        AuditEvent::SensorFault { sensor: 13, fault: SensorFault::StuckOn, fallback: Fallback::Ignore },
+        AuditEvent::SensorRecovered { sensor: 13, fault: SensorFault::StuckOn },
+    ]);
+}
+
+#[test]
+fn stuck_off_sensor_requests_green() {
+    use clock::*;
+
+    let mut controller = controller_with(test_policy(Fallback::Request));
+    let clock = SimulatedClock::new();
+    let sensors = SensorStates::new();
+
+    let mut green_13 = vec![];
+    for _ in 0..400 {
+        let time = clock.tick();
+        if controller.step(clock.now(), &sensors).iter().any(|c| c.id == 13 && c.state == JsonState::Groen) {
+            green_13.push(time);
+        }
+    }
+
+    // Nothing happens for the first two minutes, then 13 is served like a lane with a permanent request.
+    assert!(green_13.len() >= 2, "green at {:?}", green_13);
+    assert!(green_13[0] > 120);
+    assert!(controller.sensor_health().fault(SensorId(13)).is_some());
+}
+
+#[test]
+fn buses_keep_the_sensor_of_a_bus_lane_healthy() {
+    use clock::*;
+
+    let mut controller = controller_with(test_policy(Fallback::Request));
+    let clock = SimulatedClock::new();
+    let mut sensors = SensorStates::new();
+
+    // Only buses on 15, one every minute.
+    for _ in 0..400 {
+        let time = clock.tick();
+        sensors.update_bussen(&vec![BusBaan { id: 15, eerstvolgendelijn: 1, vertraging: 0, bezet: time % 60 < 5 }], clock.now());
+        controller.step(clock.now(), &sensors);
+    }
+
+    assert!(controller.sensor_health().fault(SensorId(15)).is_none());
+    assert!(controller.sensor_health().fault(SensorId(16)).is_some());
+}
@@ -0,0 +1,66 @@
+use time;
+
+use traffic_protocol::*;
+use crossroad::Crossroad;
+use controller::{Controller, ControllerEvent, SensorSnapshot};
+use safety_monitor::SafetyMonitor;
+use sensor_health::SensorStatus;
+use watchdog::WatchdogEvent;
+
+
+// -------------------------------------------------------------------------------
+// Session
+// -------------------------------------------------------------------------------
+
+/// The controller of one client, with everything the server does around a step but without the threads
+/// and the socket, so a recorded session can be replayed through the same code.
+pub struct Session {
+    controller: Controller,
+    monitor: SafetyMonitor,
+    sensor_faults: Vec<SensorStatus>,
+}
+
+impl Session {
+
+    pub fn new(crossroad: Crossroad) -> Session {
+        let controller = Controller::new(crossroad);
+        let monitor = SafetyMonitor::new(controller.crossroad());
+        Session { controller: controller, monitor: monitor, sensor_faults: vec![] }
+    }
+
+    pub fn controller(&self) -> &Controller {
+        &self.controller
+    }
+
+    /// The decisions of the controller since the last call.
+    pub fn take_events(&mut self) -> Vec<ControllerEvent> {
+        self.controller.take_events()
+    }
+
+    /// Handles what the watchdog saw, steps the controller past the safety monitor and returns the
+    /// messages for the client, in order.
+    pub fn tick(&mut self, time: i32, now: time::Tm, sensors: &SensorSnapshot, watchdog: Option<WatchdogEvent>) -> Vec<String> {
+        // A silent or lost client stops the signal program, it continues when the client talks again.
+        let safe_state = self.controller.crossroad().watchdog.safe_state;
+        match watchdog {
+            Some(WatchdogEvent::Expired { reason }) => self.controller.stop(reason, safe_state),
+            Some(WatchdogEvent::Restored) => self.controller.resume(),
+            None => (),
+        }
+
+        let changes = self.controller.step(now, sensors);
+        let changes = self.monitor.guard(time, now, &mut self.controller, changes);
+
+        // All changes of one tick go to the client in one message.
+        let mut messages = vec![];
+        if changes.len() > 0 {
+            messages.push(changes_json_str(&changes));
+        }
+
+        if self.controller.sensor_health().faults() != &self.sensor_faults {
+            self.sensor_faults = self.controller.sensor_health().faults().clone();
+            messages.push(sensor_faults_json_str(&self.sensor_faults));
+        }
+        messages
+    }
+}
@@ -1,6 +1,8 @@
 use traffic_protocol::*;
 use traffic_controls::*;
-use std::sync::mpsc::{Sender};
+use intergreen::*;
+use controller::SignalChange;
+use detector::Detectors;
 use std::intrinsics;
 
 
@@ -13,48 +15,47 @@ pub enum SignalGroupState {
 }
 
 #[derive(Debug, Clone)]
-pub struct SignalGroup<'a>  {
-    pub controls: Vec<ControlWithState<'a>>,
+pub struct SignalGroup {
+    pub controls: Vec<ControlWithState>,
     pub state: SignalGroupState,
     pub unlimited_green: bool,
     pub max_green: i32,
     pub is_bus: bool,
 }
 
-const MAX_GREEN_TEMP: i32 = 15;
+impl SignalGroup {
 
-impl<'a> SignalGroup<'a> {
-
-    pub fn new(controls: Vec<&'a Control>, unlimited_green: bool) -> SignalGroup<'a> {
+    pub fn new(controls: Vec<&Control>, unlimited_green: bool) -> SignalGroup {
         SignalGroup {
             controls: controls.iter().map(|c| ControlWithState::new(c)).collect(),
             state: SignalGroupState::Start,
             unlimited_green: unlimited_green,
-            max_green: MAX_GREEN_TEMP,
+            max_green: max_green(&controls),
             is_bus: false,
         }
     }
-    pub fn new_bus(controls: Vec<&'a Control>, unlimited_green: bool) -> SignalGroup<'a> {
+    /// Every bus lane with the aspect for the movement of its bus.
+    pub fn new_bus(controls: Vec<(&Control, JsonState)>, unlimited_green: bool) -> SignalGroup {
         SignalGroup {
-            controls: controls.iter().map(|c| ControlWithState::new(c)).collect(),
+            controls: controls.iter().map(|&(c, aspect)| ControlWithState::with_aspect(c, aspect)).collect(),
             state: SignalGroupState::Start,
             unlimited_green: unlimited_green,
-            max_green: MAX_GREEN_TEMP,
+            max_green: max_green(&controls.iter().map(|&(c, _)| c).collect()),
             is_bus: true,
         }
     }
 
-    pub fn empty() -> SignalGroup<'a> {
+    pub fn empty() -> SignalGroup {
         SignalGroup {
             controls: vec![],
             state: SignalGroupState::Start,
             unlimited_green: false,
-            max_green: MAX_GREEN_TEMP,
+            max_green: 0,
             is_bus: false,
          }
     }
 
-    pub fn clone_with(&self, state: SignalGroupState) -> SignalGroup<'a> {
+    pub fn clone_with(&self, state: SignalGroupState) -> SignalGroup {
         SignalGroup {
             controls: self.controls.clone(),
             state: state,
@@ -64,17 +65,20 @@ impl<'a> SignalGroup<'a> {
         }
     }
 
-    pub fn run_loop(&mut self, time: i32, out_tx: &Sender<String>, sensor_states: &SensorStates) -> Option<SignalGroupState> {
+    pub fn run_loop(&mut self, time: i32,
+                               changes: &mut Vec<SignalChange>,
+                               topology: &Topology,
+                               sensor_states: &SensorStates,
+                               detectors: &Detectors,
+                               clearance: &mut ClearanceState,
+                               intergreen: &IntergreenMatrix)
+                            -> Option<SignalGroupState> {
 
         match self.state {
 
             SignalGroupState::Start => {
                 println!("=> Starting ControlGroup");
-                let json_str = match self.is_bus {
-                    true  => out_compat_json_str(self.construct_bulk_json(JsonState::BusRechtdoorRechtsaf)),
-                    false => out_compat_json_str(self.construct_bulk_json(JsonState::Groen)),
-                };
-                out_tx.send(json_str).unwrap();
+                self.start_cleared(time, changes, topology, clearance, intergreen);
                 Some(SignalGroupState::Busy{ start: time })
             },
 
@@ -85,7 +89,7 @@ impl<'a> SignalGroup<'a> {
             },
 
             SignalGroupState::Busy { start } => {
-                println!("=> Busy {:?} {:?}", start + self.max_green, self.ids());
+                println!("=> Busy {:?} {:?}", start + self.max_green, self.ids(topology));
 
                 if self.controls_have_state(TrafficLightState::Red) {
                     Some(SignalGroupState::Done)
@@ -95,7 +99,8 @@ impl<'a> SignalGroup<'a> {
                     Some(SignalGroupState::ForceRed)
                 }
                 else {
-                    self.run_loops(time, out_tx, sensor_states);
+                    self.start_cleared(time, changes, topology, clearance, intergreen);
+                    self.run_loops(time, changes, topology, sensor_states, detectors, clearance);
                     None
                 }
             },
@@ -114,23 +119,58 @@ impl<'a> SignalGroup<'a> {
         }
     }
 
+    /// The controls that are green or yellow.
+    pub fn green_controls(&self) -> Vec<ControlId> {
+        self.controls.iter()
+            .filter(|c| match c.state {
+                TrafficLightState::MinimalGreen { .. } | TrafficLightState::Green { .. } | TrafficLightState::Yellow { .. } => true,
+                _ => false,
+            })
+            .map(|c| c.control)
+            .collect()
+    }
+
+    /// Every control is already on its way to red.
+    pub fn is_stopping(&self) -> bool {
+        match self.state {
+            SignalGroupState::ForceRed | SignalGroupState::Done => true,
+            _ => self.controls.iter().all(|c| c.force_red || match c.state {
+                TrafficLightState::Yellow { .. } | TrafficLightState::Red => true,
+                _ => false,
+            }),
+        }
+    }
+
     fn force_red(&mut self) {
         for c in &mut self.controls {
             c.force_red = true;
         }
     }
 
-    fn run_loops(&mut self, time: i32, out_tx: &Sender<String>, sensor_states: &SensorStates) {
+    fn run_loops(&mut self, time: i32, changes: &mut Vec<SignalChange>, topology: &Topology, sensor_states: &SensorStates, detectors: &Detectors, clearance: &mut ClearanceState) {
         for c in &mut self.controls {
-            c.run_loop(time, out_tx, sensor_states, self.unlimited_green);
+            c.run_loop(time, changes, topology, sensor_states, detectors, self.unlimited_green, clearance);
         }
     }
 
-    fn construct_bulk_json(&self, state: JsonState) -> Vec<StoplichtJson> {
-        self.controls.iter().flat_map(|ref c| c.inner.json_objs(state)).collect()
+    /// Turns every waiting control that has cleared its conflicting lights green.
+    /// The others stay in Init until their intergreen time has passed.
+    fn start_cleared(&mut self, time: i32, changes: &mut Vec<SignalChange>, topology: &Topology, clearance: &ClearanceState, intergreen: &IntergreenMatrix) {
+        for c in &mut self.controls {
+            let control = topology.control(c.control);
+            if c.state == TrafficLightState::Init && !c.force_red && clearance.may_start(control, time, intergreen) {
+                changes.extend(control.signal_changes(c.aspect));
+                c.start_green(time);
+            }
+        }
     }
 
-    fn ids(&self) -> Vec<Vec<usize>> {
-        self.controls.iter().map(|c| c.inner.get_ids()).collect()
+    pub fn ids(&self, topology: &Topology) -> Vec<Vec<usize>> {
+        self.controls.iter().map(|c| topology.control(c.control).get_ids()).collect()
     }
 }
+
+/// The group stays open until the control with the longest maximum green is done, each control stops at its own maximum.
+fn max_green(controls: &Vec<&Control>) -> i32 {
+    controls.iter().map(|c| c.timings().max_green).max().unwrap_or(0)
+}
@@ -0,0 +1,128 @@
+use time;
+
+
+// -------------------------------------------------------------------------------
+// Solver
+// -------------------------------------------------------------------------------
+
+/// Branch and bound search for the maximum weight set of candidates in which no two candidates conflict
+/// (a maximum weight independent set on the conflict graph).
+///
+/// Candidates are tried heaviest first. A branch is cut off when its weight plus an upper bound for the
+/// remaining candidates can't beat the best set found so far. The bound covers the remaining candidates
+/// with cliques of mutually conflicting candidates, of which at most one can be chosen each.
+pub struct Solver {
+    weights: Vec<i64>,
+    conflicts: Vec<Vec<bool>>,
+    order: Vec<usize>,
+    best: Vec<usize>,
+    best_weight: i64,
+}
+
+impl Solver {
+
+    pub fn new<F>(weights: Vec<i64>, is_conflicting: F) -> Solver where F: Fn(usize, usize) -> bool {
+        let n = weights.len();
+        let conflicts = (0..n).map(|a| (0..n).map(|b| a != b && (is_conflicting(a, b) || is_conflicting(b, a))).collect()).collect();
+
+        let mut order: Vec<usize> = (0..n).collect();
+        order.sort_by(|&a, &b| weights[b].cmp(&weights[a]));
+
+        Solver { weights: weights, conflicts: conflicts, order: order, best: vec![], best_weight: 0 }
+    }
+
+    /// Indices of the chosen candidates, in ascending order.
+    pub fn solve(mut self) -> Vec<usize> {
+        let candidates = self.order.clone();
+        let mut chosen = vec![];
+        self.branch(&mut chosen, 0, candidates);
+
+        let mut best = self.best;
+        best.sort();
+        best
+    }
+
+    /// `candidates` are sorted heaviest first and don't conflict with anything in `chosen`.
+    fn branch(&mut self, chosen: &mut Vec<usize>, weight: i64, candidates: Vec<usize>) {
+        if weight > self.best_weight {
+            self.best_weight = weight;
+            self.best = chosen.clone();
+        }
+
+        let mut candidates = candidates;
+        while let Some(&next) = candidates.first() {
+            if weight + self.upper_bound(&candidates) <= self.best_weight {
+                return;
+            }
+
+            candidates.remove(0);
+
+            // Take `next`, only keep the candidates that are compatible with it.
+            let remaining = candidates.iter().cloned().filter(|&c| !self.conflicts[next][c]).collect();
+            chosen.push(next);
+            let next_weight = self.weights[next];
+            self.branch(chosen, weight + next_weight, remaining);
+            chosen.pop();
+
+            // Continue without `next`.
+        }
+    }
+
+    /// Greedy clique cover, each clique contributes its heaviest candidate.
+    fn upper_bound(&self, candidates: &Vec<usize>) -> i64 {
+        let mut cliques: Vec<Vec<usize>> = vec![];
+
+        for &c in candidates {
+            match cliques.iter().position(|clique| clique.iter().all(|&other| self.conflicts[c][other])) {
+                Some(index) => cliques[index].push(c),
+                None => cliques.push(vec![c]),
+            }
+        }
+
+        // Candidates are sorted, so the first member is the heaviest.
+        cliques.iter().map(|clique| ::std::cmp::max(0, self.weights[clique[0]])).sum()
+    }
+}
+
+/// Every waiting control counts, so a control that just arrived is still added when it fits.
+pub fn waiting_weight(waiting: time::Duration) -> i64 {
+    ::std::cmp::max(0, waiting.num_seconds()) + 1
+}
+
+/// Added to the weight of a control that waited longer than its `max_waiting`. More than any sum of
+/// waiting times, so the solver only leaves an overdue control out when it conflicts with another overdue one.
+pub const OVERDUE_WEIGHT: i64 = 1 << 40;
+
+pub fn max_weight_compatible<F>(weights: Vec<i64>, is_conflicting: F) -> Vec<usize> where F: Fn(usize, usize) -> bool {
+    Solver::new(weights, is_conflicting).solve()
+}
+
+
+#[test]
+fn solver_matches_brute_force() {
+    // Small pseudo random graphs, compared against trying every subset.
+    let mut seed: u64 = 42;
+    let mut random = move || { seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407); (seed >> 33) as i64 };
+
+    for _ in 0..50 {
+        let n = 12;
+        let weights: Vec<i64> = (0..n).map(|_| random() % 20 + 1).collect();
+        let edges: Vec<Vec<bool>> = (0..n).map(|_| (0..n).map(|_| random() % 3 == 0).collect()).collect();
+        let is_conflicting = |a: usize, b: usize| edges[a][b] || edges[b][a];
+
+        let chosen = max_weight_compatible(weights.clone(), &is_conflicting);
+        let chosen_weight: i64 = chosen.iter().map(|&i| weights[i]).sum();
+
+        let mut best_weight = 0;
+        for subset in 0..(1u32 << n) {
+            let members: Vec<usize> = (0..n).filter(|&i| subset & (1 << i) != 0).collect();
+            let compatible = members.iter().all(|&a| members.iter().all(|&b| a == b || !is_conflicting(a, b)));
+            if compatible {
+                best_weight = ::std::cmp::max(best_weight, members.iter().map(|&i| weights[i]).sum());
+            }
+        }
+
+        assert!(chosen.iter().all(|&a| chosen.iter().all(|&b| a == b || !is_conflicting(a, b))));
+        assert_eq!(chosen_weight, best_weight);
+    }
+}
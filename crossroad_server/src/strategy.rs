@@ -0,0 +1,311 @@
+use std::fmt;
+use time;
+
+use traffic_protocol::*;
+use traffic_controls::*;
+use signal_group::*;
+use crossroad::Crossroad;
+use intergreen::ClearanceState;
+use bus_priority::*;
+use planner::{LookAheadStrategy, DEFAULT_HORIZON};
+use detector::Detectors;
+
+/// The name of the strategy the actuated mode uses unless another one is picked.
+pub const DEFAULT_STRATEGY: &'static str = "default";
+
+
+// -------------------------------------------------------------------------------
+// ControlStrategy
+// -------------------------------------------------------------------------------
+
+/// Everything a strategy may look at, it can't change any of it.
+pub struct StrategyView<'a> {
+    pub time: i32,
+    pub now: time::Tm,
+    pub crossroad: &'a Crossroad,
+    pub sensors: &'a SensorStates,
+    /// What every light shows, indexed by light id.
+    pub signals: &'a Vec<JsonState>,
+    pub detectors: &'a Detectors,
+    pub clearance: &'a ClearanceState,
+    pub priorities: &'a Vec<BusPriority>,
+}
+
+impl<'a> StrategyView<'a> {
+    pub fn topology(&self) -> &Topology {
+        &self.crossroad.topology
+    }
+
+    /// The bus lanes the bus priority policy wants green now.
+    pub fn early_green(&self) -> Vec<ControlId> {
+        self.priorities.iter()
+            .filter(|p| p.decision == PriorityDecision::EarlyGreen)
+            .map(|p| p.control)
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Decision {
+    /// Every light stays red, the strategy is asked again next tick.
+    Wait,
+    /// The primary lanes, green until `stop_group` says otherwise.
+    Primary,
+    Group(SignalGroup),
+}
+
+/// Decides which group turns green in the actuated mode, and when a green group stops early.
+/// The crossroad keeps the timings, the intergreen times and emergency vehicles to itself,
+/// so a strategy can't make the lights unsafe.
+pub trait ControlStrategy: fmt::Debug + Send {
+    fn name(&self) -> &'static str;
+
+    /// Called while every light is red.
+    fn next_group(&self, view: &StrategyView) -> Decision;
+
+    /// Called every tick while `group` runs, `primary` when it is the group of the primary lanes.
+    /// True stops it through its minimum green and yellow.
+    fn stop_group(&self, view: &StrategyView, group: &SignalGroup, primary: bool) -> bool;
+
+    fn box_clone(&self) -> Box<ControlStrategy>;
+}
+
+impl Clone for Box<ControlStrategy> {
+    fn clone(&self) -> Box<ControlStrategy> {
+        self.box_clone()
+    }
+}
+
+
+// -------------------------------------------------------------------------------
+// DefaultStrategy
+// -------------------------------------------------------------------------------
+
+/// The primary lanes stay green until other traffic arrives. Then traffic that waited longer than its
+/// `max_waiting` goes first, then buses the policy gives an early green, then the group with the
+/// longest combined waiting time around the traffic closest to its `max_waiting`. A group stops early
+/// for overdue traffic it blocks.
+#[derive(Debug, Copy, Clone)]
+pub struct DefaultStrategy;
+
+impl ControlStrategy for DefaultStrategy {
+
+    fn name(&self) -> &'static str {
+        DEFAULT_STRATEGY
+    }
+
+    fn next_group(&self, view: &StrategyView) -> Decision {
+        let crossroad = view.crossroad;
+        let overdue = crossroad.overdue_controls(view.time, view.now, view.sensors, view.clearance);
+
+        if let Some(&first) = overdue.first() {
+            println!("Waited longer than their max_waiting: {:?}", overdue);
+
+            // Buses keep their priority only when they waited longest.
+            if view.sensors.has_active_bus(view.topology().control(first)) {
+                if let Some(group) = self.bus_group(view, &overdue) {
+                    return Decision::Group(group);
+                }
+            }
+            self.generated_group(view, &overdue)
+        }
+        else if let Some(group) = self.bus_group(view, &overdue) {
+            Decision::Group(group)
+        }
+        else if view.sensors.has_any_active(&view.topology().lookup(&crossroad.secondary_traffic)) {
+            self.generated_group(view, &overdue)
+        }
+        else {
+            Decision::Primary
+        }
+    }
+
+    fn stop_group(&self, view: &StrategyView, group: &SignalGroup, primary: bool) -> bool {
+        let crossroad = view.crossroad;
+        let early_green = view.early_green();
+
+        if primary {
+            let group_is_green = group.controls_have_state(TrafficLightState::Green{start:0});
+            let any_sensor_active = view.sensors.has_any_active(&view.topology().lookup(&crossroad.secondary_traffic))
+                || view.sensors.has_any_active(&view.topology().lookup(&crossroad.priority_traffic))
+                || !early_green.is_empty();
+
+            group_is_green && any_sensor_active
+        }
+        else {
+            let conflicts_with_bus = group.green_controls().iter().any(|&id| {
+                early_green.iter().any(|&bus| crossroad.conflicts.controls_conflict(view.topology().control(id), view.topology().control(bus)))
+            });
+
+            let overdue = crossroad.overdue_controls(view.time, view.now, view.sensors, view.clearance);
+            let conflicts_with_overdue = group.green_controls().iter().any(|&id| {
+                overdue.iter().any(|&other| crossroad.conflicts.controls_conflict(view.topology().control(id), view.topology().control(other)))
+            });
+
+            ((!group.is_bus && conflicts_with_bus) || conflicts_with_overdue) && !group.is_stopping()
+        }
+    }
+
+    fn box_clone(&self) -> Box<ControlStrategy> {
+        Box::new(*self)
+    }
+}
+
+impl DefaultStrategy {
+
+    /// The group around the traffic closest to its `max_waiting`. Waits when only bus messages are active,
+    /// those have no sensor control to start a group from.
+    fn generated_group(&self, view: &StrategyView, overdue: &Vec<ControlId>) -> Decision {
+        let first = overdue.first().cloned().or_else(|| view.crossroad.most_urgent(view.time, view.now, view.sensors, view.clearance));
+        match view.crossroad.generate_signalgroup(view.time, view.now, view.sensors, view.detectors, overdue, first) {
+            Some(group) => Decision::Group(group),
+            None => Decision::Wait,
+        }
+    }
+
+    /// The bus lanes with an early green or an overdue bus that don't conflict with each other.
+    fn bus_group(&self, view: &StrategyView, overdue: &Vec<ControlId>) -> Option<SignalGroup> {
+        let crossroad = view.crossroad;
+        let early_green = view.early_green();
+        let mut bus_controls: Vec<(&Control, JsonState)> = vec![];
+
+        for bcontrol in view.topology().lookup(&crossroad.priority_traffic) {
+            let conflicting = bus_controls.iter().any(|&(other, _)| crossroad.conflicts.controls_conflict(bcontrol, other));
+            let has_priority = early_green.contains(&bcontrol.id) || overdue.contains(&bcontrol.id);
+            if view.sensors.has_active_bus(bcontrol) && has_priority && !conflicting {
+                bus_controls.push((bcontrol, crossroad.bus_aspect(bcontrol, view.sensors)));
+            }
+        }
+
+        match bus_controls.len() {
+            0 => None,
+            _ => Some(SignalGroup::new_bus(bus_controls, false)),
+        }
+    }
+}
+
+
+// -------------------------------------------------------------------------------
+// StrategyRegistry
+// -------------------------------------------------------------------------------
+
+pub type StrategyConstructor = fn() -> Box<ControlStrategy>;
+
+fn default_strategy() -> Box<ControlStrategy> {
+    Box::new(DefaultStrategy)
+}
+
+fn lookahead_strategy() -> Box<ControlStrategy> {
+    Box::new(LookAheadStrategy { horizon: DEFAULT_HORIZON })
+}
+
+/// The strategies that can be picked by name, so they can be compared on the same crossroad.
+pub struct StrategyRegistry {
+    strategies: Vec<(&'static str, StrategyConstructor)>,
+}
+
+impl StrategyRegistry {
+
+    /// A registry with the built-in strategies.
+    pub fn new() -> StrategyRegistry {
+        let mut registry = StrategyRegistry { strategies: vec![] };
+        registry.register(DEFAULT_STRATEGY, default_strategy);
+        registry.register("lookahead", lookahead_strategy);
+        registry
+    }
+
+    /// Replaces a strategy that was registered under the same name.
+    pub fn register(&mut self, name: &'static str, constructor: StrategyConstructor) {
+        self.strategies.retain(|&(n, _)| n != name);
+        self.strategies.push((name, constructor));
+    }
+
+    pub fn create(&self, name: &str) -> Option<Box<ControlStrategy>> {
+        self.strategies.iter()
+            .find(|&&(n, _)| n == name)
+            .map(|&(_, constructor)| constructor())
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.strategies.iter().map(|&(name, _)| name).collect()
+    }
+}
+
+
+#[cfg(test)]
+#[derive(Debug, Copy, Clone)]
+struct PrimaryOnly;
+
+#[cfg(test)]
+impl ControlStrategy for PrimaryOnly {
+    fn name(&self) -> &'static str { "primary-only" }
+    fn next_group(&self, _: &StrategyView) -> Decision { Decision::Primary }
+    fn stop_group(&self, _: &StrategyView, _: &SignalGroup, _: bool) -> bool { false }
+    fn box_clone(&self) -> Box<ControlStrategy> { Box::new(*self) }
+}
+
+#[cfg(test)]
+fn primary_only() -> Box<ControlStrategy> {
+    Box::new(PrimaryOnly)
+}
+
+#[test]
+fn registry_creates_strategies_by_name() {
+    let mut registry = StrategyRegistry::new();
+    assert_eq!(registry.names(), vec![DEFAULT_STRATEGY, "lookahead"]);
+    assert!(registry.create("primary-only").is_none());
+
+    registry.register("primary-only", primary_only);
+    assert_eq!(registry.names(), vec![DEFAULT_STRATEGY, "lookahead", "primary-only"]);
+    assert_eq!(registry.create("primary-only").unwrap().name(), "primary-only");
+    assert_eq!(registry.create(DEFAULT_STRATEGY).unwrap().name(), DEFAULT_STRATEGY);
+}
+
+#[test]
+fn strategy_decides_the_next_group() {
+    use clock::*;
+    use controller::Controller;
+
+    let crossroad = ::default_crossroad::default_crossroad();
+
+    // A car waits at 13, which conflicts with the primary lane 2.
+    let green_13 = |strategy: Box<ControlStrategy>| {
+        let mut crossroad = crossroad.clone();
+        crossroad.strategy = strategy;
+        let mut controller = Controller::new(crossroad);
+        let clock = SimulatedClock::new();
+        let mut sensors = SensorStates::new();
+        sensors.update(&vec![Baan { id: 13, bezet: true }], clock.now());
+
+        let mut green = false;
+        for _ in 0..60 {
+            clock.tick();
+            green |= controller.step(clock.now(), &sensors).iter().any(|c| c.id == 13 && c.state == JsonState::Groen);
+        }
+        green
+    };
+
+    assert!(green_13(Box::new(DefaultStrategy)));
+    assert!(!green_13(Box::new(PrimaryOnly)));
+}
+
+#[test]
+fn bus_message_on_a_regular_lane_is_no_request() {
+    use clock::*;
+    use controller::Controller;
+
+    let mut controller = Controller::new(::default_crossroad::default_crossroad());
+
+    // 13 is no bus lane, a bus there never becomes overdue and doesn't start a group.
+    let clock = SimulatedClock::new();
+    let mut sensors = SensorStates::new();
+    sensors.update_bussen(&vec![BusBaan { id: 13, eerstvolgendelijn: 1, vertraging: 0, bezet: true }], clock.now());
+
+    for _ in 0..300 {
+        clock.tick();
+        for change in controller.step(clock.now(), &sensors) {
+            assert!(change.id != 13 || change.state == JsonState::Rood, "13 turned {:?}", change.state);
+        }
+    }
+    assert_eq!(controller.fault(), None);
+}
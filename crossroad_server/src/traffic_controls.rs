@@ -2,11 +2,11 @@ use std::fmt;
 use time;
 use crossroad::*;
 use traffic_protocol::*;
-use std::sync::mpsc::{channel, Sender, Receiver};
+use intergreen::ClearanceState;
+use controller::SignalChange;
+use detector::Detectors;
 
 
-const YELLOW_TIME: i32 = 4;
-
 #[derive(Debug, PartialEq, Clone)]
 pub enum TrafficLightState {
     Init,
@@ -17,29 +17,60 @@ pub enum TrafficLightState {
 }
 
 #[derive(Debug, Clone)]
-pub struct ControlWithState<'a> {
-    pub inner: &'a Control<'a>,
+pub struct ControlWithState {
+    pub control: ControlId,
     pub state: TrafficLightState,
     pub force_red: bool,
+    pub green_start: i32,
+    /// What the lights show while green, a bus lane shows the movement of its bus.
+    pub aspect: JsonState,
+    /// Set by the bus priority policy, the green is also extended for a bus at the sensor.
+    pub extend_for_bus: bool,
 }
 
-impl <'a>ControlWithState<'a>  {
-    pub fn new(inner: &'a Control) -> ControlWithState<'a> {
-        ControlWithState { inner:inner, state: TrafficLightState::Init, force_red: false }
+impl ControlWithState {
+    pub fn new(control: &Control) -> ControlWithState {
+        ControlWithState::with_aspect(control, JsonState::Groen)
+    }
+
+    pub fn with_aspect(control: &Control, aspect: JsonState) -> ControlWithState {
+        ControlWithState { control: control.id, state: TrafficLightState::Init, force_red: false, green_start: 0, aspect: aspect, extend_for_bus: false }
+    }
+
+    pub fn start_green(&mut self, time: i32) {
+        self.state = TrafficLightState::MinimalGreen { start: time };
+        self.green_start = time;
     }
 
-    pub fn run_loop(&mut self, time: i32, out_tx: &Sender<String>, sensor_states: &SensorStates, unlimited_green: bool) -> TrafficLightState {
+    pub fn run_loop(&mut self, time: i32,
+                               changes: &mut Vec<SignalChange>,
+                               topology: &Topology,
+                               sensor_states: &SensorStates,
+                               detectors: &Detectors,
+                               unlimited_green: bool,
+                               clearance: &mut ClearanceState)
+                            -> TrafficLightState {
+
+        let control = topology.control(self.control);
+        let timings = control.timings();
 
         let new_state = match self.state {
 
+            // Waiting for conflicting lights to clear, the SignalGroup turns it green.
             TrafficLightState::Init => {
-                Some(TrafficLightState::MinimalGreen { start: time })
+                if self.force_red {
+                    self.force_red = false;
+                    Some(TrafficLightState::Red)
+                }
+                else {
+                    None
+                }
             },
 
             TrafficLightState::MinimalGreen { start } => {
-                //println!("****  MinimalGreen since {:?}, {:?} -> {:?}", start,  self.inner, self.state );
+                //println!("****  MinimalGreen since {:?}, {:?} -> {:?}", start,  control, self.state );
 
-                if time >= start + self.inner.traffic_type().min_green() {
+                if time >= start + timings.min_green {
                     Some(TrafficLightState::Green{ start: time })
                 }
                 else {
@@ -48,10 +79,11 @@ impl <'a>ControlWithState<'a>  {
             },
 
             TrafficLightState::Green { start } => {
-                //println!("****  Green since {:?}, {:?} -> {:?}", start, self.inner, self.state);
+                //println!("****  Green since {:?}, {:?} -> {:?}", start, control, self.state);
 
                 if self.force_red {
-                    self.inner.send_unsafe(out_tx, JsonState::Geel);
+                    changes.extend(control.signal_changes(JsonState::Geel));
+                    clearance.end_green(control, time);
                     self.force_red = false;
                     Some(TrafficLightState::Yellow{ start: time })
                 }
@@ -59,14 +91,22 @@ impl <'a>ControlWithState<'a>  {
                     None
                 }
                 else {
-                    // if: sensor is activated -> extend green time
+                    // if: maximum green reached -> move to yellow, even with waiting traffic
+                    // else if: vehicles are moving over the detector -> extend green time
                     // else if: check if we can move to yellow
-                    if sensor_states.has_active(self.inner) {
-                        println!(":::: Extending green timer for: {:?}" ,self.inner.get_ids());
+                    if time >= self.green_start + timings.max_green {
+                        println!(":::: Maximum green reached for: {:?}", control.get_ids());
+                        changes.extend(control.signal_changes(JsonState::Geel));
+                        clearance.end_green(control, time);
+                        Some(TrafficLightState::Yellow{ start: time })
+                    }
+                    else if detectors.extends_green(control, time, self.green_start, timings.green_extra) || (self.extend_for_bus && sensor_states.has_active_bus(control)) {
+                        println!(":::: Extending green timer for: {:?}" ,control.get_ids());
                         Some(TrafficLightState::Green{ start: time }) // reset timer
                     }
-                    else if time >= start + self.inner.traffic_type().green_extra() {
-                        self.inner.send_unsafe(out_tx, JsonState::Geel);
+                    else if time >= start + timings.green_extra {
+                        changes.extend(control.signal_changes(JsonState::Geel));
+                        clearance.end_green(control, time);
                         Some(TrafficLightState::Yellow{ start: time })
                     }
                     else {
@@ -76,10 +116,11 @@ impl <'a>ControlWithState<'a>  {
             },
 
             TrafficLightState::Yellow { start } => {
-                //println!("****  Yellow {:?} -> {:?}, {:?} -> {:?}", start, start + YELLOW_TIME, self.inner, self.state);
+                //println!("****  Yellow {:?} -> {:?}, {:?} -> {:?}", start, start + timings.yellow, control, self.state);
 
-                if time >= start + YELLOW_TIME {
-                    self.inner.send_unsafe(out_tx, JsonState::Rood);
+                if time >= start + timings.yellow {
+                    changes.extend(control.signal_changes(JsonState::Rood));
+                    clearance.start_red(control, time);
                     Some(TrafficLightState::Red)
                 }
                 else {
@@ -100,83 +141,203 @@ impl <'a>ControlWithState<'a>  {
 
 
 // -------------------------------------------------------------------------------
-// Control
+// Ids
 // -------------------------------------------------------------------------------
 
-#[derive(Debug, Eq, Hash)]
-pub enum Control<'a> {
-    Group(TrafficGroup<'a>),
-    Single(&'a TrafficLight)
-}
+/// A traffic light, the same number as the `id` of its stoplicht in the protocol.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+pub struct LightId(pub usize);
+
+/// A control, the index in `Topology::controls`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ControlId(pub usize);
 
-impl <'a>PartialEq for Control<'a> {
-    fn eq(&self, other: &Control) -> bool {
-        let address_self = self as *const Control;
-        let address_other = other as *const Control;
-        address_self == address_other
+/// A sensor (baan), the same number as the `id` of its baan in the protocol.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SensorId(pub usize);
+
+impl SensorId {
+    /// Every traffic light has one sensor with the same number.
+    pub fn light(&self) -> LightId {
+        LightId(self.0)
     }
 }
 
-impl <'a>Control<'a> {
-
-    pub fn conflicting_with(&'a self, conflicting_with: Vec<&'a Control>) -> ConflictEntry where Self: Sized {
+impl ControlId {
+    pub fn conflicting_with(self, conflicting_with: Vec<ControlId>) -> ConflictEntry {
         ConflictEntry {
             control: self,
             conflicting_with: conflicting_with,
         }
     }
+}
 
-    pub fn is(&self, other: &Control) -> bool {
-        let address_self = self as *const Control;
-        let address_other = other as *const Control;
-        address_self == address_other
+
+// -------------------------------------------------------------------------------
+// Topology
+// -------------------------------------------------------------------------------
+
+/// Owns the traffic lights and controls of a crossroad, everything else refers to them by id.
+#[derive(Debug, Clone)]
+pub struct Topology {
+    lights: Vec<TrafficLight>,
+    controls: Vec<Control>,
+    light_controls: Vec<ControlId>,
+    bus_routes: Vec<BusRoute>,
+}
+
+impl Topology {
+
+    /// Every light has to be part of exactly one control.
+    pub fn new(lights: Vec<TrafficLight>, controls: Vec<Control>) -> Topology {
+        let light_controls = lights.iter().map(|tl| {
+            controls.iter().find(|c| c.contains(tl.id.0)).expect("traffic light without a control").id
+        }).collect();
+
+        Topology { lights: lights, controls: controls, light_controls: light_controls, bus_routes: vec![] }
     }
 
-    pub fn contains_one_of(&self, ids: &Vec<usize>) -> bool {
-        match self {
-            &Control::Group(ref group) => group.contains_ids(ids),
-            &Control::Single(ref tl) => tl.contains_ids(ids),
+    pub fn lights(&self) -> &Vec<TrafficLight> {
+        &self.lights
+    }
+
+    pub fn controls(&self) -> &Vec<Control> {
+        &self.controls
+    }
+
+    pub fn light(&self, id: LightId) -> &TrafficLight {
+        &self.lights[id.0]
+    }
+
+    pub fn control(&self, id: ControlId) -> &Control {
+        &self.controls[id.0]
+    }
+
+    pub fn control_id_of(&self, light: LightId) -> Option<ControlId> {
+        self.light_controls.get(light.0).cloned()
+    }
+
+    pub fn control_of(&self, light: LightId) -> Option<&Control> {
+        self.control_id_of(light).map(|id| self.control(id))
+    }
+
+    pub fn sensor_control(&self, sensor: SensorId) -> Option<&Control> {
+        self.control_of(sensor.light())
+    }
+
+    /// The control of every light, indexed by light id.
+    pub fn indexed_controls(&self) -> Vec<ControlId> {
+        self.light_controls.clone()
+    }
+
+    pub fn lookup(&self, ids: &Vec<ControlId>) -> Vec<&Control> {
+        ids.iter().map(|&id| self.control(id)).collect()
+    }
+
+    pub fn set_timings(&mut self, id: ControlId, timings: Timings) {
+        self.controls[id.0].timings = timings;
+    }
+
+    pub fn bus_routes(&self) -> &Vec<BusRoute> {
+        &self.bus_routes
+    }
+
+    pub fn set_bus_routes(&mut self, routes: Vec<BusRoute>) {
+        self.bus_routes = routes;
+    }
+
+    /// A line that isn't in the route table may go both ways.
+    pub fn bus_movement(&self, line: i32) -> BusMovement {
+        self.bus_routes.iter()
+            .find(|route| route.line == line)
+            .map(|route| route.movement)
+            .unwrap_or(BusMovement::StraightAndRight)
+    }
+}
+
+
+// -------------------------------------------------------------------------------
+// Bus routes
+// -------------------------------------------------------------------------------
+
+/// Where a bus goes after the crossroad, the priority light shows only that movement.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum BusMovement {
+    Straight,
+    Right,
+    StraightAndRight,
+}
+
+impl BusMovement {
+    pub fn aspect(&self) -> JsonState {
+        match *self {
+            BusMovement::Straight         => JsonState::BusRechtdoor,
+            BusMovement::Right            => JsonState::BusRechtsaf,
+            BusMovement::StraightAndRight => JsonState::BusRechtdoorRechtsaf,
         }
     }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BusRoute {
+    pub line: i32,
+    pub movement: BusMovement,
+}
+
+
+// -------------------------------------------------------------------------------
+// Control
+// -------------------------------------------------------------------------------
+
+/// A single traffic light, or a group of lights that always show the same state.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Control {
+    pub id: ControlId,
+    pub lights: Vec<LightId>,
+    pub direction: Direction,
+    pub traffic_type: Type,
+    pub timings: Timings,
+}
+
+impl Control {
+
+    pub fn contains_one_of(&self, ids: &Vec<usize>) -> bool {
+        ids.iter().any(|&id| self.contains(id))
+    }
 
     pub fn contains(&self, id: usize) -> bool {
-        match self {
-            &Control::Group(ref group) => group.contains_id(id),
-            &Control::Single(ref tl) => tl.id == id,
-        }
+        self.lights.iter().any(|light| light.0 == id)
     }
 
     pub fn traffic_type(&self) -> &Type {
-        match self {
-            &Control::Group(ref group) => &group.traffic_type,
-            &Control::Single(ref tl) => &tl.traffic_type,
-        }
+        &self.traffic_type
+    }
+
+    /// The timings set on the light or group, completed with the defaults of its type.
+    pub fn timings(&self) -> SignalTimings {
+        self.timings.resolve(&self.traffic_type)
     }
 
     pub fn get_ids(&self) -> Vec<usize> {
-        match self {
-            &Control::Group(ref group) => group.get_ids(),
-            &Control::Single(ref tl) => vec![tl.id],
-        }
+        self.lights.iter().map(|light| light.0).collect()
     }
 
     pub fn direction(&self) -> Direction {
-        match self {
-            &Control::Group(ref group) => group.direction,
-            &Control::Single(ref tl) => tl.direction,
-        }
+        self.direction
     }
 
     pub fn json_objs(&self, state: JsonState) -> Vec<StoplichtJson> {
-        match self {
-            &Control::Group(ref group) => group.json_objs(&state),
-            &Control::Single(ref tl) => vec![tl.json_obj(&state)],
-        }
+        self.lights.iter().map(|light| StoplichtJson { id: light.0, status: state.id() }).collect()
     }
 
-    pub fn send_unsafe(&self, out_tx: &Sender<String>, state: JsonState) {
-        let json_str = out_compat_json_str(self.json_objs(state));
-        out_tx.send(json_str).unwrap()
+    pub fn signal_changes(&self, state: JsonState) -> Vec<SignalChange> {
+        self.get_ids().into_iter().map(|id| SignalChange { id: id, state: state }).collect()
+    }
+}
+
+impl fmt::Debug for Control {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Control({:?},{:?})", self.get_ids(), self.direction)
     }
 }
 
@@ -186,30 +347,31 @@ impl <'a>Control<'a> {
 // -------------------------------------------------------------------------------
 
 #[derive(Clone)]
-pub struct ControlSensor<'a, 'b> {
-    pub inner: &'a Control<'a>,
-    pub sensor: &'b Sensor,
+pub struct ControlSensor {
+    pub control: ControlId,
+    pub sensor: Sensor,
     pub conflicting_ids: Vec<usize>
 }
 
-impl <'a, 'b>ControlSensor<'a, 'b>  {
-    pub fn new(inner: &'a Control, sensor: &'b Sensor, conflicting_ids: Vec<usize>) -> ControlSensor<'a, 'b> {
-        ControlSensor { inner:inner, sensor:sensor, conflicting_ids:conflicting_ids }
+impl ControlSensor {
+    pub fn new(control: &Control, sensor: &Sensor, conflicting_ids: Vec<usize>) -> ControlSensor {
+        ControlSensor { control: control.id, sensor: *sensor, conflicting_ids: conflicting_ids }
     }
+    /// Since the sensor became occupied, clients that send every sensor each tick update `last_update`.
     pub fn time_waiting(&self, until: time::Tm) -> time::Duration {
-        until - self.sensor.last_update
+        until - self.sensor.waiting_since.unwrap_or(self.sensor.last_update)
     }
-    pub fn filter_conflicting<'c>(&'c self, choices: &'c Vec<ControlSensor<'a, 'b>>) -> Vec<&'c ControlSensor<'a, 'b>> {
+    pub fn filter_conflicting<'c>(&self, choices: &'c Vec<ControlSensor>, topology: &Topology) -> Vec<&'c ControlSensor> {
         choices.iter()
-               .filter(|&choice| !self.conflicting_ids.iter().any(|&id| choice.inner.contains(id)))
+               .filter(|&choice| !topology.control(choice.control).contains_one_of(&self.conflicting_ids))
                .collect()
     }
 }
 
-impl <'a, 'b>fmt::Debug for ControlSensor<'a, 'b> {
+impl fmt::Debug for ControlSensor {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "ControlSensor({:?}, sensor: {}, conflicts: {:?})",
-            self.inner, self.sensor.last_update.ctime(), self.conflicting_ids)
+            self.control, self.sensor.last_update.ctime(), self.conflicting_ids)
     }
 }
 
@@ -218,88 +380,75 @@ impl <'a, 'b>fmt::Debug for ControlSensor<'a, 'b> {
 // ControlsBuilder
 // -------------------------------------------------------------------------------
 
-pub struct ControlsBuilder<'a> {
-    pub tlights: &'a TrafficLightsBuilder,
-    pub groups: Vec<TrafficGroup<'a>>,
+pub struct ControlsBuilder {
+    pub tlights: TrafficLightsBuilder,
+    pub groups: Vec<TrafficGroup>,
 }
 
-impl <'a>ControlsBuilder<'a> {
-    pub fn new(tlights: &'a TrafficLightsBuilder) -> ControlsBuilder<'a> {
+impl ControlsBuilder {
+    pub fn new(tlights: TrafficLightsBuilder) -> ControlsBuilder {
         ControlsBuilder { tlights: tlights, groups: vec![] }
     }
-    pub fn add_group(mut self, ids: Vec<usize>, d: Direction, t: Type) -> Self {
-        let traffic_lights = ids.into_iter().map(|id| &self.tlights.traffic_lights[id]).collect();
-        self.groups.push(TrafficGroup::from(traffic_lights, d, t));
-        self
+    pub fn add_group(self, ids: Vec<usize>, d: Direction, t: Type) -> Self {
+        self.add_group_with_timings(ids, d, t, Timings::default())
     }
 
-    pub fn create_controls(self) -> Vec<Control<'a>> {
-        let mut tl_controls = self.tlights.as_controls();
+    pub fn add_group_with_timings(mut self, ids: Vec<usize>, d: Direction, t: Type, timings: Timings) -> Self {
+        let mut group = TrafficGroup::from(ids.into_iter().map(LightId).collect(), d, t);
+        group.timings = timings;
+        self.groups.push(group);
+        self
+    }
 
-        // Process each group
-        for group in self.groups.into_iter() {
+    /// Lights that are not in a group become a control of their own, the groups are added after them.
+    pub fn create_controls(self) -> Topology {
+        let ControlsBuilder { tlights, groups: added_groups } = self;
+        let lights = tlights.traffic_lights;
 
-            // Remove each id in the group from tl_controls
-            for id in group.get_ids() {
-                if let Some(index) = tl_controls.iter().position(|tlc| tlc.contains(id)) {
-                    println!("Removing tl control @ index {:?}, id: {:?}", index, id);
-                    tl_controls.remove(index);
-                }
-            }
+        let mut groups: Vec<TrafficGroup> = lights.iter()
+            .filter(|tl| !added_groups.iter().any(|g| g.traffic_lights.contains(&tl.id)))
+            .map(|tl| TrafficGroup { traffic_lights: vec![tl.id], direction: tl.direction, traffic_type: tl.traffic_type, timings: tl.timings })
+            .collect();
+        groups.extend(added_groups.into_iter());
 
-            // Add the group as a Control
-            tl_controls.push(Control::Group(group));
-        }
+        let controls = groups.into_iter().enumerate().map(|(index, group)| Control {
+            id: ControlId(index),
+            lights: group.traffic_lights,
+            direction: group.direction,
+            traffic_type: group.traffic_type,
+            timings: group.timings,
+        }).collect();
 
-        tl_controls
+        Topology::new(lights, controls)
     }
 }
 
-pub fn index_controls<'a, 'b>(input: &'b Vec<Control<'a>>) -> Vec<&'b Control<'a>> {
-    let length = input.iter().flat_map(|c| c.get_ids()).max().unwrap();
-    let mut vec = Vec::with_capacity(length);
-
-    for i in 0..length+1 {
-        vec.push(input.iter().find(|c| c.contains(i)).unwrap());
-        println!("      index {:?} = {:?}", i ,vec[i] );
-    }
-
-    vec
-}
-
 // -------------------------------------------------------------------------------
 // TrafficGroup
 // -------------------------------------------------------------------------------
 
-#[derive(PartialEq, Eq, Hash)]
-pub struct TrafficGroup<'a> {
-    pub traffic_lights: Vec<&'a TrafficLight>,
+/// Lights that are controlled together, becomes a `Control` in `ControlsBuilder::create_controls`.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct TrafficGroup {
+    pub traffic_lights: Vec<LightId>,
     pub direction: Direction,
     pub traffic_type: Type,
+    pub timings: Timings,
 }
 
-impl <'a>TrafficGroup<'a> {
-    pub fn with(traffic_lights: Vec<&'a TrafficLight>, traffic_type: Type) -> TrafficGroup {
-        TrafficGroup { traffic_lights:traffic_lights, direction:Direction::North, traffic_type:traffic_type }
+impl TrafficGroup {
+    pub fn with(traffic_lights: Vec<LightId>, traffic_type: Type) -> TrafficGroup {
+        TrafficGroup { traffic_lights:traffic_lights, direction:Direction::North, traffic_type:traffic_type, timings: Timings::default() }
     }
-    pub fn from(traffic_lights: Vec<&'a TrafficLight>, direction: Direction, traffic_type: Type) -> TrafficGroup<'a> {
-        TrafficGroup { traffic_lights:traffic_lights, direction:direction, traffic_type:traffic_type }
+    pub fn from(traffic_lights: Vec<LightId>, direction: Direction, traffic_type: Type) -> TrafficGroup {
+        TrafficGroup { traffic_lights:traffic_lights, direction:direction, traffic_type:traffic_type, timings: Timings::default() }
     }
     pub fn get_ids(&self) -> Vec<usize> {
-        self.traffic_lights.iter().map(|tl| tl.id).collect()
-    }
-    pub fn contains_id(&self, id: usize) -> bool {
-        self.traffic_lights.iter().any(|tl| tl.id == id)
-    }
-    pub fn contains_ids(&self, ids: &Vec<usize>) -> bool {
-        self.traffic_lights.iter().any(|tl| ids.iter().any(|&id| id == tl.id))
-    }
-    pub fn json_objs(&self, state: &JsonState) -> Vec<StoplichtJson> {
-        self.traffic_lights.iter().map(|tl| tl.json_obj(state)).collect()
+        self.traffic_lights.iter().map(|tl| tl.0).collect()
     }
 }
 
-impl <'a>fmt::Debug for TrafficGroup<'a> {
+impl fmt::Debug for TrafficGroup {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "TrafficGroup({:?},{:?})", self.get_ids(), self.direction)
     }
@@ -310,28 +459,29 @@ impl <'a>fmt::Debug for TrafficGroup<'a> {
 // TrafficLight
 // -------------------------------------------------------------------------------
 
-#[derive(PartialEq, Eq, Hash)]
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct TrafficLight {
-    pub id: usize,
+    pub id: LightId,
     pub direction: Direction,
     pub traffic_type: Type,
+    pub timings: Timings,
 }
 
 impl TrafficLight {
     pub fn new(id: usize, direction: Direction, traffic_type: Type) -> TrafficLight {
-        TrafficLight { id:id, direction:direction, traffic_type:traffic_type}
+        TrafficLight { id:LightId(id), direction:direction, traffic_type:traffic_type, timings: Timings::default() }
     }
     pub fn contains_ids(&self, ids: &Vec<usize>) -> bool {
-        ids.iter().any(|&id| id == self.id)
+        ids.iter().any(|&id| id == self.id.0)
     }
     pub fn json_obj(&self, state: &JsonState) -> StoplichtJson {
-        StoplichtJson { id: self.id, status: state.id() }
+        StoplichtJson { id: self.id.0, status: state.id() }
     }
 }
 
 impl fmt::Debug for TrafficLight {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "TrafficLight({:?},{:?})", self.id, self.direction)
+        write!(f, "TrafficLight({:?},{:?})", self.id.0, self.direction)
     }
 }
 
@@ -344,7 +494,7 @@ pub struct TrafficLightsBuilder {
     pub traffic_lights: Vec<TrafficLight>,
 }
 
-impl <'a>TrafficLightsBuilder {
+impl TrafficLightsBuilder {
     pub fn new(count: usize) -> TrafficLightsBuilder {
         TrafficLightsBuilder { traffic_lights: (0..count+1).map(|i| TrafficLight::new(i, Direction::North, Type::Vehicle)).collect() }
     }
@@ -360,9 +510,9 @@ impl <'a>TrafficLightsBuilder {
         for id in from..to { self.traffic_lights[id].traffic_type = t }
         self
     }
-
-    pub fn as_controls(&'a self) -> Vec<Control<'a>> {
-        self.traffic_lights.iter().map(|tl| Control::Single(tl)).collect()
+    pub fn set_timings(mut self, timings: Timings, ids: Vec<usize>) -> TrafficLightsBuilder {
+        for id in ids { self.traffic_lights[id].timings = timings }
+        self
     }
 }
 
@@ -371,35 +521,17 @@ impl <'a>TrafficLightsBuilder {
 // Direction
 // -------------------------------------------------------------------------------
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Direction {
     North, East, South, West
 }
 
-impl Direction {
-    pub fn retain<'a, 'b, 'c>(&self, src: &Vec<&'c ControlSensor<'a, 'b>>) -> Vec<&'c ControlSensor<'a, 'b>> {
-        let mut vec = src.clone();
-        vec.retain(|c| c.inner.direction() == *self);
-        vec
-    }
-}
-
-pub fn directions() -> Vec<Direction> {
-    vec![Direction::North, Direction::East, Direction::South, Direction::West]
-}
-
-pub fn split_by_directions<'a, 'b, 'c>(controls: &Vec<&'c ControlSensor<'a, 'b>>) -> Vec<Vec<&'c ControlSensor<'a, 'b>>> {
-    directions().iter()
-                .map(|d| d.retain(controls))
-                .filter(|v| v.len() != 0).collect()
-}
-
 
 // -------------------------------------------------------------------------------
 // TrafficType
 // -------------------------------------------------------------------------------
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Type {
     Primary,
     Vehicle,
@@ -407,20 +539,67 @@ pub enum Type {
 }
 
 impl Type {
-    pub fn min_green(&self) -> i32 {
+    pub fn default_timings(&self) -> SignalTimings {
         match self {
-            &Type::Primary => 5,
-            &Type::Vehicle => 5,
-            &Type::Rest => 10,
+            &Type::Primary => SignalTimings { min_green: 5,  green_extra: 999, max_green: 15, yellow: 4, min_red: 0, max_waiting: 120 },
+            &Type::Vehicle => SignalTimings { min_green: 5,  green_extra: 3,   max_green: 15, yellow: 4, min_red: 0, max_waiting: 90 },
+            &Type::Rest    => SignalTimings { min_green: 10, green_extra: 10,  max_green: 15, yellow: 4, min_red: 0, max_waiting: 90 },
         }
     }
-    pub fn green_extra(&self) -> i32 {
-        match self {
-            &Type::Primary => 999,//std::i32::MAX,
-            &Type::Vehicle => 3,
-            &Type::Rest => 10,
+}
+
+
+// -------------------------------------------------------------------------------
+// Timings
+// -------------------------------------------------------------------------------
+
+/// Timing parameters in seconds for a light or group, anything left out comes from its `Type`.
+/// `green_extra` is the time green is extended after the last vehicle passed the sensor.
+/// `max_waiting` is how long traffic may wait for green, after that it is served by the next signal group.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Timings {
+    pub min_green: Option<i32>,
+    pub green_extra: Option<i32>,
+    pub max_green: Option<i32>,
+    pub yellow: Option<i32>,
+    pub min_red: Option<i32>,
+    pub max_waiting: Option<i32>,
+}
+
+impl Timings {
+    /// These timings, with every timing that is set in `overrides` replaced.
+    pub fn with(&self, overrides: &Timings) -> Timings {
+        Timings {
+            min_green:   overrides.min_green.or(self.min_green),
+            green_extra: overrides.green_extra.or(self.green_extra),
+            max_green:   overrides.max_green.or(self.max_green),
+            yellow:      overrides.yellow.or(self.yellow),
+            min_red:     overrides.min_red.or(self.min_red),
+            max_waiting: overrides.max_waiting.or(self.max_waiting),
         }
     }
+
+    pub fn resolve(&self, traffic_type: &Type) -> SignalTimings {
+        let defaults = traffic_type.default_timings();
+        SignalTimings {
+            min_green:   self.min_green.unwrap_or(defaults.min_green),
+            green_extra: self.green_extra.unwrap_or(defaults.green_extra),
+            max_green:   self.max_green.unwrap_or(defaults.max_green),
+            yellow:      self.yellow.unwrap_or(defaults.yellow),
+            min_red:     self.min_red.unwrap_or(defaults.min_red),
+            max_waiting: self.max_waiting.unwrap_or(defaults.max_waiting),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SignalTimings {
+    pub min_green: i32,
+    pub green_extra: i32,
+    pub max_green: i32,
+    pub yellow: i32,
+    pub min_red: i32,
+    pub max_waiting: i32,
 }
 
 
@@ -428,47 +607,40 @@ impl Type {
 // Conflicts
 // -------------------------------------------------------------------------------
 
-#[derive(Debug)]
-pub struct XorConflictsGroup<'a> {
+/// The conflict entries of the controls of one approach direction.
+#[derive(Debug, Clone)]
+pub struct XorConflictsGroup {
     pub name: String,
-    conflicts: Vec<ConflictEntry<'a>>,
+    conflicts: Vec<ConflictEntry>,
 }
 
-impl<'a> XorConflictsGroup<'a> {
-    pub fn new(name: String, conflicts: Vec<ConflictEntry<'a>>) -> XorConflictsGroup {
+impl XorConflictsGroup {
+    pub fn new(name: String, conflicts: Vec<ConflictEntry>) -> XorConflictsGroup {
         XorConflictsGroup { name: name, conflicts:conflicts }
     }
 
-    pub fn get_conflicts_for(&'a self, control: &Control<'a>) ->  Option<Vec<usize>> {
-        self.conflicts.iter()
-            .find(|conflict| conflict.is_for(control))
-            .map( |conflict| self.get_conflicting_ids(conflict))
-    }
-
-    fn get_conflicting_ids(&self, conflict: &ConflictEntry) -> Vec<usize> {
-        let mut top_node_ids = self.top_node_ids();
-        top_node_ids.extend(conflict.get_conflicting_ids());
-        top_node_ids
+    pub fn entry_for(&self, control: ControlId) -> Option<&ConflictEntry> {
+        self.conflicts.iter().find(|conflict| conflict.is_for(control))
     }
 
-    fn top_node_ids(&self) -> Vec<usize> {
-        self.conflicts.iter().flat_map(|conflict| conflict.control.get_ids()).collect()
+    pub fn entries(&self) -> &Vec<ConflictEntry> {
+        &self.conflicts
     }
 }
 
 
-#[derive(Debug)]
-pub struct ConflictEntry<'a> {
-    pub control: &'a Control<'a>,
-    pub conflicting_with: Vec<&'a Control<'a>>,
+#[derive(Debug, Clone)]
+pub struct ConflictEntry {
+    pub control: ControlId,
+    pub conflicting_with: Vec<ControlId>,
 }
 
-impl<'a> ConflictEntry<'a> {
-    pub fn is_for(&self, other_control: &'a Control<'a>) -> bool {
-        self.control.is(other_control)
+impl ConflictEntry {
+    pub fn is_for(&self, other_control: ControlId) -> bool {
+        self.control == other_control
     }
 
-    pub fn get_conflicting_ids(&self) -> Vec<usize> {
-        self.conflicting_with.iter().map(|x|*x).flat_map(Control::get_ids).collect()
+    pub fn get_conflicting_ids(&self, topology: &Topology) -> Vec<usize> {
+        self.conflicting_with.iter().flat_map(|&id| topology.control(id).get_ids()).collect()
     }
 }
@@ -7,6 +7,8 @@ use time;
 use std::collections::HashMap;
 use crossroad::*;
 use traffic_controls::*;
+use controller::*;
+use sensor_health::*;
 use std::sync::mpsc::{channel, Sender, Receiver};
 use serde_json::error::Error as SerdeError;
 
@@ -18,29 +20,54 @@ pub const BAAN_COUNT: usize = 35; //TODO: REMOVE
 
 #[derive(Debug, Copy, Clone)]
 pub struct Sensor {
-    pub id: usize,
+    pub id: SensorId,
     pub bezet: bool,
     pub last_update: time::Tm,
+    /// When the sensor became occupied, unlike `last_update` it doesn't move while the sensor stays occupied.
+    pub waiting_since: Option<time::Tm>,
 }
 
 impl Sensor {
-    fn new() -> Sensor { Sensor { id: 0, bezet: false, last_update: time::empty_tm() } }
+    fn new() -> Sensor { Sensor { id: SensorId(0), bezet: false, last_update: time::empty_tm(), waiting_since: None } }
 
-    pub fn update(&mut self, baan: &Baan) {
-        self.bezet = baan.bezet;
-        if baan.bezet { self.last_update = time::now(); }
+    pub fn update(&mut self, baan: &Baan, now: time::Tm) {
+        self.set_bezet(baan.bezet, now);
     }
 
-    pub fn update_bus(&mut self, baan: &BusBaan) {
-        self.bezet = baan.bezet;
-        if baan.bezet { self.last_update = time::now(); }
+    pub fn update_bus(&mut self, baan: &BusBaan, now: time::Tm) {
+        self.set_bezet(baan.bezet, now);
+    }
+
+    fn set_bezet(&mut self, bezet: bool, now: time::Tm) {
+        match (self.bezet, bezet) {
+            (false, true) => self.waiting_since = Some(now),
+            (_, false)    => self.waiting_since = None,
+            _             => (),
+        }
+        self.bezet = bezet;
+        if bezet { self.last_update = now; }
     }
 }
 
+/// An emergency vehicle asked for green since `since`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct EmergencyRequest {
+    pub target: EmergencyTarget,
+    pub since: time::Tm,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+pub enum EmergencyTarget {
+    Light(LightId),
+    Approach(Direction),
+}
+
 pub struct SensorStates {
     sensors: [Sensor; BAAN_COUNT],
     bus_sensors: [Sensor; BAAN_COUNT],
-    current_bus_id: i32,
+    bus_lines: [i32; BAAN_COUNT],
+    bus_delays: [i32; BAAN_COUNT],
+    emergency: Option<EmergencyRequest>,
 }
 
 impl SensorStates {
@@ -49,10 +76,12 @@ impl SensorStates {
         let mut inst = SensorStates {
             sensors: [Sensor::new(); BAAN_COUNT],
             bus_sensors: [Sensor::new(); BAAN_COUNT],
-            current_bus_id: 0,
+            bus_lines: [0; BAAN_COUNT],
+            bus_delays: [0; BAAN_COUNT],
+            emergency: None,
         };
-        for i in 0..BAAN_COUNT {  inst.sensors[i].id = i; }
-        for i in 0..BAAN_COUNT {  inst.bus_sensors[i].id = i; }
+        for i in 0..BAAN_COUNT {  inst.sensors[i].id = SensorId(i); }
+        for i in 0..BAAN_COUNT {  inst.bus_sensors[i].id = SensorId(i); }
         inst
     }
 
@@ -73,21 +102,118 @@ impl SensorStates {
     }
 
 
-    pub fn update(&mut self, banen: &Vec<Baan>) {
+    /// `now` comes from the controller's clock, so waiting times can be simulated.
+    pub fn update(&mut self, banen: &Vec<Baan>, now: time::Tm) {
         for baan in banen.iter() {
-            self.sensors[baan.id].update(baan);
+            self.sensors[baan.id].update(baan, now);
+        }
+    }
+
+    /// Takes over everything a client message says about the sensors.
+    pub fn receive(&mut self, message: &ProtocolJson, now: time::Tm) {
+        if let Some(ref banen) = message.banen {
+
+            if banen.len() > 0 {
+                self.update(banen, now);
+                //println!("Client->Server: received baan sensor update: {:?} new_state = {:?}", banen, self)
+            }
+        }
+
+        if let Some(ref hulpdiensten) = message.hulpdiensten {
+
+            if hulpdiensten.len() > 0 {
+                self.update_hulpdiensten(hulpdiensten, now);
+                println!("Client->Server: received HULPDIENST update: {:?}", hulpdiensten)
+            }
+        }
+
+        if let Some(ref busbanen) = message.busbanen {
+
+            if busbanen.len() > 0 {
+                self.update_bussen(busbanen, now);
+                println!("Client->Server: received BUSBAAN sensor update: {:?} new_state = {:?}", busbanen, self)
+            }
         }
     }
-    pub fn update_bussen(&mut self, busbanen: &Vec<BusBaan>) {
+
+    /// Replaces what a sensor reports, a forced occupied sensor has been waiting `since`.
+    pub fn force(&mut self, id: SensorId, bezet: bool, since: time::Tm) {
+        if let Some(sensor) = self.sensors.get_mut(id.0) {
+            sensor.bezet = bezet;
+            sensor.waiting_since = if bezet { Some(since) } else { None };
+            if bezet { sensor.last_update = since; }
+        }
+    }
+
+    pub fn update_bussen(&mut self, busbanen: &Vec<BusBaan>, now: time::Tm) {
         for baan in busbanen.iter() {
-            self.bus_sensors[baan.id].update_bus(baan);
+            self.bus_sensors[baan.id].update_bus(baan, now);
 
             if baan.bezet {
-                self.current_bus_id = baan.eerstvolgendelijn;
+                self.bus_lines[baan.id] = baan.eerstvolgendelijn;
+                self.bus_delays[baan.id] = baan.vertraging;
             }
         }
     }
 
+    /// One emergency request at a time, a new request replaces the previous one.
+    pub fn update_hulpdiensten(&mut self, hulpdiensten: &Vec<Hulpdienst>, now: time::Tm) {
+        for hulpdienst in hulpdiensten.iter() {
+            let target = match hulpdienst.target() {
+                Some(target) => target,
+                None => { println!("Client->Server: hulpdienst without id or richting {:?}", hulpdienst); continue }
+            };
+            let current = self.emergency.map(|request| request.target);
+
+            if hulpdienst.bezet && current != Some(target) {
+                self.emergency = Some(EmergencyRequest { target: target, since: now });
+            }
+            else if !hulpdienst.bezet && current == Some(target) {
+                self.emergency = None;
+            }
+        }
+    }
+
+    pub fn emergency_request(&self) -> Option<&EmergencyRequest> {
+        self.emergency.as_ref()
+    }
+
+    /// The target of the emergency request, unless it is older than `PREEMPTION_TIMEOUT`.
+    pub fn emergency(&self, now: time::Tm) -> Option<EmergencyTarget> {
+        self.emergency.and_then(|request| match now - request.since < time::Duration::seconds(PREEMPTION_TIMEOUT) {
+            true  => Some(request.target),
+            false => None,
+        })
+    }
+
+    pub fn snapshot(&self) -> SensorSnapshot {
+        self.clone()
+    }
+
+    pub fn sensor(&self, id: SensorId) -> Option<&Sensor> {
+        self.sensors.get(id.0)
+    }
+
+    pub fn bus_sensor(&self, id: SensorId) -> Option<&Sensor> {
+        self.bus_sensors.get(id.0)
+    }
+
+    /// The line of the first bus waiting at this sensor.
+    pub fn bus_line(&self, id: SensorId) -> Option<i32> {
+        match self.bus_sensor(id) {
+            Some(sensor) if sensor.bezet => Some(self.bus_lines[id.0]),
+            _ => None,
+        }
+    }
+
+    /// Seconds the first bus waiting at this sensor is behind schedule.
+    pub fn bus_delay(&self, id: SensorId) -> Option<i32> {
+        match self.bus_sensor(id) {
+            Some(sensor) if sensor.bezet => Some(self.bus_delays[id.0]),
+            _ => None,
+        }
+    }
+
     pub fn active_sensors(&self) -> Vec<&Sensor> {
         self.sensors.iter().filter(|b| b.bezet).collect()
     }
@@ -105,10 +231,17 @@ impl SensorStates {
     }
 }
 
+// Arrays this long don't derive Clone, but they are Copy.
+impl Clone for SensorStates {
+    fn clone(&self) -> SensorStates {
+        SensorStates { sensors: self.sensors, bus_sensors: self.bus_sensors, bus_lines: self.bus_lines, bus_delays: self.bus_delays, emergency: self.emergency }
+    }
+}
+
 impl fmt::Debug for SensorStates {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         for sensor in self.sensors.iter().filter(|b| b.bezet) {
-            try!(write!(f, "[{} bezet] ", sensor.id))
+            try!(write!(f, "[{} bezet] ", sensor.id.0))
         }
         Ok(())
     }
@@ -140,6 +273,8 @@ impl JsonCompatLevel {
 pub struct ProtocolJson {
     pub banen: Option<Vec<Baan>>,
     pub busbanen: Option<Vec<BusBaan>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hulpdiensten: Option<Vec<Hulpdienst>>,
     pub stoplichten: Option<Vec<StoplichtJson>>,
 }
 
@@ -148,6 +283,7 @@ impl ProtocolJson {
         ProtocolJson {
             banen: None,
             busbanen: None,
+            hulpdiensten: None,
             stoplichten: Some(c.stoplichten),
         }
     }
@@ -155,11 +291,23 @@ impl ProtocolJson {
         ProtocolJson {
             banen: Some(vec![]),
             busbanen: Some(vec![]),
+            hulpdiensten: None,
             stoplichten: Some(c.stoplichten),
         }
     }
 }
 
+pub fn sensor_faults_json_str(faults: &Vec<SensorStatus>) -> String {
+    let json_obj = StoringenJson {
+        sensorstoringen: faults.iter().map(|s| SensorStoringJson { id: s.sensor.0, storing: s.fault, vervanging: s.fallback }).collect(),
+    };
+    serde_json::to_string(&json_obj).unwrap()
+}
+
+pub fn changes_json_str(changes: &Vec<SignalChange>) -> String {
+    out_compat_json_str(changes.iter().map(|c| c.json_obj()).collect())
+}
+
 pub fn out_compat_json_str(stoplichten: Vec<StoplichtJson>) -> String {
     let json_obj = ClientJson::new(stoplichten);
 
@@ -192,6 +340,29 @@ pub struct BusBaan {
     pub id: usize,
     pub eerstvolgendelijn: i32,
     pub bezet: bool,
+    /// Seconds the first bus is behind schedule, negative when it is early.
+    #[serde(default)]
+    pub vertraging: i32,
+}
+
+/// An emergency vehicle that wants green for one light (`id`) or for a whole approach (`richting`).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Hulpdienst {
+    #[serde(default)]
+    pub id: Option<usize>,
+    #[serde(default)]
+    pub richting: Option<Direction>,
+    pub bezet: bool,
+}
+
+impl Hulpdienst {
+    pub fn target(&self) -> Option<EmergencyTarget> {
+        match (self.id, self.richting) {
+            (Some(id), _)           => Some(EmergencyTarget::Light(LightId(id))),
+            (None, Some(direction)) => Some(EmergencyTarget::Approach(direction)),
+            (None, None)            => None,
+        }
+    }
 }
 
 
@@ -199,14 +370,15 @@ pub struct BusBaan {
 // Protocol: Server -> Client
 // -------------------------------------------------------------------------------
 
-#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
 pub enum JsonState {
     Rood = 0,
     Geel = 1,
     Groen = 2,
     BusRechtdoorRechtsaf  = 3,
     BusRechtdoor = 4,
-    BusRechtsaf = 5
+    BusRechtsaf = 5,
+    GeelKnipperend = 6,
 }
 
 impl JsonState {
@@ -232,6 +404,19 @@ pub struct StoplichtJson {
     pub status: usize,
 }
 
+/// Sent whenever a sensor becomes faulty or works again, lists every sensor that is faulty now.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StoringenJson {
+    pub sensorstoringen: Vec<SensorStoringJson>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct SensorStoringJson {
+    pub id: usize,
+    pub storing: SensorFault,
+    pub vervanging: Fallback,
+}
+
 impl StoplichtJson {
     pub fn empty() -> StoplichtJson {
         StoplichtJson { id: 0, status: JsonState::Rood.id() }
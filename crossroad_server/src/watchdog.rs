@@ -0,0 +1,117 @@
+use time;
+
+
+// -------------------------------------------------------------------------------
+// WatchdogPolicy
+// -------------------------------------------------------------------------------
+
+/// What the lights show while the controller is stopped.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SafeState {
+    /// Green lights go through yellow to red, then every light stays red.
+    AllRed,
+    /// Green lights go through yellow to red, then every light flashes yellow.
+    Flashing,
+}
+
+/// Stops the controller when the client stays silent for `timeout` seconds or disconnects.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WatchdogPolicy {
+    pub timeout: i32,
+    pub safe_state: SafeState,
+}
+
+impl Default for WatchdogPolicy {
+    fn default() -> WatchdogPolicy {
+        WatchdogPolicy { timeout: 300, safe_state: SafeState::Flashing }
+    }
+}
+
+
+// -------------------------------------------------------------------------------
+// Watchdog
+// -------------------------------------------------------------------------------
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WatchdogEvent {
+    /// The controller has to stop, the lights go to the safe state.
+    Expired { reason: String },
+    /// Messages arrive again, the controller may continue.
+    Restored,
+}
+
+/// Watches the messages of one client session. The receiver of the session reports every message
+/// and the end of the connection, the main loop checks it once per tick.
+#[derive(Debug, Clone)]
+pub struct Watchdog {
+    policy: WatchdogPolicy,
+    last_message: time::Tm,
+    connected: bool,
+    expired: bool,
+}
+
+impl Watchdog {
+
+    /// A session that just connected at `now`.
+    pub fn new(policy: WatchdogPolicy, now: time::Tm) -> Watchdog {
+        Watchdog { policy: policy, last_message: now, connected: true, expired: false }
+    }
+
+    pub fn policy(&self) -> WatchdogPolicy {
+        self.policy
+    }
+
+    pub fn message(&mut self, now: time::Tm) {
+        self.last_message = now;
+    }
+
+    /// The connection is gone, it can't be restored.
+    pub fn disconnect(&mut self) {
+        self.connected = false;
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// Reports a change once, every call after that returns None until it changes again.
+    pub fn check(&mut self, now: time::Tm) -> Option<WatchdogEvent> {
+        let silent = (now - self.last_message).num_seconds();
+
+        match (self.expired, self.connected, silent > self.policy.timeout as i64) {
+            (false, false, _) => {
+                self.expired = true;
+                Some(WatchdogEvent::Expired { reason: "the client disconnected".to_string() })
+            },
+            (false, true, true) => {
+                self.expired = true;
+                Some(WatchdogEvent::Expired { reason: format!("no message from the client for {} seconds", silent) })
+            },
+            (true, true, false) => {
+                self.expired = false;
+                Some(WatchdogEvent::Restored)
+            },
+            _ => None,
+        }
+    }
+}
+
+
+#[test]
+fn watchdog_expires_on_silence_and_disconnect() {
+    let at = |seconds: i64| time::at_utc(time::Timespec::new(seconds, 0));
+    let mut watchdog = Watchdog::new(WatchdogPolicy { timeout: 10, safe_state: SafeState::AllRed }, at(0));
+
+    assert_eq!(watchdog.check(at(10)), None);
+    assert_eq!(watchdog.check(at(11)), Some(WatchdogEvent::Expired { reason: "no message from the client for 11 seconds".to_string() }));
+    assert_eq!(watchdog.check(at(12)), None);
+
+    watchdog.message(at(13));
+    assert_eq!(watchdog.check(at(13)), Some(WatchdogEvent::Restored));
+
+    // A closed connection stays expired, whatever arrived last.
+    watchdog.disconnect();
+    assert_eq!(watchdog.check(at(14)), Some(WatchdogEvent::Expired { reason: "the client disconnected".to_string() }));
+    watchdog.message(at(15));
+    assert_eq!(watchdog.check(at(15)), None);
+}
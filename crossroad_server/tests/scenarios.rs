@@ -0,0 +1,28 @@
+extern crate crossroad_server;
+
+use std::fs;
+use std::path::Path;
+
+use crossroad_server::scenario::Scenario;
+
+/// Runs every `.scenario` file in tests/scenarios, see `Scenario` for the statements.
+#[test]
+fn scenarios() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("scenarios");
+    let mut paths: Vec<_> = fs::read_dir(&dir).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().map_or(false, |ext| ext == "scenario"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no scenarios in {:?}", dir);
+
+    let mut failures = vec![];
+    for path in &paths {
+        match Scenario::from_file(path).and_then(|scenario| scenario.verify()) {
+            Ok(failed) => failures.extend(failed),
+            Err(err) => failures.push(format!("{}", err)),
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}\n", failures.join("\n"));
+}